@@ -1,108 +1,1584 @@
 #![no_std]
 #![no_main]
 
-#[path = "../example_common.rs"]
-mod example_common;
-
+use core::cell::{Cell, RefCell};
+use core::fmt;
 use core::mem;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering};
+
+// Like example_common, minus panic-probe: this binary has its own panic
+// handler that blinks the LEDs.
+use defmt_rtt as _; // global logger
 
-use defmt::{info, warn, *};
+use defmt::{error, info, unwrap, warn, Display2Format, Format};
 use embassy_executor::Spawner;
 use embassy_nrf::{
     config,
-    gpio::{AnyPin, Level, Output, OutputDrive},
+    gpio::{Input, Pull},
     interrupt::Priority,
+    pac,
+    peripherals::PWM0,
+    pwm::SimplePwm,
 };
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use futures::future::{join, select, Either};
+use futures::pin_mut;
 use nrf_softdevice::ble::advertisement_builder::{
     Flag, LegacyAdvertisementBuilder, LegacyAdvertisementPayload, ServiceList, ServiceUuid16,
 };
-use nrf_softdevice::ble::{gatt_server, peripheral};
-use nrf_softdevice::{raw, Softdevice};
+use nrf_softdevice::ble::gatt_server::WriteOp;
+use nrf_softdevice::ble::peripheral::AdvertiseError;
+use nrf_softdevice::ble::{gatt_server, peripheral, Connection, SetConnParamsError};
+use nrf_softdevice::{raw, Flash, Softdevice};
+use static_cell::StaticCell;
+
+/// P0 pins of LED1..LED4 on the nRF52840-DK.
+const LED_PINS: [usize; 4] = [13, 14, 15, 16];
+const LED_COUNT: u8 = LED_PINS.len() as u8;
+/// LEDs actually fitted, bit n => LED n+1. Variant boards that leave pads
+/// unpopulated clear their bits; absent LEDs are never driven. The DK has
+/// all four.
+const PRESENT_LEDS: u8 = 0x0f;
+const _: () = core::assert!(PRESENT_LEDS >> LED_COUNT == 0);
+
+/// The DK wires its LEDs active-low; build with `leds-active-high` for boards
+/// that drive them the other way round.
+const LEDS_ACTIVE_LOW: bool = !cfg!(feature = "leds-active-high");
+
+/// PWM counter top: 1 MHz PWM clock (the default prescaler) / 1000 = 1 kHz,
+/// well above visible flicker.
+const LED_PWM_TOP: u16 = 1000;
+/// Default fade time for a mask change; 0 switches LEDs instantly.
+const LED_RAMP_MS: u16 = 200;
+/// Fade resolution.
+const LED_RAMP_TICK_MS: u16 = 10;
+/// Default breathing period (see `led_breathe_task`): one full dark-bright-
+/// dark cycle.
+const BREATHE_PERIOD_MS: u16 = 4000;
+/// Shorter periods are raised to this; faster "breathing" is just flicker.
+const BREATHE_MIN_PERIOD_MS: u16 = 500;
+/// Breathing brightness update interval, 50 Hz.
+const BREATHE_TICK: Duration = Duration::from_millis(20);
+/// LED (1-based) that blinks as an "alive" indicator from boot, or 0 for
+/// none. On by default with the `alive-led` feature; changeable at runtime
+/// via `alive_led` until the next reset.
+const ALIVE_LED: u8 = if cfg!(feature = "alive-led") { 4 } else { 0 };
+/// Time the alive LED spends lit, then dark.
+const ALIVE_HALF_PERIOD: Duration = Duration::from_secs(1);
+
+/// Autoplay (see `playlist_task`): up to this many steps, each a mask and
+/// how long it shows (`[mask u16 LE, ms u16 LE]` in `playlist`).
+const PLAYLIST_MAX_STEPS: usize = 16;
+const PLAYLIST_STEP_LEN: usize = 4;
+const PLAYLIST_LEN: usize = PLAYLIST_MAX_STEPS * PLAYLIST_STEP_LEN;
+/// Shorter steps are raised to this.
+const PLAYLIST_MIN_STEP_MS: u16 = 50;
+/// Flash page keeping the playlist and the autoplay flag across resets: the
+/// last 4 KiB of the nRF52840's 1 MiB, far above this image.
+const PLAYLIST_FLASH_ADDR: u32 = 0xff000;
+const PLAYLIST_FLASH_PAGE: u32 = 4096;
+/// Start of a valid record; erased flash reads as 0xff.
+const PLAYLIST_MAGIC: [u8; 4] = *b"PLST";
+/// Magic, step count, autoplay flag, two bytes padding, then the steps as
+/// in `playlist`. A multiple of four, as flash writes must be.
+const PLAYLIST_RECORD_LEN: usize = 8 + PLAYLIST_LEN;
+
+/// Base GAP device name. Each board appends `-XXXX` from its FICR DEVICEID
+/// (see `unique_device_name`) so boards flashed with the same image can be
+/// told apart.
+const DEVICE_NAME: &str = "HelloRust";
+/// "-" plus four hex digits.
+const DEVICE_NAME_SUFFIX_LEN: usize = 5;
+/// Room reserved in the GAP config so the name can change at runtime.
+const DEVICE_NAME_MAX_LEN: u16 = 20;
+/// Device Information Service strings, fixed at build time.
+const DIS_MANUFACTURER: &str = "Nordic Semiconductor";
+const DIS_MODEL: &str = "nRF52840-DK";
+const DIS_FIRMWARE_REV: &str = env!("CARGO_PKG_VERSION");
+/// Longest DIS string.
+const DIS_STR_LEN: usize = 32;
+/// Whether centrals may write the Device Name characteristic (open link, no
+/// pairing required). Off by default: the name is read-only.
+const DEVICE_NAME_WRITABLE: bool = false;
+
+const _: () = core::assert!(DEVICE_NAME.len() + DEVICE_NAME_SUFFIX_LEN <= DEVICE_NAME_MAX_LEN as usize);
+
+/// Peripheral links the SoftDevice is configured for.
+const PERIPH_ROLE_COUNT: u8 = 3;
+/// Links this firmware serves at once: it stops advertising while a central
+/// is connected, so a second one can't connect until the first leaves.
+const MAX_PERIPH_LINKS: u8 = 1;
+
+/// LED bitmask, bit n => LED n+1. Sent as two bytes (LE) so up to 16 LEDs
+/// can be addressed; a one-byte write from older hosts is the low byte.
+type LedMask = u16;
+
+/// Logical channels, switched as units through `channel_mask`: channel n
+/// (bit n) drives the LEDs in `LED_CHANNELS[n]`. Edit to match the wiring;
+/// `led_mask` still addresses single LEDs.
+const LED_CHANNELS: [LedMask; 2] = [0x03, 0x0c];
+const CHANNEL_COUNT: usize = LED_CHANNELS.len();
+const _: () = core::assert!(CHANNEL_COUNT <= 8);
+
+/// LED mask bits toggled by BUTTON1..BUTTON4 (DK: P0.11, P0.12, P0.24, P0.25).
+const BUTTON_TOGGLES: [LedMask; 4] = [0x01, 0x02, 0x04, 0x08];
+/// Holding BUTTON4 this long advertises `IDENTIFY_NAME` for `IDENTIFY_TIME`
+/// instead of toggling LED4, to pick this board out in a scanner.
+const IDENTIFY_BUTTON: usize = 3;
+const IDENTIFY_HOLD: Duration = Duration::from_millis(1500);
+const IDENTIFY_TIME: Duration = Duration::from_secs(10);
+const IDENTIFY_NAME: &str = "IDENTIFY";
+/// Crude debounce: ignore bounces right after a press.
+const BUTTON_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// Advertise non-connectably from boot, so the board shows up in scans but
+/// refuses connections. Also switchable at runtime via `advertise_only`.
+const ADVERTISE_ONLY: bool = cfg!(feature = "advertise-only");
+
+/// Stop advertising after this many seconds without a connection (after
+/// boot or a disconnect) and go to System OFF until BUTTON1 (P0.11) is
+/// pressed; 0 advertises forever. The nRF52840 draws well under 1 uA in
+/// System OFF against roughly 1 mA averaged while advertising with the LEDs
+/// dark (DK current measurements also include the on-board debugger).
+/// Waking is a reset, so a value written to `adv_timeout` lasts until then.
+const ADV_TIMEOUT_SECS: u16 = 0;
+/// `peripheral::Config::timeout` counts 10 ms units in a u16.
+const ADV_TIMEOUT_MAX_SECS: u16 = 655;
+/// BUTTON1, which wakes the board from System OFF.
+const WAKE_PIN: usize = 11;
+
+/// LEDs always go dark on disconnect. With `restore-mask-on-connect` the
+/// mask they showed comes back on the next connection (unless a button lit
+/// something in between), so the hardware matches what a reconnecting
+/// host last set and reads back.
+const RESTORE_MASK_ON_CONNECT: bool = cfg!(feature = "restore-mask-on-connect");
+
+/// `led_mask` writes without response are the streaming path: they skip the
+/// host log, and with `quiet-fast-writes` also the `led_mask` notification,
+/// which the writer doesn't need and other subscribers then only see on the
+/// next acknowledged write or button press.
+const NOTIFY_FAST_WRITES: bool = !cfg!(feature = "quiet-fast-writes");
+
+/// Link guard defaults: disconnect once the connection RSSI has stayed below
+/// `RSSI_GUARD_DBM` for `RSSI_GUARD_SECS` seconds (0 disables), so a board on
+/// battery re-advertises instead of holding on to a marginal link.
+const RSSI_GUARD_DBM: i8 = -90;
+const RSSI_GUARD_SECS: u8 = 30;
+
+/// Version of the `control` framing: `[version, opcode, payload...]`.
+/// Bump when an existing opcode changes meaning; new opcodes don't need it.
+///
+/// v2: `OP_SET_MASK` carries a 16-bit mask (LE) instead of one byte.
+const PROTOCOL_VERSION: u8 = 2;
+/// Oldest frame version still accepted.
+const PROTOCOL_MIN_VERSION: u8 = 1;
+
+const OP_SET_MASK: u8 = 0x01;
+const OP_SET_TEST_MODE: u8 = 0x02;
+const OP_SET_LINK_GUARD: u8 = 0x03;
+/// Payload must be `REBOOT_MAGIC`, so a stray write can't reset the board.
+const OP_REBOOT: u8 = 0x04;
+const REBOOT_MAGIC: [u8; 4] = *b"BOOT";
+/// Run `check_outputs`; the result lands in the `self_test` characteristic.
+const OP_SELF_TEST: u8 = 0x05;
+/// Wait after each duty change before reading a pin back: changes take
+/// effect at the end of the current (1 ms) PWM period.
+const SELF_TEST_SETTLE: Duration = Duration::from_millis(5);
+/// Set on the opcode of a reply: `[PROTOCOL_VERSION, opcode | OP_REPLY, status]`.
+const OP_REPLY: u8 = 0x80;
+
+/// Bits of the `features` characteristic. The GUI mirrors these in
+/// `features.rs`; existing bits never change meaning.
+const FEATURE_PWM_RAMP: u32 = 1 << 0;
+const FEATURE_BLINK: u32 = 1 << 1;
+const FEATURE_HEARTBEAT: u32 = 1 << 2;
+const FEATURE_NUS_LOG: u32 = 1 << 3;
+const FEATURE_CONTROL: u32 = 1 << 4;
+const FEATURE_TEST_MODE: u32 = 1 << 5;
+const FEATURE_LINK_GUARD: u32 = 1 << 6;
+const FEATURE_BUTTONS: u32 = 1 << 7;
+const FEATURE_SELF_TEST: u32 = 1 << 8;
+const FEATURE_IDENTIFY: u32 = 1 << 9;
+const FEATURE_ADV_TIMEOUT: u32 = 1 << 10;
+const FEATURE_BREATHE: u32 = 1 << 11;
+const FEATURE_OUTPUT_TEST: u32 = 1 << 12;
+const FEATURE_CONN_LIMITS: u32 = 1 << 13;
+const FEATURE_LOCK: u32 = 1 << 14;
+const FEATURE_LAST_ERROR: u32 = 1 << 15;
+const FEATURE_ALIVE_LED: u32 = 1 << 16;
+const FEATURE_CHANNELS: u32 = 1 << 17;
+const FEATURE_MEMORY: u32 = 1 << 18;
+const FEATURE_STAGING: u32 = 1 << 19;
+const FEATURE_PRESENT_LEDS: u32 = 1 << 20;
+const FEATURE_PLAYLIST: u32 = 1 << 21;
+const FEATURE_CONN_PARAMS: u32 = 1 << 22;
+/// What this build supports.
+const FEATURES: u32 = FEATURE_PWM_RAMP
+    | FEATURE_BLINK
+    | FEATURE_HEARTBEAT
+    | FEATURE_NUS_LOG
+    | FEATURE_CONTROL
+    | FEATURE_TEST_MODE
+    | FEATURE_LINK_GUARD
+    | FEATURE_BUTTONS
+    | FEATURE_IDENTIFY
+    | FEATURE_ADV_TIMEOUT
+    | FEATURE_BREATHE
+    | FEATURE_OUTPUT_TEST
+    | FEATURE_CONN_LIMITS
+    | FEATURE_LOCK
+    | FEATURE_LAST_ERROR
+    | FEATURE_ALIVE_LED
+    | FEATURE_CHANNELS
+    | FEATURE_MEMORY
+    | FEATURE_STAGING
+    | FEATURE_PRESENT_LEDS
+    | FEATURE_PLAYLIST
+    | FEATURE_CONN_PARAMS
+    | if cfg!(feature = "led-self-test") { FEATURE_SELF_TEST } else { 0 };
+
+/// Button presses (index into `BUTTON_TOGGLES`) from `button_task` to `main`.
+static BUTTON_EVENTS: Channel<ThreadModeRawMutex, usize, 4> = Channel::new();
+/// Long press of `IDENTIFY_BUTTON`; picked up by the advertising loop.
+static IDENTIFY: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Longest line `fw_log` keeps; the rest is cut off.
+const FW_LOG_LINE_LEN: usize = 48;
+/// NUS TX notification size; fits the default ATT MTU, so no MTU exchange
+/// is needed.
+const NUS_CHUNK: usize = 20;
+/// Minimum gap between two log lines on the air, so logging can't crowd out
+/// mask notifications.
+const FW_LOG_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Lines for the host's log, sent over NUS TX while someone is subscribed.
+/// When full, new lines are dropped.
+static FW_LOG: Channel<ThreadModeRawMutex, heapless::String<FW_LOG_LINE_LEN>, 8> = Channel::new();
+
+/// Current fade time (see `led_ramp`), read by `Leds::apply_mask` and the
+/// ramp task.
+static LED_RAMP_CURRENT_MS: AtomicU16 = AtomicU16::new(LED_RAMP_MS);
+/// Wakes `led_ramp_task` when a mask change needs fading.
+static LED_RAMP: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Blink period per LED in ms (see `led_blink`); 0 = solid.
+static BLINK_PERIODS: [AtomicU16; 4] = [const { AtomicU16::new(0) }; 4];
+/// Wakes `led_blink_task` when `BLINK_PERIODS` changes.
+static BLINK_CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// LEDs that breathe instead of lighting fully (see `led_breathe_task`).
+static BREATHE_MASK: AtomicU16 = AtomicU16::new(0);
+static BREATHE_PERIOD_CURRENT_MS: AtomicU16 = AtomicU16::new(BREATHE_PERIOD_MS);
+/// Wakes `led_breathe_task` when `BREATHE_MASK` goes from empty to not.
+static BREATHE_CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Current alive LED (see `led_alive_task`), 1-based; 0 = none.
+static ALIVE_LED_CURRENT: AtomicU8 = AtomicU8::new(ALIVE_LED);
+/// Wakes `led_alive_task` when `ALIVE_LED_CURRENT` changes.
+static ALIVE_CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Raised by `publish_mask` when the client wants a notification; drained by
+/// `notify_mask`, so a burst of changes collapses into one.
+static MASK_CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Raised by an accepted `OP_REBOOT`; the connection loop resets the board
+/// once the reply has gone out.
+static REBOOT: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Raised by `OP_SELF_TEST`; the connection loop runs `check_outputs`.
+static SELF_TEST: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Most recent `FwError` (see `report_error`); survives disconnects.
+static LAST_ERROR: AtomicU16 = AtomicU16::new(FwError::None as u16);
+/// Raised by `report_error`; the connection loop updates `last_error`.
+static LAST_ERROR_CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Steps through the playlist (see `playlist_task`); kept in flash.
+static AUTOPLAY: AtomicBool = AtomicBool::new(false);
+/// Wakes `playlist_task` when the playlist or `AUTOPLAY` changes.
+static PLAYLIST_CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+/// Mask of the step `playlist_task` just showed; the connection loop
+/// publishes it.
+static AUTOPLAY_MASK: Signal<ThreadModeRawMutex, LedMask> = Signal::new();
+/// Raised when the playlist or `AUTOPLAY` changes; `playlist_store_task`
+/// writes them to flash.
+static PLAYLIST_SAVE: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Unused stack is filled with this at boot; the deepest overwritten word
+/// marks the most stack ever used. There's no heap, only statics.
+const STACK_CANARY: u32 = 0xa5a5_a5a5;
+/// Room left below the live stack pointer while painting.
+const STACK_PAINT_MARGIN: usize = 256;
+/// How often `memory` is refreshed (and notified) while connected.
+const MEMORY_INTERVAL: Duration = Duration::from_secs(10);
+
+// From cortex-m-rt's linker script: the stack grows down from
+// `_stack_start` towards the end of the statics at `__sheap`.
+extern "C" {
+    static mut __sheap: u32;
+    static _stack_start: u32;
+}
+
+/// Paint the free stack with `STACK_CANARY`; first thing in `main`.
+fn paint_stack() {
+    let sp = cortex_m::register::msp::read() as usize;
+    let mut p = core::ptr::addr_of_mut!(__sheap);
+    while (p as usize) < sp - STACK_PAINT_MARGIN {
+        // SAFETY: `p` stays between the end of the statics and the margin
+        // below the live stack pointer, memory nothing else uses yet.
+        unsafe {
+            p.write_volatile(STACK_CANARY);
+            p = p.add(1);
+        }
+    }
+}
+
+/// Stack size and its high-water mark in bytes. Walks the untouched part
+/// from the bottom, so it gets cheaper as the stack grows.
+fn stack_usage() -> (u32, u32) {
+    let bottom = core::ptr::addr_of!(__sheap);
+    let top = core::ptr::addr_of!(_stack_start) as usize;
+    let mut p = bottom;
+    // SAFETY: `p` stays within the stack region, which is always mapped.
+    // Reading a word another task is writing only skews the estimate.
+    unsafe {
+        while (p as usize) < top && p.read_volatile() == STACK_CANARY {
+            p = p.add(1);
+        }
+    }
+    ((top - bottom as usize) as u32, (top - p as usize) as u32)
+}
+
+fn memory_value() -> [u8; 8] {
+    let (size, peak) = stack_usage();
+    let mut buf = [0u8; 8];
+    buf[..4].copy_from_slice(&size.to_le_bytes());
+    buf[4..].copy_from_slice(&peak.to_le_bytes());
+    buf
+}
+
+/// Blink LED1+LED4 / LED2+LED3 alternately, forever.
+///
+/// Tasks (and possibly the SoftDevice) are dead by now, so this drives the
+/// GPIO registers directly and busy-waits with interrupts disabled.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    error!("panic: {}", Display2Format(info));
+
+    // Hand the pins back to the GPIO peripheral.
+    pac::PWM0.enable().write(|w| w.set_enable(false));
+
+    let p0 = pac::P0;
+    for pin in LED_PINS {
+        p0.pin_cnf(pin).write(|w| {
+            w.set_dir(pac::gpio::vals::Dir::OUTPUT);
+            w.set_input(pac::gpio::vals::Input::DISCONNECT);
+        });
+    }
+
+    let mask = |pins: [usize; 2]| pins.iter().fold(0u32, |m, p| m | (1 << LED_PINS[*p]));
+    let (a, b) = (mask([0, 3]), mask([1, 2]));
+    let show = |lit: u32, dark: u32| {
+        let (low, high) = if LEDS_ACTIVE_LOW { (lit, dark) } else { (dark, lit) };
+        p0.outclr().write(|w| w.0 = low);
+        p0.outset().write(|w| w.0 = high);
+    };
+    loop {
+        show(a, b);
+        cortex_m::asm::delay(8_000_000);
+        show(b, a);
+        cortex_m::asm::delay(8_000_000);
+    }
+}
 
 #[embassy_executor::task]
 async fn softdevice_task(sd: &'static Softdevice) -> ! {
     sd.run().await
 }
 
+#[embassy_executor::task(pool_size = 4)]
+async fn button_task(idx: usize, mut button: Input<'static>) -> ! {
+    loop {
+        button.wait_for_falling_edge().await;
+        if idx != IDENTIFY_BUTTON {
+            BUTTON_EVENTS.send(idx).await;
+            Timer::after(BUTTON_DEBOUNCE).await;
+            continue;
+        }
+
+        // Short or long press is only known on release (or at the hold
+        // time), so this button toggles its LED on release.
+        Timer::after(BUTTON_DEBOUNCE).await;
+        let held_since = Instant::now();
+        while button.is_low() && held_since.elapsed() < IDENTIFY_HOLD {
+            Timer::after(Duration::from_millis(20)).await;
+        }
+        if button.is_high() {
+            BUTTON_EVENTS.send(idx).await;
+            continue;
+        }
+        info!("button {}: long press, identify", idx + 1);
+        IDENTIFY.signal(());
+        while button.is_low() {
+            Timer::after(Duration::from_millis(20)).await;
+        }
+        Timer::after(BUTTON_DEBOUNCE).await;
+    }
+}
+
+/// Step LED brightness toward the current mask whenever a fade is pending.
+/// Runs separately so a fade never holds up the GATT event loop.
+#[embassy_executor::task]
+async fn led_ramp_task(leds: &'static RefCell<Leds>) -> ! {
+    loop {
+        LED_RAMP.wait().await;
+        while !leds.borrow_mut().step_ramp() {
+            Timer::after(Duration::from_millis(LED_RAMP_TICK_MS as u64)).await;
+        }
+    }
+}
+
+/// Blink LEDs that have a period set. Every phase is counted from the same
+/// start instant, so LEDs with related periods stay in step instead of
+/// drifting apart.
+#[embassy_executor::task]
+async fn led_blink_task(leds: &'static RefCell<Leds>) -> ! {
+    let start = Instant::now();
+    loop {
+        let elapsed = start.elapsed().as_millis();
+        let mut off: LedMask = 0;
+        let mut next_edge: Option<u64> = None;
+        for (idx, period) in BLINK_PERIODS.iter().enumerate() {
+            let half = (period.load(Ordering::Relaxed) / 2) as u64;
+            if half == 0 {
+                continue;
+            }
+            if (elapsed / half) % 2 == 1 {
+                off |= 1 << idx;
+            }
+            let edge = (elapsed / half + 1) * half;
+            next_edge = Some(next_edge.map_or(edge, |e| e.min(edge)));
+        }
+        leds.borrow_mut().set_blink_off(off);
+
+        let Some(edge) = next_edge else {
+            BLINK_CHANGED.wait().await;
+            continue;
+        };
+        let timer = Timer::at(start + Duration::from_millis(edge));
+        let changed = BLINK_CHANGED.wait();
+        pin_mut!(changed);
+        select(timer, changed).await;
+    }
+}
+
+/// Drive the breathing LEDs' brightness along a smooth curve. Sleeps on
+/// `BREATHE_CHANGED` while nothing breathes; otherwise wakes every
+/// `BREATHE_TICK`, so the executor (and the SoftDevice, which preempts it
+/// anyway) always gets its time.
+#[embassy_executor::task]
+async fn led_breathe_task(leds: &'static RefCell<Leds>) -> ! {
+    let start = Instant::now();
+    loop {
+        let mask = BREATHE_MASK.load(Ordering::Relaxed);
+        if mask == 0 {
+            leds.borrow_mut().set_breathe(0, 0);
+            BREATHE_CHANGED.wait().await;
+            continue;
+        }
+        let period = BREATHE_PERIOD_CURRENT_MS.load(Ordering::Relaxed).max(BREATHE_MIN_PERIOD_MS) as u64;
+        let phase = (start.elapsed().as_millis() % period * 1000 / period) as u32;
+        leds.borrow_mut().set_breathe(mask, breathe_level(phase));
+        Timer::after(BREATHE_TICK).await;
+    }
+}
+
+/// Blink the alive LED, if one is set, whether or not a host is connected.
+/// That LED leaves mask control (and blink, breathing, fades) while it is
+/// the indicator and goes back to showing its mask bit once it isn't.
+#[embassy_executor::task]
+async fn led_alive_task(leds: &'static RefCell<Leds>) -> ! {
+    let mut lit = false;
+    loop {
+        let led = ALIVE_LED_CURRENT.load(Ordering::Relaxed);
+        if led == 0 {
+            leds.borrow_mut().set_alive(None, false);
+            ALIVE_CHANGED.wait().await;
+            continue;
+        }
+        lit = !lit;
+        leds.borrow_mut().set_alive(Some(led as usize - 1), lit);
+        let timer = Timer::after(ALIVE_HALF_PERIOD);
+        let changed = ALIVE_CHANGED.wait();
+        pin_mut!(changed);
+        select(timer, changed).await;
+    }
+}
+
+/// Show each playlist step for its time, round and round, while `AUTOPLAY`
+/// is set, whether or not a host is connected. Any change to the playlist or
+/// the flag starts again from the first step.
+#[embassy_executor::task]
+async fn playlist_task(leds: &'static RefCell<Leds>, playlist: &'static RefCell<Playlist>) -> ! {
+    loop {
+        let steps = playlist.borrow().clone();
+        if !AUTOPLAY.load(Ordering::Relaxed) || steps.is_empty() {
+            PLAYLIST_CHANGED.wait().await;
+            continue;
+        }
+        for (mask, ms) in steps {
+            {
+                let mut leds = leds.borrow_mut();
+                leds.apply_mask(mask);
+                AUTOPLAY_MASK.signal(leds.current_mask());
+            }
+
+            let timer = Timer::after(Duration::from_millis(ms.max(PLAYLIST_MIN_STEP_MS) as u64));
+            let changed = PLAYLIST_CHANGED.wait();
+            pin_mut!(changed);
+            if let Either::Right(_) = select(timer, changed).await {
+                break;
+            }
+        }
+    }
+}
+
+/// Write the playlist and `AUTOPLAY` to flash whenever they change. Erasing
+/// a page takes tens of milliseconds, so it happens here rather than in
+/// the GATT handler.
+#[embassy_executor::task]
+async fn playlist_store_task(mut flash: Flash, playlist: &'static RefCell<Playlist>) -> ! {
+    loop {
+        PLAYLIST_SAVE.wait().await;
+        let record = playlist_record(&playlist.borrow(), AUTOPLAY.load(Ordering::Relaxed));
+        let end = PLAYLIST_FLASH_ADDR + PLAYLIST_FLASH_PAGE;
+        let result = match flash.erase(PLAYLIST_FLASH_ADDR, end).await {
+            Ok(()) => flash.write(PLAYLIST_FLASH_ADDR, &record).await,
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(()) => info!("playlist saved"),
+            Err(err) => {
+                warn!("playlist save failed: {:?}", err);
+                report_error(FwError::FlashWriteFailed);
+            }
+        }
+    }
+}
+
+/// Brightness at `phase` (0..1000 through the period): a triangle eased with
+/// smoothstep, which is close enough to a raised sine without floating
+/// point. Dark at both ends, full brightness halfway.
+fn breathe_level(phase: u32) -> u16 {
+    let t = 1000 - phase.abs_diff(500) * 2;
+    let eased = t * t / 1000 * (3000 - 2 * t) / 1000;
+    (eased * LED_PWM_TOP as u32 / 1000) as u16
+}
+
+#[cfg(feature = "battery-service")]
 #[nrf_softdevice::gatt_service(uuid = "180f")]
 struct BatteryService {
     #[characteristic(uuid = "2a19", read, notify)]
     battery_level: u8,
 }
 
-/// Custom LED control service: write 1 byte bitmask.
+/// Custom LED control service: write a `LedMask` (one or two bytes).
 /// bit0..bit3 => LED1..LED4
 #[nrf_softdevice::gatt_service(uuid = "9e7312e0-2354-11eb-9f10-fbc30a62cf38")]
 struct LedService {
-    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a63cf38", read, write, notify)]
-    led_mask: u8,
+    /// Also writable without response, for hosts streaming rapid changes.
+    /// Those cost the board little (`apply_mask` sets a few PWM duty values
+    /// or wakes the ramp task), and notifications are coalesced to one per
+    /// connection interval (see `notify_mask`), so the link sets the pace:
+    /// a few write commands per connection event, i.e. some 100-300 updates
+    /// a second at a 7.5-15 ms interval.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a63cf38", read, write, write_without_response, notify)]
+    led_mask: heapless::Vec<u8, 2>,
+
+    /// Channel bits (see `LED_CHANNELS`). A write lights the LEDs of the set
+    /// channels and clears those of the others; LEDs outside every channel
+    /// keep their state. Reads show the channels whose LEDs are all lit.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a77cf38", read, write)]
+    channel_mask: u8,
+
+    /// The channel table: one `LedMask` (u16 LE) per channel, channel 0 first.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a78cf38", read, value = "channel_table()")]
+    channels: [u8; 2 * CHANNEL_COUNT],
+
+    /// Read-only diagnostics: uptime seconds (u32 LE) followed by the
+    /// RESETREAS value captured at boot (u32 LE).
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a64cf38", read)]
+    diagnostics: [u8; 8],
+
+    /// Stack size and the most of it used since boot (two u32 LE, bytes),
+    /// notified every `MEMORY_INTERVAL`.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a79cf38", read, notify)]
+    memory: [u8; 8],
+
+    /// Connection limits and use: `[PERIPH_ROLE_COUNT, MAX_PERIPH_LINKS,
+    /// active links]`, updated as centrals come and go.
+    #[characteristic(
+        uuid = "9e7312e0-2354-11eb-9f10-fbc30a73cf38",
+        read,
+        value = "[PERIPH_ROLE_COUNT, MAX_PERIPH_LINKS, 0]"
+    )]
+    connections: [u8; 3],
+
+    /// Number of LEDs behind `led_mask` (bit0..bit(n-1)), so hosts can size
+    /// their controls.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a65cf38", read, value = "[LED_COUNT]")]
+    led_count: u8,
+
+    /// Autoplay steps, `[mask u16 LE, ms u16 LE]` each, up to
+    /// `PLAYLIST_MAX_STEPS`. A write replaces the list and restarts it;
+    /// kept in flash.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a7dcf38", read, write)]
+    playlist: heapless::Vec<u8, PLAYLIST_LEN>,
+
+    /// Non-zero: the board steps through `playlist` by itself, connected or
+    /// not, and mask writes and button toggles are refused. 0 hands the LEDs
+    /// back as they are. Kept in flash.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a7ecf38", read, write)]
+    autoplay: u8,
+
+    /// `PRESENT_LEDS`: which of those are fitted. Mask bits for the others
+    /// are ignored.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a7ccf38", read, value = "[PRESENT_LEDS]")]
+    present_leds: u8,
+
+    /// RSSI link guard: threshold dBm (i8) followed by how many seconds the
+    /// link must stay below it before we disconnect (u8, 0 = off).
+    #[characteristic(
+        uuid = "9e7312e0-2354-11eb-9f10-fbc30a66cf38",
+        read,
+        write,
+        value = "[RSSI_GUARD_DBM as u8, RSSI_GUARD_SECS]"
+    )]
+    link_guard: [u8; 2],
+
+    /// Non-zero: mask writes are logged and echoed back (GATT value and
+    /// notification) but not applied to the pins. Cleared on every connection.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a67cf38", read, write)]
+    test_mode: u8,
+
+    /// Versioned commands (see `decode_control`); each one is answered with
+    /// a status notification.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a68cf38", write, notify)]
+    control: heapless::Vec<u8, 16>,
+
+    /// Non-zero: once the current connection ends, advertise non-connectably
+    /// until the next reset.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a6acf38", read, write)]
+    advertise_only: u8,
+
+    /// Non-zero freezes the LEDs (kiosk mode): mask writes are ignored and
+    /// the unchanged mask is notified back, and the LEDs stay lit across
+    /// disconnects. Buttons still work. Anyone connected can clear it; it
+    /// should require bonding once the firmware supports that. Lasts until
+    /// the next reset.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a74cf38", read, write)]
+    locked: u8,
+
+    /// Seconds (u16 LE) to advertise before System OFF; 0 = forever. See
+    /// `ADV_TIMEOUT_SECS`.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a6fcf38", read, write)]
+    adv_timeout: [u8; 2],
+
+    /// Counter (u32 LE) bumped and notified once a second while connected,
+    /// so hosts can tell a hung firmware from a quiet one.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a6bcf38", read, notify)]
+    heartbeat: [u8; 4],
+
+    /// Fade time in ms (u16 LE) for mask changes; 0 switches instantly.
+    #[characteristic(
+        uuid = "9e7312e0-2354-11eb-9f10-fbc30a6ccf38",
+        read,
+        write,
+        value = "LED_RAMP_MS.to_le_bytes()"
+    )]
+    led_ramp: [u8; 2],
+
+    /// LED (1-based) blinking once a second as an "alive" indicator instead
+    /// of following the mask; 0 = none. See `ALIVE_LED`.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a76cf38", read, write, value = "[ALIVE_LED]")]
+    alive_led: u8,
+
+    /// Blink period per LED in ms (four u16 LE, LED1 first); 0 = solid.
+    /// A blinking LED is only lit while its mask bit is set.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a6dcf38", read, write)]
+    led_blink: [u8; 8],
+
+    /// LEDs (`LedMask`, u16 LE) that fade in and out instead of lighting
+    /// steadily. Like blinking, a breathing LED is only lit while its mask
+    /// bit is set.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a70cf38", read, write)]
+    breathe_mask: [u8; 2],
+
+    /// Breathing period in ms (u16 LE); at least `BREATHE_MIN_PERIOD_MS`.
+    #[characteristic(
+        uuid = "9e7312e0-2354-11eb-9f10-fbc30a71cf38",
+        read,
+        write,
+        value = "BREATHE_PERIOD_MS.to_le_bytes()"
+    )]
+    breathe_period: [u8; 2],
+
+    /// Compiled-in capabilities (u32 LE, `FEATURE_*` bits), so hosts only
+    /// offer controls the firmware can honor.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a6ecf38", read, value = "FEATURES.to_le_bytes()")]
+    features: [u8; 4],
+
+    /// Non-zero: mask, blink and breathe writes (from any characteristic or
+    /// `control`) are held instead of shown, until `apply`. Writing 0 drops
+    /// what was held. Cleared on every connection and by `apply`.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a7acf38", read, write)]
+    staging: u8,
+
+    /// Any write shows everything held since `staging` was set, in one go,
+    /// and ends staging.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a7bcf38", write)]
+    apply: u8,
+
+    /// Outcome of the last `OP_SELF_TEST`: LEDs tested, then LEDs that
+    /// passed (two `LedMask`, u16 LE). All zero while a test runs and
+    /// before the first one.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a72cf38", read)]
+    self_test: [u8; 4],
+
+    /// Most recent firmware-side failure (`FwError`, u16 LE); 0 = none
+    /// since reset. Notified whenever a failure is reported.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a75cf38", read, notify)]
+    last_error: [u8; 2],
+
+    /// Active connection interval (1.25 ms units), peripheral latency
+    /// (events) and supervision timeout (10 ms units), u16 LE each; notified
+    /// when they change. A write asks the central for those values. It may
+    /// refuse or settle on others; the value shows what it picked.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a7fcf38", read, write, notify)]
+    conn_params: [u8; 6],
+
+    /// Highest `control` frame version this firmware understands.
+    #[characteristic(uuid = "9e7312e0-2354-11eb-9f10-fbc30a69cf38", read, value = "[PROTOCOL_VERSION]")]
+    protocol_version: u8,
+}
+
+/// Status byte of a `control` reply.
+#[derive(Clone, Copy, Format)]
+#[repr(u8)]
+enum ControlStatus {
+    Ok = 0,
+    UnsupportedVersion = 1,
+    UnknownOpcode = 2,
+    BadPayload = 3,
+    /// Refused because `locked` is set.
+    Locked = 4,
+    /// Refused because `autoplay` is running.
+    Autoplay = 5,
+}
+
+/// Codes of the `last_error` characteristic. The GUI mirrors these in
+/// `protocol.rs`; existing codes never change meaning.
+#[derive(Clone, Copy, Format)]
+#[repr(u16)]
+enum FwError {
+    None = 0,
+    /// A notification couldn't be queued (SoftDevice buffers full).
+    NotifyFailed = 1,
+    /// `check_outputs` found at least one LED output not following its pin.
+    SelfTestFailed = 2,
+    /// `sd_ble_gap_device_name_set` refused the identify or default name.
+    DeviceNameSet = 3,
+    /// A `control` frame was rejected (the reply carries the status).
+    ControlRejected = 4,
+    /// Saving the playlist to flash failed; it is kept until the next reset.
+    FlashWriteFailed = 5,
+    /// The SoftDevice refused to request the written `conn_params` (out of
+    /// range, or an update already in progress).
+    ConnParamsRejected = 6,
+}
+
+/// Remember `err` for the `last_error` characteristic and tell a connected
+/// host about it.
+fn report_error(err: FwError) {
+    LAST_ERROR.store(err as u16, Ordering::Relaxed);
+    LAST_ERROR_CHANGED.signal(());
+}
+
+enum ControlRequest {
+    SetMask(LedMask),
+    SetTestMode(bool),
+    SetLinkGuard([u8; 2]),
+    Reboot,
+    SelfTest,
+}
+
+/// Parse a `control` frame. Payload bytes beyond what an opcode needs are
+/// ignored, so newer hosts can append fields without breaking older boards.
+fn decode_control(frame: &[u8]) -> Result<ControlRequest, ControlStatus> {
+    let [version, opcode, payload @ ..] = frame else {
+        return Err(ControlStatus::BadPayload);
+    };
+    if !(PROTOCOL_MIN_VERSION..=PROTOCOL_VERSION).contains(version) {
+        return Err(ControlStatus::UnsupportedVersion);
+    }
+    match (*opcode, payload) {
+        (OP_SET_MASK, [lo, hi, ..]) if *version >= 2 => Ok(ControlRequest::SetMask(LedMask::from_le_bytes([*lo, *hi]))),
+        (OP_SET_MASK, [lo, ..]) if *version == 1 => Ok(ControlRequest::SetMask(*lo as LedMask)),
+        (OP_SET_TEST_MODE, [on, ..]) => Ok(ControlRequest::SetTestMode(*on != 0)),
+        (OP_SET_LINK_GUARD, [dbm, secs, ..]) => Ok(ControlRequest::SetLinkGuard([*dbm, *secs])),
+        (OP_REBOOT, [a, b, c, d, ..]) if [*a, *b, *c, *d] == REBOOT_MAGIC => Ok(ControlRequest::Reboot),
+        (OP_SELF_TEST, _) => Ok(ControlRequest::SelfTest),
+        (OP_SET_MASK | OP_SET_TEST_MODE | OP_SET_LINK_GUARD | OP_REBOOT, _) => Err(ControlStatus::BadPayload),
+        _ => Err(ControlStatus::UnknownOpcode),
+    }
+}
+
+/// Autoplay steps: mask and milliseconds shown.
+type Playlist = heapless::Vec<(LedMask, u16), PLAYLIST_MAX_STEPS>;
+
+/// Steps as written to `playlist`; a trailing partial step is dropped.
+fn playlist_from_bytes(bytes: &[u8]) -> Playlist {
+    bytes
+        .chunks_exact(PLAYLIST_STEP_LEN)
+        .take(PLAYLIST_MAX_STEPS)
+        .map(|s| (LedMask::from_le_bytes([s[0], s[1]]), u16::from_le_bytes([s[2], s[3]])))
+        .collect()
+}
+
+fn playlist_value(steps: &Playlist) -> heapless::Vec<u8, PLAYLIST_LEN> {
+    let mut value = heapless::Vec::new();
+    for (mask, ms) in steps {
+        // Always fits: PLAYLIST_LEN bytes for PLAYLIST_MAX_STEPS steps.
+        let _ = value.extend_from_slice(&mask.to_le_bytes());
+        let _ = value.extend_from_slice(&ms.to_le_bytes());
+    }
+    value
+}
+
+fn playlist_record(steps: &Playlist, autoplay: bool) -> [u8; PLAYLIST_RECORD_LEN] {
+    let mut record = [0u8; PLAYLIST_RECORD_LEN];
+    record[..4].copy_from_slice(&PLAYLIST_MAGIC);
+    record[4] = steps.len() as u8;
+    record[5] = autoplay as u8;
+    let value = playlist_value(steps);
+    record[8..8 + value.len()].copy_from_slice(&value);
+    record
+}
+
+/// `None` for erased flash or a record this build can't read.
+fn playlist_from_record(record: &[u8; PLAYLIST_RECORD_LEN]) -> Option<(Playlist, bool)> {
+    let len = record[4] as usize;
+    if record[..4] != PLAYLIST_MAGIC || len > PLAYLIST_MAX_STEPS {
+        return None;
+    }
+    let steps = playlist_from_bytes(&record[8..8 + len * PLAYLIST_STEP_LEN]);
+    Some((steps, record[5] != 0))
+}
+
+/// Writes held while `staging` is set; the latest of each kind wins.
+#[derive(Clone, Copy, Default)]
+struct Staged {
+    mask: Option<LedMask>,
+    blink: Option<[u8; 8]>,
+    breathe_mask: Option<[u8; 2]>,
+    breathe_period: Option<[u8; 2]>,
+}
+
+/// Settings for `guard_link`, as written to `link_guard`.
+#[derive(Clone, Copy)]
+struct LinkGuard {
+    threshold_dbm: i8,
+    /// 0 disables the guard.
+    secs: u8,
+}
+
+impl LinkGuard {
+    fn from_bytes(v: [u8; 2]) -> Self {
+        Self { threshold_dbm: v[0] as i8, secs: v[1] }
+    }
+}
+
+/// Poll the connection RSSI once a second and disconnect once it has been
+/// below the guard threshold for the configured time. Never returns; the
+/// disconnect ends the GATT server future instead.
+async fn guard_link(conn: &Connection, guard: &Cell<LinkGuard>) -> ! {
+    conn.start_rssi();
+    let mut weak_since: Option<Instant> = None;
+
+    loop {
+        Timer::after(Duration::from_secs(1)).await;
+
+        let g = guard.get();
+        let rssi = match conn.rssi() {
+            Some(rssi) if g.secs != 0 && rssi < g.threshold_dbm => rssi,
+            _ => {
+                weak_since = None;
+                continue;
+            }
+        };
+
+        let since = *weak_since.get_or_insert(Instant::now());
+        if since.elapsed() >= Duration::from_secs(g.secs as u64) {
+            warn!(
+                "RSSI {} dBm below {} dBm for {}s, disconnecting",
+                rssi, g.threshold_dbm, g.secs
+            );
+            fw_log(format_args!("weak link ({} dBm), disconnecting", rssi));
+            let _ = conn.disconnect();
+            weak_since = None;
+        }
+    }
+}
+
+/// Queue a short line for the host (see `NusService`). Use for events worth
+/// seeing without a debugger; everything still goes to defmt as well.
+fn fw_log(args: fmt::Arguments) {
+    let mut line = heapless::String::new();
+    // Overlong lines are truncated rather than dropped.
+    let _ = fmt::write(&mut line, args);
+    let _ = FW_LOG.try_send(line);
+}
+
+/// Drain `FW_LOG` to NUS TX while the host is subscribed; lines queue up
+/// (up to the channel size) until it is.
+async fn stream_fw_log(server: &Server, conn: &Connection, notify: &Cell<bool>) -> ! {
+    loop {
+        while !notify.get() {
+            Timer::after(FW_LOG_INTERVAL).await;
+        }
+        let line = FW_LOG.receive().await;
+        let mut bytes: heapless::Vec<u8, { FW_LOG_LINE_LEN + 1 }> = unwrap!(heapless::Vec::from_slice(line.as_bytes()));
+        let _ = bytes.push(b'\n');
+        for chunk in bytes.chunks(NUS_CHUNK) {
+            if let Err(err) = server.nus.tx_notify(conn, &unwrap!(heapless::Vec::from_slice(chunk))) {
+                warn!("notify nus failed: {:?}", err);
+                report_error(FwError::NotifyFailed);
+                break;
+            }
+        }
+        Timer::after(FW_LOG_INTERVAL).await;
+    }
+}
+
+/// Mask from a `led_mask` write: low byte first, high byte optional.
+fn mask_from_bytes(bytes: &[u8]) -> LedMask {
+    let lo = bytes.first().copied().unwrap_or(0);
+    let hi = bytes.get(1).copied().unwrap_or(0);
+    LedMask::from_le_bytes([lo, hi])
+}
+
+/// Replace the channel LEDs of `current` with those of the channels in
+/// `channels`.
+fn mask_from_channels(current: LedMask, channels: u8) -> LedMask {
+    let (mut all, mut on) = (0, 0);
+    for (n, leds) in LED_CHANNELS.iter().enumerate() {
+        all |= leds;
+        if channels & (1 << n) != 0 {
+            on |= leds;
+        }
+    }
+    (current & !all) | on
+}
+
+/// Channels whose LEDs are all lit in `mask`.
+fn channels_from_mask(mask: LedMask) -> u8 {
+    let mut channels = 0;
+    for (n, leds) in LED_CHANNELS.iter().enumerate() {
+        if mask & leds == *leds {
+            channels |= 1 << n;
+        }
+    }
+    channels
+}
+
+const fn channel_table() -> [u8; 2 * CHANNEL_COUNT] {
+    let mut table = [0; 2 * CHANNEL_COUNT];
+    let mut n = 0;
+    while n < CHANNEL_COUNT {
+        let [lo, hi] = LED_CHANNELS[n].to_le_bytes();
+        table[2 * n] = lo;
+        table[2 * n + 1] = hi;
+        n += 1;
+    }
+    table
 }
 
+fn mask_value(mask: LedMask) -> heapless::Vec<u8, 2> {
+    unwrap!(heapless::Vec::from_slice(&mask.to_le_bytes()))
+}
+
+fn diagnostics_value(uptime_secs: u32, reset_reason: u32) -> [u8; 8] {
+    let mut buf = [0u8; 8];
+    buf[..4].copy_from_slice(&uptime_secs.to_le_bytes());
+    buf[4..].copy_from_slice(&reset_reason.to_le_bytes());
+    buf
+}
+
+fn conn_params_value(params: &raw::ble_gap_conn_params_t) -> [u8; 6] {
+    let mut buf = [0u8; 6];
+    buf[..2].copy_from_slice(&params.max_conn_interval.to_le_bytes());
+    buf[2..4].copy_from_slice(&params.slave_latency.to_le_bytes());
+    buf[4..].copy_from_slice(&params.conn_sup_timeout.to_le_bytes());
+    buf
+}
+
+/// Ask the central for the interval, latency and timeout of a
+/// `conn_params` write. The SoftDevice checks them against the spec limits.
+fn request_conn_params(conn: &Connection, v: [u8; 6]) -> Result<(), SetConnParamsError> {
+    let [interval, latency, timeout] = [0, 2, 4].map(|i| u16::from_le_bytes([v[i], v[i + 1]]));
+    let mut params = conn.conn_params();
+    params.min_conn_interval = interval;
+    params.max_conn_interval = interval;
+    params.slave_latency = latency;
+    params.conn_sup_timeout = timeout;
+    conn.set_conn_params(params)
+}
+
+/// Blinks per RESETREAS cause for `boot-reason-blink`; the first listed bit
+/// that is set wins. A power-on reset (nothing latched) is one blink, any
+/// other cause `RESET_BLINKS_OTHER`.
+#[cfg(feature = "boot-reason-blink")]
+const RESET_BLINKS: [(u32, u8); 5] = [
+    // DOG: watchdog.
+    (1 << 1, 2),
+    // SREQ: soft reset, e.g. `OP_REBOOT`.
+    (1 << 2, 3),
+    // RESETPIN: the reset button.
+    (1 << 0, 4),
+    // LOCKUP: the CPU locked up.
+    (1 << 3, 5),
+    // OFF: woken from System OFF (see `ADV_TIMEOUT_SECS`).
+    (1 << 16, 6),
+];
+#[cfg(feature = "boot-reason-blink")]
+const RESET_BLINKS_OTHER: u8 = 7;
+/// On and off time of each blink.
+#[cfg(feature = "boot-reason-blink")]
+const RESET_BLINK_TIME: Duration = Duration::from_millis(200);
+
+/// Read RESETREAS and clear it, so the next boot reports only its own cause.
+/// Zero means power-on reset (no other flag latched).
+fn take_reset_reason() -> u32 {
+    let reason = pac::POWER.resetreas().read().0;
+    pac::POWER.resetreas().write(|w| w.0 = reason);
+    reason
+}
+
+/// Standard Device Information Service, so generic scanners and phone apps
+/// can tell what they're talking to.
+#[nrf_softdevice::gatt_service(uuid = "180a")]
+struct DeviceInformationService {
+    #[characteristic(uuid = "2a29", read, value = "DIS_MANUFACTURER.as_bytes()")]
+    manufacturer_name: heapless::String<DIS_STR_LEN>,
+
+    #[characteristic(uuid = "2a24", read, value = "DIS_MODEL.as_bytes()")]
+    model_number: heapless::String<DIS_STR_LEN>,
+
+    #[characteristic(uuid = "2a26", read, value = "DIS_FIRMWARE_REV.as_bytes()")]
+    firmware_revision: heapless::String<DIS_STR_LEN>,
+}
+
+/// Nordic UART Service, TX only: `fw_log` lines, '\n'-terminated and split
+/// into `NUS_CHUNK`-byte notifications. Writes to RX are not supported.
+#[nrf_softdevice::gatt_service(uuid = "6e400001-b5a3-f393-e0a9-e50e24dcca9e")]
+struct NusService {
+    #[characteristic(uuid = "6e400003-b5a3-f393-e0a9-e50e24dcca9e", notify)]
+    tx: heapless::Vec<u8, NUS_CHUNK>,
+}
+
+// The gatt_server macro doesn't carry `#[cfg]` through to its generated
+// code, so the Battery Service variant is a separate struct.
+#[cfg(feature = "battery-service")]
 #[nrf_softdevice::gatt_server]
 struct Server {
     bas: BatteryService,
+    dis: DeviceInformationService,
+    led: LedService,
+    nus: NusService,
+}
+
+#[cfg(not(feature = "battery-service"))]
+#[nrf_softdevice::gatt_server]
+struct Server {
+    dis: DeviceInformationService,
     led: LedService,
+    nus: NusService,
 }
 
+/// `Server` as `gatt_server::run` drives it, noting whether each write came
+/// without response; the generated events don't say.
+struct WriteOpTap<'a> {
+    server: &'a Server,
+    fast: Cell<bool>,
+}
+
+impl gatt_server::Server for WriteOpTap<'_> {
+    type Event = ServerEvent;
+
+    fn on_write(&self, conn: &Connection, handle: u16, op: WriteOp, offset: usize, data: &[u8]) -> Option<ServerEvent> {
+        self.fast.set(op == WriteOp::Command);
+        gatt_server::Server::on_write(self.server, conn, handle, op, offset, data)
+    }
+}
+
+/// 16-bit services in the advertisement.
+#[cfg(feature = "battery-service")]
+const ADV_SERVICES_16: &[ServiceUuid16] = &[ServiceUuid16::BATTERY, ServiceUuid16::DEVICE_INFORMATION];
+#[cfg(not(feature = "battery-service"))]
+const ADV_SERVICES_16: &[ServiceUuid16] = &[ServiceUuid16::DEVICE_INFORMATION];
+/// The LED service, as the advertising data carries it (little-endian).
+const LED_SERVICE_UUID_LE: [u8; 16] = 0x9e7312e0_2354_11eb_9f10_fbc30a62cf38_u128.to_le_bytes();
+
+/// How the advertising data is split between the advertisement and the scan
+/// response (see `adv_payloads`). Either way the board advertises connectable
+/// and undirected (ADV_IND): legacy advertising has no connectable PDU
+/// without a scan response, so `Single` answers scan requests with an empty one.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum AdvLayout {
+    /// Name and 16-bit services in the advertisement, the 128-bit LED service
+    /// UUID in the scan response (it leaves no room for the name). Passive
+    /// scanners see the name but not that the board is an LED board; active
+    /// scanners (BlueZ by default) see both after the scan response.
+    NameFirst,
+    /// The LED service UUID and as much of the name as fits (8 bytes, marked
+    /// shortened) in the advertisement; the full name and 16-bit services in
+    /// the scan response. Passive scans and scans filtered on the LED service
+    /// find the board from the advertisement alone.
+    UuidFirst,
+    /// As `UuidFirst`, with an empty scan response: the shortest exchange on
+    /// air, but the full name and the 16-bit services (battery, device
+    /// information) only show up after connecting.
+    Single,
+}
+
+const ADV_LAYOUT: AdvLayout = if cfg!(feature = "adv-single-packet") {
+    AdvLayout::Single
+} else if cfg!(feature = "adv-uuid-first") {
+    AdvLayout::UuidFirst
+} else {
+    AdvLayout::NameFirst
+};
+const _: () = core::assert!(
+    !(cfg!(feature = "adv-single-packet") && cfg!(feature = "adv-uuid-first")),
+    "pick one of adv-single-packet and adv-uuid-first"
+);
+
+/// PWM channels drive the LEDs so mask changes can fade (see
+/// `led_ramp_task`).
 struct Leds {
-    // embassy-nrf 0.3.1 Output is Output<'d> (no pin generic). Use AnyPin.
-    led1: Output<'static>,
-    led2: Output<'static>,
-    led3: Output<'static>,
-    led4: Output<'static>,
+    // Channel 0..3 => LED1..LED4 => mask bit0..bit3.
+    pwm: SimplePwm<'static, PWM0>,
+    /// true: LOW = ON (nRF52840-DK). false: HIGH = ON (some custom boards).
+    active_low: bool,
+    /// What the LEDs are showing or fading toward.
+    mask: LedMask,
+    /// Blinking LEDs currently in their dark half (see `led_blink_task`).
+    blink_off: LedMask,
+    /// Breathing LEDs and their current brightness (see `led_breathe_task`).
+    breathe: LedMask,
+    breathe_level: u16,
+    /// The alive indicator (see `led_alive_task`), by index, and whether
+    /// it is in its lit half.
+    alive: Option<usize>,
+    alive_lit: bool,
+    /// Brightness per LED, 0..=LED_PWM_TOP.
+    level: [u16; 4],
+    /// Output instead of `level` while `check_outputs` drives the pins.
+    forced: Option<[u16; 4]>,
 }
 
 impl Leds {
-    fn new(p: embassy_nrf::Peripherals) -> Self {
-        // nRF52840-DK LEDs are P0.13..P0.16 and are active-low.
-        // Start HIGH = off.
-        let led1 = Output::new(AnyPin::from(p.P0_13), Level::High, OutputDrive::Standard);
-        let led2 = Output::new(AnyPin::from(p.P0_14), Level::High, OutputDrive::Standard);
-        let led3 = Output::new(AnyPin::from(p.P0_15), Level::High, OutputDrive::Standard);
-        let led4 = Output::new(AnyPin::from(p.P0_16), Level::High, OutputDrive::Standard);
+    fn new(pwm: SimplePwm<'static, PWM0>, active_low: bool) -> Self {
+        let mut leds = Self {
+            pwm,
+            active_low,
+            mask: 0,
+            blink_off: 0,
+            breathe: 0,
+            breathe_level: 0,
+            alive: None,
+            alive_lit: false,
+            level: [0; 4],
+            forced: None,
+        };
+        leds.pwm.set_max_duty(LED_PWM_TOP);
+        leds.write_levels();
+        leds
+    }
+
+    /// The compare value is the LOW part of each period, so active-low LEDs
+    /// take the brightness as is and active-high ones its complement.
+    fn write_levels(&mut self) {
+        for (ch, level) in self.forced.unwrap_or(self.level).into_iter().enumerate() {
+            let duty = if self.active_low { level } else { LED_PWM_TOP - level };
+            self.pwm.set_duty(ch, duty);
+        }
+    }
+
+    fn target(&self, idx: usize) -> u16 {
+        if PRESENT_LEDS & (1 << idx) == 0 {
+            0
+        } else if self.alive == Some(idx) {
+            if self.alive_lit {
+                LED_PWM_TOP
+            } else {
+                0
+            }
+        } else if self.mask & !self.blink_off & (1 << idx) == 0 {
+            0
+        } else if self.breathe & (1 << idx) != 0 {
+            self.breathe_level
+        } else {
+            LED_PWM_TOP
+        }
+    }
+
+    /// Jump straight to the current mask, skipping any fade.
+    fn snap(&mut self) {
+        for idx in 0..self.level.len() {
+            self.level[idx] = self.target(idx);
+        }
+        self.write_levels();
+    }
+
+    /// Move one `LED_RAMP_TICK_MS` step toward the mask; true once there.
+    fn step_ramp(&mut self) -> bool {
+        let ramp_ms = LED_RAMP_CURRENT_MS.load(Ordering::Relaxed).max(LED_RAMP_TICK_MS);
+        let step = (LED_PWM_TOP as u32 * LED_RAMP_TICK_MS as u32 / ramp_ms as u32).max(1) as u16;
+        let mut done = true;
+        for idx in 0..self.level.len() {
+            let (level, target) = (self.level[idx], self.target(idx));
+            self.level[idx] = if level < target {
+                (level + step).min(target)
+            } else {
+                level.saturating_sub(step).max(target)
+            };
+            done &= self.level[idx] == target;
+        }
+        self.write_levels();
+        done
+    }
+
+    /// Blinking is a hard on/off, so it bypasses the fade.
+    fn set_blink_off(&mut self, off: LedMask) {
+        if off != self.blink_off {
+            self.blink_off = off;
+            self.snap();
+        }
+    }
 
-        Self {
-            led1,
-            led2,
-            led3,
-            led4,
+    /// Breathing follows its own curve, so it bypasses the fade too; LEDs
+    /// that stop breathing (or never did) are left alone.
+    fn set_breathe(&mut self, breathe: LedMask, level: u16) {
+        let changed = self.breathe | breathe;
+        self.breathe = breathe;
+        self.breathe_level = level;
+        if changed == 0 {
+            return;
         }
+        for idx in 0..self.level.len() {
+            if changed & (1 << idx) != 0 {
+                self.level[idx] = self.target(idx);
+            }
+        }
+        self.write_levels();
     }
 
+    /// Like blinking, a hard on/off. An LED that stops being the indicator
+    /// jumps to what its mask bit says.
+    fn set_alive(&mut self, alive: Option<usize>, lit: bool) {
+        let before = self.alive;
+        self.alive = alive;
+        self.alive_lit = lit;
+        for idx in [before, alive].into_iter().flatten() {
+            self.level[idx] = self.target(idx);
+        }
+        self.write_levels();
+    }
+
+    /// Off immediately (disconnect, reboot), without a fade. Also ends an
+    /// interrupted `check_outputs`.
     fn all_off(&mut self) {
-        self.led1.set_high();
-        self.led2.set_high();
-        self.led3.set_high();
-        self.led4.set_high();
+        self.mask = 0;
+        self.forced = None;
+        self.snap();
     }
 
-    fn apply_mask(&mut self, mask: u8) {
-        // active-low: LOW = ON, HIGH = OFF
-        if (mask & 0x01) != 0 {
-            self.led1.set_low();
-        } else {
-            self.led1.set_high();
+    /// Drive the pins with `levels` regardless of mask, ramp, blink and
+    /// breathing (which keep updating `level` underneath); `None` hands the
+    /// pins back.
+    fn force(&mut self, levels: Option<[u16; 4]>) {
+        self.forced = levels;
+        self.write_levels();
+    }
+
+    /// Light each LED in turn, then turn them all off, so a freshly flashed
+    /// board shows it is alive and wired correctly.
+    #[cfg(feature = "led-self-test")]
+    async fn self_test(&mut self) {
+        for bit in 0..4 {
+            // The ramp task isn't running yet.
+            self.mask = 1 << bit;
+            self.snap();
+            Timer::after(Duration::from_millis(150)).await;
         }
-        if (mask & 0x02) != 0 {
-            self.led2.set_low();
-        } else {
-            self.led2.set_high();
+        self.all_off();
+    }
+
+    /// Blink the fitted LEDs together to say why the board reset (see
+    /// `RESET_BLINKS`), so the cause shows without a debugger or host.
+    #[cfg(feature = "boot-reason-blink")]
+    async fn show_reset_reason(&mut self, reason: u32) {
+        let blinks = match reason {
+            0 => 1,
+            _ => RESET_BLINKS
+                .iter()
+                .find(|(bit, _)| reason & bit != 0)
+                .map_or(RESET_BLINKS_OTHER, |(_, n)| *n),
+        };
+        for _ in 0..blinks {
+            // The ramp task isn't running yet.
+            self.mask = PRESENT_LEDS as LedMask;
+            self.snap();
+            Timer::after(RESET_BLINK_TIME).await;
+            self.all_off();
+            Timer::after(RESET_BLINK_TIME).await;
         }
-        if (mask & 0x04) != 0 {
-            self.led3.set_low();
+        // Keeps the count apart from whatever the LEDs do next.
+        Timer::after(RESET_BLINK_TIME * 3).await;
+    }
+
+    /// Bits beyond the available LEDs, or for absent ones, are ignored.
+    /// Fades unless the ramp is set to 0.
+    fn apply_mask(&mut self, mask: LedMask) {
+        self.mask = mask & ((1 << self.level.len()) - 1) & PRESENT_LEDS as LedMask;
+        if LED_RAMP_CURRENT_MS.load(Ordering::Relaxed) == 0 {
+            self.snap();
         } else {
-            self.led3.set_high();
+            LED_RAMP.signal(());
+        }
+    }
+
+    /// Mask the LEDs show (or are fading toward). Kept by `apply_mask` as
+    /// the single source of truth rather than read back from the PWM duty,
+    /// which lags it during a fade and dips with blinking and breathing.
+    fn current_mask(&self) -> LedMask {
+        self.mask
+    }
+}
+
+/// Continuity self-test for manufacturing: drive each LED pin fully lit,
+/// then dark, and read the pad back through its input buffer. An LED passes
+/// if its pin is an output and follows both levels. Nothing senses the LED
+/// current, so this catches misconfiguration (wrong pin, pin not routed to
+/// the PWM, wrong polarity), not a dead LED. Returns the LEDs that passed.
+async fn check_outputs(leds: &RefCell<Leds>) -> LedMask {
+    let mut passed: LedMask = 0;
+    for (idx, pin) in LED_PINS.into_iter().enumerate() {
+        if PRESENT_LEDS & (1 << idx) == 0 {
+            continue;
+        }
+        let cnf = pac::P0.pin_cnf(pin);
+        let mut ok = cnf.read().dir() == pac::gpio::vals::Dir::OUTPUT;
+        cnf.modify(|w| w.set_input(pac::gpio::vals::Input::CONNECT));
+        for lit in [true, false] {
+            let mut levels = [0; 4];
+            levels[idx] = if lit { LED_PWM_TOP } else { 0 };
+            leds.borrow_mut().force(Some(levels));
+            Timer::after(SELF_TEST_SETTLE).await;
+            let high = pac::P0.in_().read().pin(pin);
+            ok &= high == (lit != LEDS_ACTIVE_LOW);
         }
-        if (mask & 0x08) != 0 {
-            self.led4.set_low();
+        cnf.modify(|w| w.set_input(pac::gpio::vals::Input::DISCONNECT));
+        if ok {
+            passed |= 1 << idx;
         } else {
-            self.led4.set_high();
+            warn!("self-test: LED{} (P0.{}) failed", idx + 1, pin);
+            report_error(FwError::SelfTestFailed);
         }
     }
+    leds.borrow_mut().force(None);
+    passed
+}
+
+/// `DEVICE_NAME` plus the low 16 bits of the factory-programmed DEVICEID,
+/// e.g. "HelloRust-A1B2".
+fn unique_device_name() -> heapless::String<{ DEVICE_NAME_MAX_LEN as usize }> {
+    let id = pac::FICR.deviceid(0).read() as u16;
+    let mut name = heapless::String::new();
+    // Always fits: checked against DEVICE_NAME_MAX_LEN at compile time.
+    let _ = fmt::write(&mut name, format_args!("{}-{:04X}", DEVICE_NAME, id));
+    name
+}
+
+/// Turn the LEDs off and enter System OFF; pressing `WAKE_PIN` resets the
+/// board.
+fn system_off() -> ! {
+    info!("advertising timed out; System OFF until button 1");
+    // Like the panic handler: release the pins so the LEDs stay dark.
+    pac::PWM0.enable().write(|w| w.set_enable(false));
+    let p0 = pac::P0;
+    for pin in LED_PINS {
+        p0.pin_cnf(pin).write(|w| {
+            w.set_dir(pac::gpio::vals::Dir::INPUT);
+            w.set_input(pac::gpio::vals::Input::DISCONNECT);
+        });
+    }
+    // Active-low button with the pull-up on our side.
+    p0.pin_cnf(WAKE_PIN).write(|w| {
+        w.set_dir(pac::gpio::vals::Dir::INPUT);
+        w.set_input(pac::gpio::vals::Input::CONNECT);
+        w.set_pull(pac::gpio::vals::Pull::PULLUP);
+        w.set_sense(pac::gpio::vals::Sense::LOW);
+    });
+    let ret = unsafe { raw::sd_power_system_off() };
+    // Only returns on error (or emulated System OFF under a debugger).
+    warn!("sd_power_system_off failed: {}", ret);
+    loop {
+        cortex_m::asm::wfe();
+    }
+}
+
+/// Advertising config with `secs` as the timeout (0 = none).
+fn adv_config(secs: u16) -> peripheral::Config {
+    let secs = secs.min(ADV_TIMEOUT_MAX_SECS);
+    peripheral::Config { timeout: (secs > 0).then_some(secs * 100), ..Default::default() }
+}
+
+/// Change the GAP device name (what the Device Name characteristic reads).
+fn set_device_name(name: &str) {
+    let perm = device_name_write_perm();
+    let ret = unsafe { raw::sd_ble_gap_device_name_set(&perm, name.as_ptr(), name.len() as u16) };
+    if ret != raw::NRF_SUCCESS {
+        warn!("sd_ble_gap_device_name_set failed: {}", ret);
+        report_error(FwError::DeviceNameSet);
+    }
+}
+
+/// Advertisement and scan response carrying `name`, laid out as `ADV_LAYOUT`
+/// says. Each fits the 31 legacy bytes for names up to `DEVICE_NAME_MAX_LEN`.
+fn adv_payloads(name: &str) -> (LegacyAdvertisementPayload, LegacyAdvertisementPayload) {
+    let adv = LegacyAdvertisementBuilder::new().flags(&[Flag::GeneralDiscovery, Flag::LE_Only]);
+    let uuid = LegacyAdvertisementBuilder::new().services_128(ServiceList::Complete, &[LED_SERVICE_UUID_LE]);
+    match ADV_LAYOUT {
+        AdvLayout::NameFirst => (
+            adv.services_16(ServiceList::Complete, ADV_SERVICES_16)
+                .full_name(name)
+                .build(),
+            uuid.build(),
+        ),
+        AdvLayout::UuidFirst => (
+            adv.services_128(ServiceList::Complete, &[LED_SERVICE_UUID_LE])
+                .adapt_name(name)
+                .build(),
+            LegacyAdvertisementBuilder::new()
+                .services_16(ServiceList::Complete, ADV_SERVICES_16)
+                .full_name(name)
+                .build(),
+        ),
+        AdvLayout::Single => (
+            adv.services_128(ServiceList::Complete, &[LED_SERVICE_UUID_LE])
+                .adapt_name(name)
+                .build(),
+            LegacyAdvertisementBuilder::new().build(),
+        ),
+    }
+}
+
+/// Resolve once the identify state changes: on a long press, switch the
+/// name to `IDENTIFY_NAME`; once `IDENTIFY_TIME` is up, switch it back. The
+/// caller restarts advertising so the new name goes on air.
+async fn identify_change(until: &Cell<Option<Instant>>, device_name: &str) {
+    match until.get() {
+        None => {
+            IDENTIFY.wait().await;
+            info!("identify: advertising as {} for {}s", IDENTIFY_NAME, IDENTIFY_TIME.as_secs());
+            fw_log(format_args!("identify for {}s", IDENTIFY_TIME.as_secs()));
+            set_device_name(IDENTIFY_NAME);
+            until.set(Some(Instant::now() + IDENTIFY_TIME));
+        }
+        Some(t) => {
+            Timer::at(t).await;
+            info!("identify: back to {}", device_name);
+            set_device_name(device_name);
+            until.set(None);
+        }
+    }
+}
+
+/// Security mode 1 level 1 (open) when the name is writable, otherwise
+/// mode 0 level 0 (no access).
+fn device_name_write_perm() -> raw::ble_gap_conn_sec_mode_t {
+    if DEVICE_NAME_WRITABLE {
+        raw::ble_gap_conn_sec_mode_t {
+            _bitfield_1: raw::ble_gap_conn_sec_mode_t::new_bitfield_1(1, 1),
+        }
+    } else {
+        unsafe { mem::zeroed() }
+    }
+}
+
+/// Update the GATT value to `mask` and, if subscribed, queue a notification
+/// (sent by `notify_mask`).
+fn publish_mask(server: &Server, notify: bool, mask: LedMask) {
+    let _ = server.led.led_mask_set(&mask_value(mask));
+    let _ = server.led.channel_mask_set(&channels_from_mask(mask));
+    if notify {
+        MASK_CHANGED.signal(());
+    }
+}
+
+/// Notify `led_mask` at most once per connection interval, always with the
+/// latest value. Host-side strobing can write far faster than that; one
+/// notification per write would back up the SoftDevice's queue and fail.
+async fn notify_mask(server: &Server, conn: &Connection, notify: &Cell<bool>) -> ! {
+    loop {
+        MASK_CHANGED.wait().await;
+        if !notify.get() {
+            continue;
+        }
+        let Ok(value) = server.led.led_mask_get() else { continue };
+        if let Err(err) = server.led.led_mask_notify(conn, &value) {
+            warn!("notify led_mask failed: {:?}", err);
+            report_error(FwError::NotifyFailed);
+        }
+        // Interval in 1.25 ms units; it can change during the connection.
+        let interval = conn.conn_params().max_conn_interval as u64 * 1250;
+        Timer::after(Duration::from_micros(interval)).await;
+    }
+}
+
+/// Toggle LEDs on button presses, publishing the result the same way a host
+/// write would, so a connected GUI sees the change via notifications.
+/// Ignored during autoplay, like mask writes: the next step would overwrite
+/// the toggle anyway.
+async fn handle_buttons(leds: &RefCell<Leds>, server: &Server, notify: &Cell<bool>) -> ! {
+    loop {
+        let idx = BUTTON_EVENTS.receive().await;
+        if AUTOPLAY.load(Ordering::Relaxed) {
+            warn!("button {} ignored: autoplay", idx + 1);
+            fw_log(format_args!("button {} ignored (autoplay)", idx + 1));
+            continue;
+        }
+        let mut leds = leds.borrow_mut();
+        let mask = leds.current_mask() ^ BUTTON_TOGGLES[idx];
+        leds.apply_mask(mask);
+
+        let current = leds.current_mask();
+        info!("button {} -> LED mask 0x{:04x}", idx + 1, current);
+        publish_mask(server, notify.get(), current);
+    }
 }
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
+    paint_stack();
     info!("Hello World!");
 
     // Initialize Embassy peripherals with interrupt priorities compatible with SoftDevice.
@@ -113,9 +1589,57 @@ async fn main(spawner: Spawner) {
 
     let p = embassy_nrf::init(ecfg);
 
-    let mut leds = Leds::new(p);
+    // Must run before the SoftDevice takes over the POWER peripheral.
+    let reset_reason = take_reset_reason();
+    info!("reset reason: 0x{:08x}", reset_reason);
+
+    // nRF52840-DK LEDs are P0.13..P0.16.
+    let pwm = SimplePwm::new_4ch(p.PWM0, p.P0_13, p.P0_14, p.P0_15, p.P0_16);
+    let mut leds = Leds::new(pwm, LEDS_ACTIVE_LOW);
     leds.all_off();
 
+    #[cfg(feature = "led-self-test")]
+    leds.self_test().await;
+    #[cfg(feature = "boot-reason-blink")]
+    leds.show_reset_reason(reset_reason).await;
+
+    // Shared between the GATT handler, button handling and the ramp task.
+    static LEDS: StaticCell<RefCell<Leds>> = StaticCell::new();
+    let leds: &'static RefCell<Leds> = LEDS.init(RefCell::new(leds));
+    unwrap!(spawner.spawn(led_ramp_task(leds)));
+    unwrap!(spawner.spawn(led_blink_task(leds)));
+    unwrap!(spawner.spawn(led_breathe_task(leds)));
+    unwrap!(spawner.spawn(led_alive_task(leds)));
+
+    let advertise_only = Cell::new(ADVERTISE_ONLY);
+    info!(
+        "advertising mode: {}",
+        if ADVERTISE_ONLY { "non-connectable (advertise-only)" } else { "connectable" }
+    );
+
+    let adv_timeout = Cell::new(ADV_TIMEOUT_SECS);
+
+    let locked = Cell::new(false);
+
+    // Survives reconnects; reset to the defaults on reboot.
+    let link_guard = Cell::new(LinkGuard::from_bytes([RSSI_GUARD_DBM as u8, RSSI_GUARD_SECS]));
+
+    // DK buttons are active-low with the pull-up on the MCU side. Plain
+    // `Input`s rather than GPIOTE channels: the identify button also reads
+    // its level while held, and edges come from the GPIOTE PORT event.
+    let buttons = [
+        Input::new(p.P0_11, Pull::Up),
+        Input::new(p.P0_12, Pull::Up),
+        Input::new(p.P0_24, Pull::Up),
+        Input::new(p.P0_25, Pull::Up),
+    ];
+    for (idx, button) in buttons.into_iter().enumerate() {
+        unwrap!(spawner.spawn(button_task(idx, button)));
+    }
+
+    let device_name = unique_device_name();
+    info!("device name: {}", device_name.as_str());
+
     let config = nrf_softdevice::Config {
         clock: Some(raw::nrf_clock_lf_cfg_t {
             source: raw::NRF_CLOCK_LF_SRC_RC as u8,
@@ -133,16 +1657,16 @@ async fn main(spawner: Spawner) {
         }),
         gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
             adv_set_count: 1,
-            periph_role_count: 3,
+            periph_role_count: PERIPH_ROLE_COUNT,
             central_role_count: 3,
             central_sec_count: 0,
             _bitfield_1: raw::ble_gap_cfg_role_count_t::new_bitfield_1(0),
         }),
         gap_device_name: Some(raw::ble_gap_cfg_device_name_t {
-            p_value: b"HelloRust" as *const u8 as _,
-            current_len: 9,
-            max_len: 9,
-            write_perm: unsafe { mem::zeroed() },
+            p_value: device_name.as_ptr() as _,
+            current_len: device_name.len() as u16,
+            max_len: DEVICE_NAME_MAX_LEN,
+            write_perm: device_name_write_perm(),
             _bitfield_1: raw::ble_gap_cfg_device_name_t::new_bitfield_1(raw::BLE_GATTS_VLOC_STACK as u8),
         }),
         ..Default::default()
@@ -151,56 +1675,541 @@ async fn main(spawner: Spawner) {
     let sd = Softdevice::enable(&config);
     let server = unwrap!(Server::new(sd));
     unwrap!(spawner.spawn(softdevice_task(sd)));
+    let _ = server.led.adv_timeout_set(&ADV_TIMEOUT_SECS.to_le_bytes());
 
-    static ADV_DATA: LegacyAdvertisementPayload = LegacyAdvertisementBuilder::new()
-        .flags(&[Flag::GeneralDiscovery, Flag::LE_Only])
-        .services_16(ServiceList::Complete, &[ServiceUuid16::BATTERY])
-        .full_name("HelloRust")
-        .build();
+    // Flash goes through the SoftDevice, so only now that it runs.
+    let mut flash = Flash::take(sd);
+    let mut record = [0u8; PLAYLIST_RECORD_LEN];
+    let stored = match flash.read(PLAYLIST_FLASH_ADDR, &mut record).await {
+        Ok(()) => playlist_from_record(&record),
+        Err(err) => {
+            warn!("playlist read failed: {:?}", err);
+            None
+        }
+    };
+    let (steps, autoplay) = stored.unwrap_or_default();
+    info!("playlist: {} step(s), autoplay {}", steps.len(), autoplay);
+    let _ = server.led.playlist_set(&playlist_value(&steps));
+    let _ = server.led.autoplay_set(&(autoplay as u8));
+    AUTOPLAY.store(autoplay, Ordering::Relaxed);
+    // Shared between the GATT handler and the autoplay tasks.
+    static PLAYLIST: StaticCell<RefCell<Playlist>> = StaticCell::new();
+    let playlist: &'static RefCell<Playlist> = PLAYLIST.init(RefCell::new(steps));
+    unwrap!(spawner.spawn(playlist_task(leds, playlist)));
+    unwrap!(spawner.spawn(playlist_store_task(flash, playlist)));
 
-    static SCAN_DATA: LegacyAdvertisementPayload = LegacyAdvertisementBuilder::new()
-        .services_128(
-            ServiceList::Complete,
-            &[0x9e7312e0_2354_11eb_9f10_fbc30a62cf38_u128.to_le_bytes()],
-        )
-        .build();
+    // Mask shown when the last connection dropped (`RESTORE_MASK_ON_CONNECT`).
+    let restore_mask: Cell<Option<LedMask>> = Cell::new(None);
+    // Set while the board is identifying (see `identify_change`).
+    let identify_until: Cell<Option<Instant>> = Cell::new(None);
 
     loop {
-        let config = peripheral::Config::default();
+        // Rebuilt every time round: the name changes while identifying.
+        let (adv_data, scan_data) = match identify_until.get() {
+            Some(_) => adv_payloads(IDENTIFY_NAME),
+            None => adv_payloads(&device_name),
+        };
+        let config = adv_config(adv_timeout.get());
         let adv = peripheral::ConnectableAdvertisement::ScannableUndirected {
-            adv_data: &ADV_DATA,
-            scan_data: &SCAN_DATA,
+            adv_data: &adv_data,
+            scan_data: &scan_data,
         };
-        let conn = unwrap!(peripheral::advertise_connectable(sd, adv, &config).await);
+        // CCCDs start cleared on every new (unbonded) connection.
+        let led_notify = Cell::new(false);
+
+        if advertise_only.get() {
+            let adv = peripheral::NonconnectableAdvertisement::ScannableUndirected {
+                adv_data: &adv_data,
+                scan_data: &scan_data,
+            };
+            let adv_fut = peripheral::advertise(sd, adv, &config);
+            let button_fut = handle_buttons(leds, &server, &led_notify);
+            let identify_fut = identify_change(&identify_until, &device_name);
+            pin_mut!(adv_fut);
+            pin_mut!(button_fut);
+            pin_mut!(identify_fut);
+            match select(adv_fut, select(button_fut, identify_fut)).await {
+                Either::Left((Err(AdvertiseError::Timeout), _)) => system_off(),
+                Either::Left((r, _)) => warn!("non-connectable advertising stopped: {:?}", r),
+                // Restart advertising under the new name.
+                Either::Right((Either::Right(_), _)) => continue,
+                Either::Right((Either::Left(_), _)) => core::unreachable!(),
+            }
+            // Don't spin if the SoftDevice keeps refusing.
+            Timer::after(Duration::from_secs(1)).await;
+            continue;
+        }
+        let test_mode = Cell::new(false);
+
+        // Buttons keep working while nobody is connected.
+        let conn = {
+            let adv_fut = peripheral::advertise_connectable(sd, adv, &config);
+            let button_fut = handle_buttons(leds, &server, &led_notify);
+            let identify_fut = identify_change(&identify_until, &device_name);
+            pin_mut!(adv_fut);
+            pin_mut!(button_fut);
+            pin_mut!(identify_fut);
+            match select(adv_fut, select(button_fut, identify_fut)).await {
+                Either::Left((Err(AdvertiseError::Timeout), _)) => system_off(),
+                Either::Left((conn, _)) => unwrap!(conn),
+                Either::Right((Either::Right(_), _)) => continue,
+                Either::Right((Either::Left(_), _)) => core::unreachable!(),
+            }
+        };
+
+        // Nobody needs to find a board that's already connected.
+        if identify_until.take().is_some() {
+            info!("identify: cancelled by connection");
+            set_device_name(&device_name);
+        }
 
         info!("connected!");
+        let _ = server.led.connections_set(&[PERIPH_ROLE_COUNT, MAX_PERIPH_LINKS, 1]);
+        fw_log(format_args!("connected, reset reason 0x{:x}", reset_reason));
+
+        if let Some(mask) = restore_mask.take().filter(|_| leds.borrow().current_mask() == 0) {
+            info!("restoring LED mask 0x{:04x}", mask);
+            leds.borrow_mut().apply_mask(mask);
+        }
+
+        // The GATT table is what clients read, so seed it from `Leds`
+        // rather than whatever was last written.
+        let mask = leds.borrow().current_mask();
+        let _ = server.led.led_mask_set(&mask_value(mask));
+        let _ = server.led.channel_mask_set(&channels_from_mask(mask));
+        let _ = server.led.test_mode_set(&0);
+        let _ = server.led.staging_set(&0);
+        let _ = server.led.last_error_set(&LAST_ERROR.load(Ordering::Relaxed).to_le_bytes());
+
+        let heartbeat_notify = Cell::new(false);
+        let last_error_notify = Cell::new(false);
+        let memory_notify = Cell::new(false);
+        let conn_params_notify = Cell::new(false);
+
+        // Reads are served from the attribute table, so keep the uptime fresh
+        // while a client is connected. The heartbeat rides along: it runs on
+        // the same executor as everything else, so it stops if we hang.
+        let diag_fut = async {
+            let mut beat: u32 = 0;
+            let mut params = conn_params_value(&conn.conn_params());
+            let _ = server.led.conn_params_set(&params);
+            loop {
+                let uptime = Instant::now().as_secs() as u32;
+                let _ = server.led.diagnostics_set(&diagnostics_value(uptime, reset_reason));
+
+                // The central changes them when it likes, ours or not.
+                let now = conn_params_value(&conn.conn_params());
+                if now != params {
+                    params = now;
+                    info!("conn params: {=[u8]:x}", &params[..]);
+                    let _ = server.led.conn_params_set(&params);
+                    if conn_params_notify.get() {
+                        if let Err(err) = server.led.conn_params_notify(&conn, &params) {
+                            warn!("notify conn_params failed: {:?}", err);
+                            report_error(FwError::NotifyFailed);
+                        }
+                    }
+                }
+
+                beat = beat.wrapping_add(1);
+                let _ = server.led.heartbeat_set(&beat.to_le_bytes());
+                if heartbeat_notify.get() {
+                    if let Err(err) = server.led.heartbeat_notify(&conn, &beat.to_le_bytes()) {
+                        warn!("notify heartbeat failed: {:?}", err);
+                        report_error(FwError::NotifyFailed);
+                    }
+                }
+                Timer::after(Duration::from_secs(1)).await;
+            }
+        };
 
-        let r = gatt_server::run(&conn, &server, |e| match e {
+        let button_fut = handle_buttons(leds, &server, &led_notify);
+        // Left over from the previous connection.
+        MASK_CHANGED.reset();
+        let mask_notify_fut = notify_mask(&server, &conn, &led_notify);
+        let guard_fut = guard_link(&conn, &link_guard);
+        // Already in the table from above.
+        LAST_ERROR_CHANGED.reset();
+        let last_error_fut = async {
+            loop {
+                LAST_ERROR_CHANGED.wait().await;
+                let value = LAST_ERROR.load(Ordering::Relaxed).to_le_bytes();
+                let _ = server.led.last_error_set(&value);
+                if last_error_notify.get() {
+                    // Not reported itself: that would only come back here.
+                    if let Err(err) = server.led.last_error_notify(&conn, &value) {
+                        warn!("notify last_error failed: {:?}", err);
+                    }
+                }
+            }
+        };
+        // Autoplay steps reach the GATT table (and notifications) like writes.
+        AUTOPLAY_MASK.reset();
+        let autoplay_fut = async {
+            loop {
+                let mask = AUTOPLAY_MASK.wait().await;
+                publish_mask(&server, led_notify.get(), mask);
+            }
+        };
+        let memory_fut = async {
+            loop {
+                let value = memory_value();
+                let _ = server.led.memory_set(&value);
+                if memory_notify.get() {
+                    if let Err(err) = server.led.memory_notify(&conn, &value) {
+                        warn!("notify memory failed: {:?}", err);
+                        report_error(FwError::NotifyFailed);
+                    }
+                }
+                Timer::after(MEMORY_INTERVAL).await;
+            }
+        };
+
+        let staging = Cell::new(false);
+        let staged = Cell::new(Staged::default());
+
+        // Set for the duration of a `led_mask` write without response.
+        let fast_write = Cell::new(false);
+        // Shared by the per-feature characteristics and `control`; false if
+        // `locked` or autoplay refused the write.
+        let write_mask = |mask: LedMask| {
+            if locked.get() {
+                warn!("LED mask write 0x{:04x} ignored: locked", mask);
+                fw_log(format_args!("mask 0x{:04x} ignored (locked)", mask));
+                // Undo the write in the GATT table and tell the writer.
+                publish_mask(&server, led_notify.get(), leds.borrow().current_mask());
+                return false;
+            }
+            if AUTOPLAY.load(Ordering::Relaxed) {
+                warn!("LED mask write 0x{:04x} ignored: autoplay", mask);
+                fw_log(format_args!("mask 0x{:04x} ignored (autoplay)", mask));
+                publish_mask(&server, led_notify.get(), leds.borrow().current_mask());
+                return false;
+            }
+            if staging.get() {
+                info!("LED mask write: 0x{:04x} (staged)", mask);
+                let mut s = staged.get();
+                s.mask = Some(mask);
+                staged.set(s);
+                return true;
+            }
+            if test_mode.get() {
+                info!("LED mask write: 0x{:04x} (test mode, not applied)", mask);
+                fw_log(format_args!("mask 0x{:04x} (test)", mask));
+                publish_mask(&server, led_notify.get(), mask);
+                return true;
+            }
+            info!("LED mask write: 0x{:04x}", mask);
+            if !fast_write.get() {
+                fw_log(format_args!("mask 0x{:04x}", mask));
+            }
+            let mut leds = leds.borrow_mut();
+            leds.apply_mask(mask);
+
+            // Report what the pins actually show (e.g. bits above LED4
+            // are dropped), both for reads and for notifications.
+            let notify = led_notify.get() && (NOTIFY_FAST_WRITES || !fast_write.get());
+            publish_mask(&server, notify, leds.current_mask());
+            true
+        };
+        let set_blink = |v: [u8; 8]| {
+            for (period, b) in BLINK_PERIODS.iter().zip(v.chunks_exact(2)) {
+                period.store(u16::from_le_bytes([b[0], b[1]]), Ordering::Relaxed);
+            }
+            info!("LED blink periods: {=[u8]:x}", &v[..]);
+            let _ = server.led.led_blink_set(&v);
+            BLINK_CHANGED.signal(());
+        };
+        let set_breathe_mask = |v: [u8; 2]| {
+            let mask = LedMask::from_le_bytes(v) & ((1 << LED_COUNT) - 1);
+            info!("LED breathe mask: 0x{:04x}", mask);
+            BREATHE_MASK.store(mask, Ordering::Relaxed);
+            let _ = server.led.breathe_mask_set(&mask.to_le_bytes());
+            BREATHE_CHANGED.signal(());
+        };
+        let set_breathe_period = |v: [u8; 2]| {
+            let ms = u16::from_le_bytes(v).max(BREATHE_MIN_PERIOD_MS);
+            info!("LED breathe period: {} ms", ms);
+            BREATHE_PERIOD_CURRENT_MS.store(ms, Ordering::Relaxed);
+            let _ = server.led.breathe_period_set(&ms.to_le_bytes());
+        };
+        let set_test_mode = |on: bool| {
+            info!("test mode: {}", on);
+            test_mode.set(on);
+            let _ = server.led.test_mode_set(&(on as u8));
+            if !on {
+                // Test writes left the GATT value out of step with the pins.
+                publish_mask(&server, led_notify.get(), leds.borrow().current_mask());
+            }
+        };
+        let set_link_guard = |v: [u8; 2]| {
+            let g = LinkGuard::from_bytes(v);
+            info!("link guard: below {} dBm for {}s", g.threshold_dbm, g.secs);
+            link_guard.set(g);
+            let _ = server.led.link_guard_set(&v);
+        };
+        let control_notify = Cell::new(false);
+        let nus_notify = Cell::new(false);
+        let nus_fut = stream_fw_log(&server, &conn, &nus_notify);
+
+        let tap = WriteOpTap {
+            server: &server,
+            fast: Cell::new(false),
+        };
+        let gatt_fut = gatt_server::run(&conn, &tap, |e| match e {
+            #[cfg(feature = "battery-service")]
             ServerEvent::Bas(e) => match e {
                 BatteryServiceEvent::BatteryLevelCccdWrite { notifications } => {
                     info!("battery notifications: {}", notifications)
                 }
             },
 
-            ServerEvent::Led(e) => match e {
-                LedServiceEvent::LedMaskWrite(mask) => {
-                    info!("LED mask write: 0x{:02x}", mask);
-                    leds.apply_mask(mask);
+            // Read-only, so it never raises events.
+            ServerEvent::Dis(e) => match e {},
+
+            ServerEvent::Nus(e) => match e {
+                NusServiceEvent::TxCccdWrite { notifications } => {
+                    info!("nus notifications: {}", notifications);
+                    nus_notify.set(notifications);
+                }
+            },
 
-                    // Optional: notify back current mask so PC can confirm state.
-                    if let Err(err) = server.led.led_mask_notify(&conn, &mask) {
-                        warn!("notify led_mask failed: {:?}", err);
+            ServerEvent::Led(e) => match e {
+                LedServiceEvent::LedMaskWrite(bytes) => {
+                    fast_write.set(tap.fast.get());
+                    write_mask(mask_from_bytes(&bytes));
+                    fast_write.set(false);
+                }
+                LedServiceEvent::ChannelMaskWrite(v) => {
+                    info!("channel mask write: 0x{:02x}", v);
+                    // On top of an earlier staged mask, if there is one.
+                    let current = staged.get().mask.unwrap_or_else(|| leds.borrow().current_mask());
+                    write_mask(mask_from_channels(current, v));
+                }
+                LedServiceEvent::StagingWrite(v) => {
+                    info!("staging: {}", v != 0);
+                    staging.set(v != 0);
+                    staged.take();
+                }
+                LedServiceEvent::ApplyWrite(_) => {
+                    let s = staged.take();
+                    staging.set(false);
+                    let _ = server.led.staging_set(&0);
+                    info!("applying staged values");
+                    fw_log(format_args!("apply"));
+                    // All from this one handler, so the LED tasks only ever
+                    // see the complete set. The mask goes last: its publish
+                    // is what hosts wait for.
+                    if let Some(v) = s.blink {
+                        set_blink(v);
+                    }
+                    if let Some(v) = s.breathe_mask {
+                        set_breathe_mask(v);
+                    }
+                    if let Some(v) = s.breathe_period {
+                        set_breathe_period(v);
+                    }
+                    if let Some(mask) = s.mask {
+                        write_mask(mask);
+                    }
+                }
+                LedServiceEvent::PlaylistWrite(bytes) => {
+                    let steps = playlist_from_bytes(&bytes);
+                    info!("playlist: {} step(s)", steps.len());
+                    fw_log(format_args!("playlist {} steps", steps.len()));
+                    let _ = server.led.playlist_set(&playlist_value(&steps));
+                    playlist.replace(steps);
+                    PLAYLIST_CHANGED.signal(());
+                    PLAYLIST_SAVE.signal(());
+                }
+                LedServiceEvent::ConnParamsWrite(v) => {
+                    info!("conn params requested: {=[u8]:x}", &v[..]);
+                    if let Err(err) = request_conn_params(&conn, v) {
+                        warn!("conn params request failed: {:?}", err);
+                        fw_log(format_args!("conn params refused"));
+                        report_error(FwError::ConnParamsRejected);
                     }
+                    // Reads show the active values; the new ones follow once
+                    // the central has agreed.
+                    let _ = server.led.conn_params_set(&conn_params_value(&conn.conn_params()));
+                }
+                LedServiceEvent::ConnParamsCccdWrite { notifications } => conn_params_notify.set(notifications),
+                LedServiceEvent::AutoplayWrite(v) => {
+                    info!("autoplay: {}", v != 0);
+                    fw_log(format_args!("autoplay {}", if v != 0 { "on" } else { "off" }));
+                    AUTOPLAY.store(v != 0, Ordering::Relaxed);
+                    PLAYLIST_CHANGED.signal(());
+                    PLAYLIST_SAVE.signal(());
+                }
+                LedServiceEvent::LockedWrite(v) => {
+                    info!("LEDs {}", if v != 0 { "locked" } else { "unlocked" });
+                    fw_log(format_args!("{}", if v != 0 { "locked" } else { "unlocked" }));
+                    locked.set(v != 0);
                 }
                 LedServiceEvent::LedMaskCccdWrite { notifications } => {
-                    info!("led notifications: {}", notifications)
+                    info!("led notifications: {}", notifications);
+                    led_notify.set(notifications);
+                }
+                LedServiceEvent::TestModeWrite(v) => set_test_mode(v != 0),
+                LedServiceEvent::LedRampWrite(v) => {
+                    let ms = u16::from_le_bytes(v);
+                    info!("LED ramp: {} ms", ms);
+                    LED_RAMP_CURRENT_MS.store(ms, Ordering::Relaxed);
+                }
+                LedServiceEvent::LedBlinkWrite(v) if staging.get() => {
+                    let mut s = staged.get();
+                    s.blink = Some(v);
+                    staged.set(s);
+                }
+                LedServiceEvent::LedBlinkWrite(v) => set_blink(v),
+                LedServiceEvent::BreatheMaskWrite(v) if staging.get() => {
+                    let mut s = staged.get();
+                    s.breathe_mask = Some(v);
+                    staged.set(s);
+                }
+                LedServiceEvent::BreatheMaskWrite(v) => set_breathe_mask(v),
+                LedServiceEvent::BreathePeriodWrite(v) if staging.get() => {
+                    let mut s = staged.get();
+                    s.breathe_period = Some(v);
+                    staged.set(s);
+                }
+                LedServiceEvent::BreathePeriodWrite(v) => set_breathe_period(v),
+                LedServiceEvent::AliveLedWrite(v) => {
+                    // Out of range turns the indicator off.
+                    let led = if v <= LED_COUNT { v } else { 0 };
+                    info!("alive LED: {}", led);
+                    ALIVE_LED_CURRENT.store(led, Ordering::Relaxed);
+                    let _ = server.led.alive_led_set(&led);
+                    ALIVE_CHANGED.signal(());
+                }
+                LedServiceEvent::LinkGuardWrite(v) => set_link_guard(v),
+                LedServiceEvent::AdvTimeoutWrite(v) => {
+                    let secs = u16::from_le_bytes(v).min(ADV_TIMEOUT_MAX_SECS);
+                    info!("advertising timeout: {}s", secs);
+                    adv_timeout.set(secs);
+                    let _ = server.led.adv_timeout_set(&secs.to_le_bytes());
+                }
+                LedServiceEvent::AdvertiseOnlyWrite(v) => {
+                    let on = v != 0;
+                    info!("advertising mode after disconnect: {}", if on { "non-connectable" } else { "connectable" });
+                    advertise_only.set(on);
+                }
+                LedServiceEvent::ControlWrite(frame) => {
+                    let status = match decode_control(&frame) {
+                        Ok(ControlRequest::SetMask(mask)) => {
+                            if write_mask(mask) {
+                                ControlStatus::Ok
+                            } else if locked.get() {
+                                ControlStatus::Locked
+                            } else {
+                                ControlStatus::Autoplay
+                            }
+                        }
+                        Ok(ControlRequest::SetTestMode(on)) => {
+                            set_test_mode(on);
+                            ControlStatus::Ok
+                        }
+                        Ok(ControlRequest::SetLinkGuard(v)) => {
+                            set_link_guard(v);
+                            ControlStatus::Ok
+                        }
+                        Ok(ControlRequest::Reboot) => {
+                            info!("reboot requested");
+                            fw_log(format_args!("rebooting"));
+                            REBOOT.signal(());
+                            ControlStatus::Ok
+                        }
+                        Ok(ControlRequest::SelfTest) => {
+                            info!("self-test requested");
+                            // So a host polling for the result can't read the last one.
+                            let _ = server.led.self_test_set(&[0; 4]);
+                            SELF_TEST.signal(());
+                            ControlStatus::Ok
+                        }
+                        Err(status) => {
+                            warn!("control frame {=[u8]:x} rejected: {}", &frame[..], status);
+                            fw_log(format_args!("control rejected: status {}", status as u8));
+                            report_error(FwError::ControlRejected);
+                            status
+                        }
+                    };
+
+                    if control_notify.get() {
+                        let opcode = frame.get(1).copied().unwrap_or(0);
+                        let reply = [PROTOCOL_VERSION, opcode | OP_REPLY, status as u8];
+                        let reply = unwrap!(heapless::Vec::from_slice(&reply));
+                        if let Err(err) = server.led.control_notify(&conn, &reply) {
+                            warn!("notify control failed: {:?}", err);
+                            report_error(FwError::NotifyFailed);
+                        }
+                    }
+                }
+                LedServiceEvent::ControlCccdWrite { notifications } => control_notify.set(notifications),
+                LedServiceEvent::HeartbeatCccdWrite { notifications } => {
+                    info!("heartbeat notifications: {}", notifications);
+                    heartbeat_notify.set(notifications);
                 }
+                LedServiceEvent::LastErrorCccdWrite { notifications } => last_error_notify.set(notifications),
+                LedServiceEvent::MemoryCccdWrite { notifications } => memory_notify.set(notifications),
             },
-        })
-        .await;
+        });
+
+        // Give the write response and reply notification time to go out.
+        let reboot_fut = async {
+            REBOOT.wait().await;
+            Timer::after(Duration::from_millis(200)).await;
+            leds.borrow_mut().all_off();
+            info!("rebooting");
+            cortex_m::peripheral::SCB::sys_reset()
+        };
+
+        let self_test_fut = async {
+            loop {
+                SELF_TEST.wait().await;
+                let tested = PRESENT_LEDS as LedMask;
+                let passed = check_outputs(leds).await;
+                info!("self-test: passed 0x{:04x} of 0x{:04x}", passed, tested);
+                fw_log(format_args!(
+                    "self-test {}/{} ok",
+                    passed.count_ones(),
+                    tested.count_ones()
+                ));
+                let [t0, t1] = tested.to_le_bytes();
+                let [p0, p1] = passed.to_le_bytes();
+                let _ = server.led.self_test_set(&[t0, t1, p0, p1]);
+            }
+        };
+
+        let background_fut = join(
+            join(join(diag_fut, button_fut), mask_notify_fut),
+            join(
+                join(guard_fut, reboot_fut),
+                join(
+                    nus_fut,
+                    join(self_test_fut, join(last_error_fut, join(memory_fut, autoplay_fut))),
+                ),
+            ),
+        );
+        pin_mut!(background_fut);
+        pin_mut!(gatt_fut);
+
+        let r = match select(background_fut, gatt_fut).await {
+            Either::Left(_) => core::unreachable!(),
+            Either::Right((r, _)) => r,
+        };
 
         info!("disconnected: {:?}", r);
-        leds.all_off();
+        let _ = server.led.connections_set(&[PERIPH_ROLE_COUNT, MAX_PERIPH_LINKS, 0]);
+        if RESTORE_MASK_ON_CONNECT {
+            restore_mask.set(Some(leds.borrow().current_mask()));
+        }
+        // A locked display keeps showing what it was set to, and autoplay
+        // carries on without a host.
+        if !locked.get() && !AUTOPLAY.load(Ordering::Relaxed) {
+            leds.borrow_mut().all_off();
+        }
+        // Long presses while connected don't count.
+        IDENTIFY.reset();
     }
 }
 
@@ -0,0 +1,55 @@
+//! Connection parameters from the `conn_params` characteristic: interval
+//! (1.25 ms units), peripheral latency (events) and supervision timeout
+//! (10 ms units), u16 LE each. Writing the same layout asks the board to
+//! request them from the central, which has the final say.
+
+/// Bounds the GUI's sliders offer; the spec allows 7.5 ms..4 s and a latency
+/// of up to 499, but beyond these a board mostly just looks unresponsive.
+pub const MIN_INTERVAL_MS: f64 = 7.5;
+pub const MAX_INTERVAL_MS: f64 = 200.0;
+pub const MAX_LATENCY: u16 = 20;
+/// Supervision timeout floor; BlueZ's own default is 4 s as well.
+const MIN_TIMEOUT_MS: u32 = 4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnParams {
+    /// In 1.25 ms units.
+    pub interval: u16,
+    pub latency: u16,
+    /// In 10 ms units.
+    pub timeout: u16,
+}
+
+impl ConnParams {
+    /// Parameters for `interval_ms` and `latency`, with a timeout that covers
+    /// the events the board may skip: the spec wants more than twice the
+    /// latency-stretched interval; this gives three times, at least 4 s.
+    pub fn request(interval_ms: f64, latency: u16) -> Self {
+        let interval = (interval_ms.clamp(MIN_INTERVAL_MS, MAX_INTERVAL_MS) / 1.25).round() as u16;
+        let latency = latency.min(MAX_LATENCY);
+        let stretched_ms = (1 + latency as u32) * interval as u32 * 5 / 4;
+        let timeout_ms = (stretched_ms * 3).max(MIN_TIMEOUT_MS);
+        ConnParams { interval, latency, timeout: timeout_ms.div_ceil(10) as u16 }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let field = |i: usize| Some(u16::from_le_bytes(bytes.get(i..i + 2)?.try_into().ok()?));
+        Some(ConnParams { interval: field(0)?, latency: field(2)?, timeout: field(4)? })
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        [self.interval, self.latency, self.timeout].iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    pub fn interval_ms(self) -> f64 {
+        self.interval as f64 * 1.25
+    }
+}
+
+/// "30 ms, latency 0, timeout 4.00 s"
+impl std::fmt::Display for ConnParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let timeout_s = self.timeout as f64 / 100.0;
+        write!(f, "{} ms, latency {}, timeout {timeout_s:.2} s", self.interval_ms(), self.latency)
+    }
+}
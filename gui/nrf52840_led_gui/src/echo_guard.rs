@@ -0,0 +1,69 @@
+//! Keeps widget handlers from echoing programmatic updates back to the board.
+//!
+//! Setting a toggle, check or scale from code fires the same signal as a
+//! click, so reflecting the board's state (or All On/All Off) would otherwise
+//! write that state straight back. Code that updates widgets does so inside
+//! `quietly`; handlers only send when `should_echo` says the change was the
+//! user's.
+
+use std::cell::Cell;
+
+#[derive(Debug, Default)]
+pub struct EchoGuard {
+    /// Nesting depth of `quietly` calls.
+    depth: Cell<u32>,
+}
+
+impl EchoGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `update` with echoes suppressed. Nests, so a helper that guards
+    /// its own updates can be called from a guarded block.
+    pub fn quietly<R>(&self, update: impl FnOnce() -> R) -> R {
+        self.depth.set(self.depth.get() + 1);
+        let result = update();
+        self.depth.set(self.depth.get() - 1);
+        result
+    }
+
+    /// Whether a change seen now came from the user and should be sent.
+    pub fn should_echo(&self) -> bool {
+        self.depth.get() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_updates_go_through() {
+        let guard = EchoGuard::new();
+        assert!(guard.should_echo());
+    }
+
+    #[test]
+    fn programmatic_updates_are_suppressed() {
+        let guard = EchoGuard::new();
+        assert!(!guard.quietly(|| guard.should_echo()));
+        assert!(guard.should_echo());
+    }
+
+    #[test]
+    fn nested_updates_stay_suppressed_until_the_outer_one_ends() {
+        let guard = EchoGuard::new();
+        guard.quietly(|| {
+            guard.quietly(|| assert!(!guard.should_echo()));
+            assert!(!guard.should_echo());
+        });
+        assert!(guard.should_echo());
+    }
+
+    #[test]
+    fn returns_the_update_result() {
+        let guard = EchoGuard::new();
+        assert_eq!(guard.quietly(|| 5), 5);
+    }
+}
@@ -0,0 +1,86 @@
+//! Capabilities the firmware reports in its `features` characteristic: a
+//! u32 (LE) with one bit per compiled-in feature. The bit assignments mirror
+//! the `FEATURE_*` constants in `ble_led.rs`; new bits are only ever added.
+//!
+//! Boards that predate the characteristic report nothing, and the GUI falls
+//! back to probing for the individual characteristics.
+
+pub const PWM_RAMP: u32 = 1 << 0;
+pub const BLINK: u32 = 1 << 1;
+pub const HEARTBEAT: u32 = 1 << 2;
+pub const NUS_LOG: u32 = 1 << 3;
+pub const CONTROL: u32 = 1 << 4;
+pub const TEST_MODE: u32 = 1 << 5;
+pub const LINK_GUARD: u32 = 1 << 6;
+pub const BUTTONS: u32 = 1 << 7;
+pub const SELF_TEST: u32 = 1 << 8;
+pub const IDENTIFY: u32 = 1 << 9;
+pub const ADV_TIMEOUT: u32 = 1 << 10;
+pub const BREATHE: u32 = 1 << 11;
+pub const OUTPUT_TEST: u32 = 1 << 12;
+pub const CONN_LIMITS: u32 = 1 << 13;
+pub const LOCK: u32 = 1 << 14;
+pub const LAST_ERROR: u32 = 1 << 15;
+pub const ALIVE_LED: u32 = 1 << 16;
+pub const CHANNELS: u32 = 1 << 17;
+pub const MEMORY: u32 = 1 << 18;
+pub const STAGING: u32 = 1 << 19;
+pub const PRESENT_LEDS: u32 = 1 << 20;
+pub const PLAYLIST: u32 = 1 << 21;
+pub const CONN_PARAMS: u32 = 1 << 22;
+
+const NAMES: [(u32, &str); 23] = [
+    (PWM_RAMP, "pwm-ramp"),
+    (BLINK, "blink"),
+    (HEARTBEAT, "heartbeat"),
+    (NUS_LOG, "nus-log"),
+    (CONTROL, "control"),
+    (TEST_MODE, "test-mode"),
+    (LINK_GUARD, "link-guard"),
+    (BUTTONS, "buttons"),
+    (SELF_TEST, "self-test"),
+    (IDENTIFY, "identify"),
+    (ADV_TIMEOUT, "adv-timeout"),
+    (BREATHE, "breathe"),
+    (OUTPUT_TEST, "output-test"),
+    (CONN_LIMITS, "conn-limits"),
+    (LOCK, "lock"),
+    (LAST_ERROR, "last-error"),
+    (ALIVE_LED, "alive-led"),
+    (CHANNELS, "channels"),
+    (MEMORY, "memory"),
+    (STAGING, "staging"),
+    (PRESENT_LEDS, "present-leds"),
+    (PLAYLIST, "playlist"),
+    (CONN_PARAMS, "conn-params"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features(pub u32);
+
+impl Features {
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Features(u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?)))
+    }
+
+    pub fn has(&self, bit: u32) -> bool {
+        self.0 & bit != 0
+    }
+}
+
+/// Known feature names, comma-separated; unknown bits as hex.
+impl std::fmt::Display for Features {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names: Vec<String> =
+            NAMES.iter().filter(|(bit, _)| self.has(*bit)).map(|(_, name)| name.to_string()).collect();
+        let unknown = NAMES.iter().fold(self.0, |rest, (bit, _)| rest & !bit);
+        if unknown != 0 {
+            names.push(format!("0x{unknown:08x}"));
+        }
+        if names.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&names.join(", "))
+        }
+    }
+}
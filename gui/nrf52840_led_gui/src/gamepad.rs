@@ -0,0 +1,95 @@
+//! Optional gamepad input (cargo feature `gamepad`).
+//!
+//! Holding a mapped button lights its LED: every press or release sends
+//! `Cmd::SetMask` with the bits of all buttons currently held, so letting go
+//! of everything turns the LEDs off. Mask notifications from the board keep
+//! the GUI's toggles in step, exactly as for clicks.
+//!
+//! The mapping is `gamepad_buttons` in the settings file: button names in
+//! LED order, so the default `South, East, West, North` puts A/B/X/Y (Xbox
+//! layout) on LED1..LED4. Names are gilrs' `Button` variants (`DPadUp`,
+//! `LeftTrigger`, `Start`, ...); unknown ones are skipped with a warning on
+//! stderr. An empty list disables the backend.
+//!
+//! gilrs is polled, so this runs on its own thread and feeds the same
+//! command channel as the GUI.
+
+use gilrs::{Button, EventType, Gilrs};
+use tokio::sync::mpsc as tokio_mpsc;
+
+use crate::settings::Settings;
+use crate::{Cmd, MAX_LED_COUNT};
+
+/// Start listening for gamepads; does nothing if no buttons are mapped.
+pub fn spawn(settings: &Settings, cmd_tx: tokio_mpsc::UnboundedSender<Cmd>) {
+    let map: Vec<Option<Button>> =
+        settings.gamepad_buttons.iter().take(MAX_LED_COUNT as usize).map(|name| parse_button(name)).collect();
+    if map.iter().all(Option::is_none) {
+        return;
+    }
+
+    std::thread::spawn(move || {
+        let mut gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(e) => return eprintln!("gamepad: {e}"),
+        };
+        let mut held: u16 = 0;
+
+        loop {
+            let Some(event) = gilrs.next_event_blocking(None) else { continue };
+            let next = match event.event {
+                EventType::ButtonPressed(button, _) => held | bit_for(&map, button),
+                EventType::ButtonReleased(button, _) => held & !bit_for(&map, button),
+                // Don't leave LEDs stuck on when the pad goes away mid-press.
+                EventType::Disconnected => 0,
+                _ => continue,
+            };
+            if next == held {
+                continue;
+            }
+            held = next;
+            // The GUI (and with it the worker) has gone away.
+            if cmd_tx.send(Cmd::SetMask(held)).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+fn bit_for(map: &[Option<Button>], button: Button) -> u16 {
+    match map.iter().position(|b| *b == Some(button)) {
+        Some(idx) => 1 << idx,
+        None => 0,
+    }
+}
+
+fn parse_button(name: &str) -> Option<Button> {
+    let button = match name {
+        "South" => Button::South,
+        "East" => Button::East,
+        "North" => Button::North,
+        "West" => Button::West,
+        "C" => Button::C,
+        "Z" => Button::Z,
+        "LeftTrigger" => Button::LeftTrigger,
+        "LeftTrigger2" => Button::LeftTrigger2,
+        "RightTrigger" => Button::RightTrigger,
+        "RightTrigger2" => Button::RightTrigger2,
+        "Select" => Button::Select,
+        "Start" => Button::Start,
+        "Mode" => Button::Mode,
+        "LeftThumb" => Button::LeftThumb,
+        "RightThumb" => Button::RightThumb,
+        "DPadUp" => Button::DPadUp,
+        "DPadDown" => Button::DPadDown,
+        "DPadLeft" => Button::DPadLeft,
+        "DPadRight" => Button::DPadRight,
+        // A blank entry leaves that LED unmapped.
+        "" => return None,
+        other => {
+            eprintln!("gamepad: unknown button {other:?}, ignored");
+            return None;
+        }
+    };
+    Some(button)
+}
@@ -0,0 +1,120 @@
+//! Optional InfluxDB exporter (cargo feature `influx`).
+//!
+//! Receives the same `Telemetry` readings the GUI shows and writes them to an
+//! InfluxDB v2 `/api/v2/write` endpoint as line protocol, tagged by device
+//! address. Configured through the `influx_*` keys in the settings file; left
+//! disabled when `influx_url` is empty.
+//!
+//! Failures are logged to stderr and otherwise ignored: exporting must never
+//! stall or kill the BLE worker.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc as tokio_mpsc;
+
+use crate::settings::Settings;
+use crate::Telemetry;
+
+pub struct InfluxConfig {
+    url: String,
+    org: String,
+    bucket: String,
+    token: String,
+    /// At most one point per device per this interval.
+    min_interval: Duration,
+}
+
+impl InfluxConfig {
+    pub fn from_settings(s: &Settings) -> Option<Self> {
+        if s.influx_url.is_empty() {
+            return None;
+        }
+        Some(Self {
+            url: s.influx_url.trim_end_matches('/').to_string(),
+            org: s.influx_org.clone(),
+            bucket: s.influx_bucket.clone(),
+            token: s.influx_token.clone(),
+            min_interval: Duration::from_secs(s.influx_interval_secs),
+        })
+    }
+}
+
+/// Start the exporter on the current tokio runtime; feed it through the
+/// returned sender.
+pub fn spawn(cfg: InfluxConfig) -> tokio_mpsc::UnboundedSender<Telemetry> {
+    let (tx, mut rx) = tokio_mpsc::unbounded_channel::<Telemetry>();
+
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        let mut last_sent: HashMap<String, Instant> = HashMap::new();
+
+        while let Some(t) = rx.recv().await {
+            if last_sent.get(&t.addr).is_some_and(|at| at.elapsed() < cfg.min_interval) {
+                continue;
+            }
+            let Some(line) = line_protocol(&t) else { continue };
+
+            match write(&client, &cfg, line).await {
+                Ok(()) => {
+                    last_sent.insert(t.addr.clone(), Instant::now());
+                }
+                Err(e) => eprintln!("influx: write failed: {e:#}"),
+            }
+        }
+    });
+
+    tx
+}
+
+async fn write(client: &reqwest::Client, cfg: &InfluxConfig, body: String) -> Result<()> {
+    let resp = client
+        .post(format!("{}/api/v2/write", cfg.url))
+        .query(&[("org", cfg.org.as_str()), ("bucket", cfg.bucket.as_str()), ("precision", "s")])
+        .header("Authorization", format!("Token {}", cfg.token))
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(body)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .context("POST")?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let text = resp.text().await.unwrap_or_default();
+        bail!("HTTP {status}: {text}");
+    }
+    Ok(())
+}
+
+/// `ble_telemetry,addr=<addr> battery=87i,rssi=-60i <unix secs>`, or `None`
+/// if there's nothing to report.
+fn line_protocol(t: &Telemetry) -> Option<String> {
+    let mut fields = Vec::new();
+    if let Some(b) = t.battery {
+        fields.push(format!("battery={b}i"));
+    }
+    if let Some(r) = t.rssi {
+        fields.push(format!("rssi={r}i"));
+    }
+    if let Some((_, peak)) = t.stack {
+        fields.push(format!("stack_peak={peak}i"));
+    }
+    if fields.is_empty() {
+        return None;
+    }
+
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    Some(format!("ble_telemetry,addr={} {} {ts}", escape_tag(&t.addr), fields.join(",")))
+}
+
+fn escape_tag(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
@@ -0,0 +1,188 @@
+//! Headless `inventory` subcommand: scan, then briefly connect to every board
+//! exposing the LED service and print what it reports.
+//!
+//! ```text
+//! nrf52840_led_gui inventory [--json]
+//! ```
+//!
+//! Output is CSV by default, or a JSON array with `--json`.
+
+use anyhow::{anyhow, Context, Result};
+use btleplug::api::{bleuuid::uuid_from_u16, Central, Manager as _, Peripheral as _, ScanFilter};
+use btleplug::platform::{Manager, Peripheral};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::settings::Settings;
+use crate::{collect_devices, mask_from_bytes, RssiFilter, RssiSmoother, DIAG_CHAR_UUID};
+
+const SCAN_TIME: Duration = Duration::from_secs(5);
+/// Upper bound for connect + discover + reads on a single board.
+const PER_DEVICE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+struct Row {
+    addr: String,
+    name: Option<String>,
+    rssi: Option<i16>,
+    battery: Option<u8>,
+    mask: Option<u16>,
+    uptime_secs: Option<u32>,
+    reset_reason: Option<u32>,
+    error: Option<String>,
+}
+
+pub fn run(args: &[String]) -> Result<()> {
+    let json = args.iter().any(|a| a == "--json");
+    let rt = tokio::runtime::Runtime::new().context("tokio runtime")?;
+    let rows = rt.block_on(inventory())?;
+
+    if json {
+        print_json(&rows);
+    } else {
+        print_csv(&rows);
+    }
+    Ok(())
+}
+
+async fn inventory() -> Result<Vec<Row>> {
+    let manager = Manager::new().await.context("btleplug Manager::new")?;
+    let adapters = manager.adapters().await.context("manager.adapters")?;
+    let adapter = adapters.into_iter().next().ok_or_else(|| anyhow!("No BLE adapters found"))?;
+
+    eprintln!("Scanning ({}s)...", SCAN_TIME.as_secs());
+    adapter.start_scan(ScanFilter::default()).await.context("start_scan")?;
+    tokio::time::sleep(SCAN_TIME).await;
+    adapter.stop_scan().await.ok();
+
+    let everything = RssiFilter { min_dbm: i16::MIN, include_unknown: true };
+    let (infos, peris) = collect_devices(&adapter, everything, &mut RssiSmoother::new(1.0)).await?;
+
+    let mut rows = Vec::new();
+    for (info, peri) in infos.into_iter().zip(peris) {
+        if !info.controllable {
+            continue;
+        }
+        eprintln!("Probing {}...", info.addr);
+
+        let mut row = Row {
+            addr: info.addr.clone(),
+            name: info.name.clone(),
+            rssi: info.rssi,
+            ..Default::default()
+        };
+        match tokio::time::timeout(PER_DEVICE_TIMEOUT, probe(&peri, &mut row)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => row.error = Some(format!("{e:#}")),
+            Err(_) => row.error = Some("timed out".into()),
+        }
+        // Always leave the board free for the next client.
+        peri.disconnect().await.ok();
+
+        rows.push(row);
+    }
+
+    eprintln!("{} board(s) found.", rows.len());
+    Ok(rows)
+}
+
+/// Connect and fill in whatever the board exposes. Missing characteristics
+/// are left as `None`; only connection/discovery failures are errors.
+async fn probe(peri: &Peripheral, row: &mut Row) -> Result<()> {
+    let battery_uuid = uuid_from_u16(0x2A19);
+    let led_uuids = Settings::load().led_char_uuids;
+    let diag_uuid = Uuid::parse_str(DIAG_CHAR_UUID).unwrap();
+
+    peri.connect().await.context("connect")?;
+    peri.discover_services().await.context("discover_services")?;
+
+    let chars = peri.characteristics();
+    let led_uuid = led_uuids.iter().find(|u| chars.iter().any(|c| c.uuid == **u)).copied();
+
+    for ch in chars {
+        if ch.uuid != battery_uuid && Some(ch.uuid) != led_uuid && ch.uuid != diag_uuid {
+            continue;
+        }
+        let Ok(bytes) = peri.read(&ch).await else { continue };
+
+        if ch.uuid == battery_uuid {
+            row.battery = bytes.first().copied();
+        } else if Some(ch.uuid) == led_uuid {
+            row.mask = mask_from_bytes(&bytes);
+        } else if bytes.len() >= 8 {
+            row.uptime_secs = Some(u32::from_le_bytes(bytes[0..4].try_into().unwrap()));
+            row.reset_reason = Some(u32::from_le_bytes(bytes[4..8].try_into().unwrap()));
+        }
+    }
+    Ok(())
+}
+
+fn print_csv(rows: &[Row]) {
+    println!("addr,name,rssi,battery,mask,uptime_secs,reset_reason,error");
+    for r in rows {
+        let fields = [
+            csv_field(&r.addr),
+            csv_field(r.name.as_deref().unwrap_or("")),
+            opt(r.rssi),
+            opt(r.battery),
+            r.mask.map(|m| format!("0x{m:04x}")).unwrap_or_default(),
+            opt(r.uptime_secs),
+            r.reset_reason.map(|v| format!("0x{v:08x}")).unwrap_or_default(),
+            csv_field(r.error.as_deref().unwrap_or("")),
+        ];
+        println!("{}", fields.join(","));
+    }
+}
+
+fn print_json(rows: &[Row]) {
+    let items: Vec<String> = rows
+        .iter()
+        .map(|r| {
+            format!(
+                "  {{\"addr\": {}, \"name\": {}, \"rssi\": {}, \"battery\": {}, \"mask\": {}, \
+                 \"uptime_secs\": {}, \"reset_reason\": {}, \"error\": {}}}",
+                json_str(&r.addr),
+                r.name.as_deref().map(json_str).unwrap_or_else(|| "null".into()),
+                json_opt(r.rssi),
+                json_opt(r.battery),
+                json_opt(r.mask),
+                json_opt(r.uptime_secs),
+                json_opt(r.reset_reason),
+                r.error.as_deref().map(json_str).unwrap_or_else(|| "null".into()),
+            )
+        })
+        .collect();
+    println!("[\n{}\n]", items.join(",\n"));
+}
+
+pub(crate) fn opt<T: ToString>(v: Option<T>) -> String {
+    v.map(|v| v.to_string()).unwrap_or_default()
+}
+
+pub(crate) fn json_opt<T: ToString>(v: Option<T>) -> String {
+    v.map(|v| v.to_string()).unwrap_or_else(|| "null".into())
+}
+
+pub(crate) fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+pub(crate) fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -1,63 +1,884 @@
-use anyhow::{anyhow, Context, Result};
+mod conn_params;
+mod echo_guard;
+mod features;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+#[cfg(feature = "influx")]
+mod influx;
+mod inventory;
+mod playlist;
+mod preferences;
+mod protocol;
+mod pulse;
+mod quality;
+mod scan_export;
+mod session;
+mod settings;
+mod settings_export;
+mod soak;
+#[cfg(feature = "tcp")]
+mod tcp;
+
+use anyhow::{anyhow, bail, Context, Result};
 use btleplug::api::{
-    Central, CharPropFlags, Manager as _, Peripheral as _, ScanFilter, WriteType,
+    bleuuid::uuid_from_u16, Central, CentralEvent, CharPropFlags, Characteristic, Manager as _, Peripheral as _,
+    PeripheralProperties, ScanFilter, WriteType,
 };
-use btleplug::platform::{Adapter, Manager, Peripheral};
+use btleplug::platform::{Adapter, Manager, Peripheral, PeripheralId};
+use futures::StreamExt;
 use gtk::prelude::*;
 use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc;
-use std::time::Duration;
-use tokio::sync::mpsc as tokio_mpsc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc as tokio_mpsc, oneshot};
 use uuid::Uuid;
 
-// LED characteristic UUID (from firmware)
+use echo_guard::EchoGuard;
+use settings::{Settings, Streams};
+
+// LED service / characteristic UUIDs (from firmware)
+const LED_SERVICE_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a62cf38";
 const LED_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a63cf38";
+const DIAG_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a64cf38";
+const CONNECTIONS_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a73cf38";
+const LED_COUNT_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a65cf38";
+const PRESENT_LEDS_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a7ccf38";
+const TEST_MODE_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a67cf38";
+const CONTROL_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a68cf38";
+const PROTOCOL_VERSION_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a69cf38";
+const HEARTBEAT_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a6bcf38";
+/// Blink period per LED in ms (four u16 LE, LED1 first); 0 = solid.
+const BLINK_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a6dcf38";
+const FEATURES_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a6ecf38";
+const BREATHE_MASK_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a70cf38";
+const BREATHE_PERIOD_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a71cf38";
+const SELF_TEST_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a72cf38";
+const LOCKED_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a74cf38";
+const LAST_ERROR_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a75cf38";
+const MEMORY_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a79cf38";
+const STAGING_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a7acf38";
+const APPLY_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a7bcf38";
+const PLAYLIST_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a7dcf38";
+const AUTOPLAY_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a7ecf38";
+const CONN_PARAMS_CHAR_UUID: &str = "9e7312e0-2354-11eb-9f10-fbc30a7fcf38";
+/// Nordic UART Service TX: firmware log lines, '\n'-terminated, chunked.
+const NUS_TX_CHAR_UUID: &str = "6e400003-b5a3-f393-e0a9-e50e24dcca9e";
+
+/// LED count assumed for firmware that doesn't report one (the DK has four).
+const DEFAULT_LED_COUNT: u8 = 4;
+/// The mask is at most two bytes, so that's as many LEDs as we can address.
+const MAX_LED_COUNT: u8 = 16;
+/// Blink periods are set for the DK's four LEDs; longest offered in the UI.
+const BLINK_LED_COUNT: usize = 4;
+const BLINK_MAX_MS: u16 = 2000;
+/// Breathing period range offered in the UI; the firmware raises anything
+/// below its own minimum (500 ms).
+const BREATHE_MIN_MS: u16 = 500;
+const BREATHE_MAX_MS: u16 = 10_000;
+
+/// How often live scan pushes a fresh device list to the UI.
+const LIVE_SCAN_REFRESH: Duration = Duration::from_secs(2);
+/// How often the connected board's battery/RSSI are sampled.
+const TELEMETRY_INTERVAL: Duration = Duration::from_secs(5);
+/// Log the raw bytes of every read, write and notification ("Verbose (hex)").
+/// Set from the UI thread, read by the worker and its notification tasks.
+static WIRE_TRACE: AtomicBool = AtomicBool::new(false);
+
+/// How often mask-write throughput is reported while connected.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+/// The firmware notifies a heartbeat this often; the watchdog checks at the
+/// same rate.
+const HEARTBEAT_PERIOD: Duration = Duration::from_secs(1);
+/// How often the adapter is checked for removal, or looked for while absent.
+const ADAPTER_CHECK: Duration = Duration::from_secs(3);
+/// Extra tries for a peripheral's properties before listing it unresolved;
+/// BlueZ sometimes hasn't populated them right after discovery.
+const PROPERTIES_RETRIES: u32 = 2;
+const PROPERTIES_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// A mask write this soon after the previous one counts as part of a burst
+/// (gamepad, rapid clicking) and skips the ACK when the board allows it.
+const BURST_WINDOW: Duration = Duration::from_millis(100);
+/// Verified mode: write-and-read-back rounds before a mask write is
+/// reported as not applied.
+const VERIFIED_MAX_TRIES: u32 = 3;
+/// Output self-test: the board needs ~40 ms; poll its result this often,
+/// this many times.
+const SELF_TEST_POLL: Duration = Duration::from_millis(100);
+const SELF_TEST_POLLS: u32 = 10;
+/// How long a one-off scan listens; quick connect gets by with less.
+const SCAN_TIME: Duration = Duration::from_secs(5);
+const QUICK_CONNECT_SCAN_TIME: Duration = Duration::from_secs(3);
+/// How long after a connection parameter request the result is read back;
+/// the board refreshes `conn_params` once a second.
+const CONN_PARAMS_SETTLE: Duration = Duration::from_secs(2);
+/// How long a connect scans for a board that isn't in the last scan list.
+const LOOKUP_SCAN_TIME: Duration = Duration::from_secs(3);
+/// Service discovery tries per connect (see `discover_with_retry`), and the
+/// pause between them.
+const DISCOVERY_ATTEMPTS: u32 = 3;
+const DISCOVERY_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// Pause after powering the adapter on before BlueZ accepts discovery.
+const ADAPTER_POWER_ON_SETTLE: Duration = Duration::from_millis(500);
+/// How long a device stays listed (as cached) after scans stop seeing it.
+const SCAN_CACHE_TTL: Duration = Duration::from_secs(300);
+/// How long the background scan runs when an adapter comes back.
+const CACHE_REFRESH_TIME: Duration = Duration::from_secs(5);
+/// "Benchmark discovery": scans per run, how long each waits for the
+/// target, and the pause between them so the adapter really restarts.
+const DISCOVERY_BENCH_RUNS: u32 = 5;
+const DISCOVERY_BENCH_TIMEOUT: Duration = Duration::from_secs(10);
+const DISCOVERY_BENCH_GAP: Duration = Duration::from_millis(500);
+/// How long a newly discovered device stays highlighted.
+const NEW_DEVICE_HIGHLIGHT: Duration = Duration::from_secs(5);
+/// How long the LED preview is outlined after a mask notification.
+const NOTIFY_FLASH: Duration = Duration::from_millis(150);
+/// How long a toast stays up before fading out.
+const TOAST_TIME: Duration = Duration::from_secs(3);
 
 #[derive(Debug, Clone)]
 struct DeviceInfo {
     addr: String,
     name: Option<String>,
+    /// Smoothed (see `RssiSmoother`); used for filtering and sorting.
+    rssi: Option<i16>,
+    /// Latest reading as reported by the adapter.
+    rssi_raw: Option<i16>,
+    /// Advertises the firmware's LED service (so we can control it).
+    controllable: bool,
+    /// Service UUIDs from the advertisement / scan response.
+    services: Vec<Uuid>,
+    /// The stack had no properties for it yet; live scan fills them in on a
+    /// later refresh.
+    resolving: bool,
+    /// Not seen by the latest scan; listed from `ScanCache` until it expires.
+    cached: bool,
+}
+
+/// Which scanned devices make it into the list, by signal strength.
+#[derive(Debug, Clone, Copy)]
+struct RssiFilter {
+    min_dbm: i16,
+    include_unknown: bool,
+}
+
+impl RssiFilter {
+    fn from_settings(s: &Settings) -> Self {
+        Self { min_dbm: s.min_rssi, include_unknown: s.include_unknown_rssi }
+    }
+
+    fn accepts(&self, rssi: Option<i16>) -> bool {
+        match rssi {
+            Some(v) => v >= self.min_dbm,
+            None => self.include_unknown,
+        }
+    }
+}
+
+/// Which scanned devices make it into the list, by address or name
+/// (`device_allow` / `device_deny` in the settings). Deny wins; a non-empty
+/// allow list admits only the devices on it.
+#[derive(Debug, Clone)]
+struct DeviceFilter {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl DeviceFilter {
+    fn from_settings(s: &Settings) -> Self {
+        Self { allow: s.device_allow.clone(), deny: s.device_deny.clone() }
+    }
+
+    fn accepts(&self, d: &DeviceInfo) -> bool {
+        let hit = |pattern: &String| {
+            pattern.eq_ignore_ascii_case(&d.addr) || d.name.as_deref().is_some_and(|n| glob_match(pattern, n))
+        };
+        !self.deny.iter().any(hit) && (self.allow.is_empty() || self.allow.iter().any(hit))
+    }
+}
+
+/// `*` matches any run of characters, everything else itself.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((head, rest)) => {
+            let Some(tail) = text.strip_prefix(head) else { return false };
+            (0..=tail.len()).filter(|&i| tail.is_char_boundary(i)).any(|i| glob_match(rest, &tail[i..]))
+        }
+    }
+}
+
+/// Exponential moving average of RSSI per device, so the list order and the
+/// telemetry readout don't jump around with every advertisement.
+#[derive(Debug)]
+struct RssiSmoother {
+    /// Weight of the newest reading; 1.0 passes readings through unchanged.
+    alpha: f32,
+    by_addr: HashMap<String, f32>,
+}
+
+impl RssiSmoother {
+    fn new(alpha: f32) -> Self {
+        Self { alpha: alpha.clamp(0.05, 1.0), by_addr: HashMap::new() }
+    }
+
+    /// Fold `raw` into the average for `addr` and return the new average.
+    fn update(&mut self, addr: &str, raw: i16) -> i16 {
+        let alpha = self.alpha;
+        let avg = self
+            .by_addr
+            .entry(addr.to_string())
+            .and_modify(|avg| *avg += alpha * (raw as f32 - *avg))
+            .or_insert(raw as f32);
+        avg.round() as i16
+    }
+}
+
+/// Periodic readings from the connected board.
+#[derive(Debug, Clone)]
+struct Telemetry {
+    addr: String,
+    /// false when the firmware was built without the Battery Service.
+    has_battery: bool,
+    battery: Option<u8>,
+    /// Smoothed, like `DeviceInfo::rssi`.
     rssi: Option<i16>,
+    rssi_raw: Option<i16>,
+    /// Firmware stack size and high-water mark in bytes, if it reports them.
+    stack: Option<(u32, u32)>,
+}
+
+/// Auto-reconnect backoff after an unexpected disconnect.
+#[derive(Debug, Clone, Copy)]
+struct ReconnectPolicy {
+    base: Duration,
+    max: Duration,
+    /// 0 disables auto-reconnect.
+    max_attempts: u32,
+    /// Fraction (0.2 = ±20%) by which each delay is randomly varied. Links
+    /// that drop together (adapter reset, board power cycle) would otherwise
+    /// retry at exactly the same moments and keep colliding.
+    jitter: f64,
+}
+
+impl ReconnectPolicy {
+    fn from_settings(s: &Settings) -> Self {
+        Self {
+            base: Duration::from_millis(s.reconnect_base_ms),
+            max: Duration::from_millis(s.reconnect_max_ms),
+            max_attempts: s.reconnect_max_attempts,
+            jitter: s.reconnect_jitter_pct as f64 / 100.0,
+        }
+    }
+
+    /// Delay before attempt `n` (1-based): base * 2^(n-1), capped at `max`,
+    /// then jittered.
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        let delay = self.base.saturating_mul(factor).min(self.max);
+        delay.mul_f64((1.0 + self.jitter * random_unit()).max(0.0))
+    }
+}
+
+/// Roughly uniform in [-1, 1]. `RandomState` is randomly seeded, which is
+/// plenty for spreading retries and saves a dependency on `rand`.
+fn random_unit() -> f64 {
+    use std::hash::BuildHasher;
+    let bits = std::collections::hash_map::RandomState::new().hash_one(Instant::now());
+    (bits as f64 / u64::MAX as f64) * 2.0 - 1.0
 }
 
 #[derive(Debug)]
 enum Cmd {
-    Scan,
+    /// `cmd` from a remote client (see `tcp`); its outcome, `Ok(detail)` or
+    /// `Err(reason)`, goes back through the sender.
+    WithReply(Box<Cmd>, oneshot::Sender<Result<String, String>>),
+    Scan(RssiFilter),
+    /// Scan briefly and connect to the one LED board found; with none or
+    /// several, leave the choice to the list (strongest selected).
+    QuickConnect(RssiFilter),
+    /// Time `DISCOVERY_BENCH_RUNS` fresh scans until the board with `addr`
+    /// (or, failing that, `name`) is first heard.
+    BenchmarkDiscovery { addr: String, name: Option<String> },
+    /// Connect/write/read/disconnect `addr` `cycles` times (see `soak`).
+    Soak { addr: String, cycles: u32 },
+    StopSoak,
+    /// Start (Some) or stop (None) continuous background scanning.
+    LiveScan(Option<RssiFilter>),
     Connect { addr: String },
+    /// Abort a connect (or reconnect attempt) that is still in progress.
+    CancelConnect,
     Disconnect,
-    SetMask(u8),
+    SetMask(u16),
+    /// Ask the board to echo mask writes without touching the LEDs.
+    SetTestMode(bool),
+    /// Freeze (or release) the board's LEDs against mask writes.
+    SetLocked(bool),
+    /// Have the board hold mask, blink and breathe writes (true), or drop
+    /// the held ones (false).
+    SetStaging(bool),
+    /// Show everything held since `SetStaging(true)` at once.
+    ApplyStaged,
+    /// Replace the board's autoplay steps (it keeps them in flash).
+    SetPlaylist(Vec<playlist::Step>),
+    /// Start or stop the board stepping through its playlist.
+    SetAutoplay(bool),
+    /// Have the board ask the central for these connection parameters.
+    SetConnParams(conn_params::ConnParams),
+    /// Reset the board (needs the versioned control protocol).
+    Reboot,
+    /// Run the board's LED output self-test and report the result.
+    SelfTest,
+    /// Switch LED `idx` (0-based), leaving the others as the board reports them.
+    SetLed { idx: u8, on: bool },
+    /// Read the mask back after each `SetMask` and compare.
+    SetVerifyWrites(bool),
+    /// Repeat each `SetMask` until the board reads back the same mask, up
+    /// to `VERIFIED_MAX_TRIES` times.
+    SetVerifiedMode(bool),
+    /// Watch the board without writing to it: commands that would (see
+    /// `Cmd::writes`) are refused, and LED mask notifications are on.
+    SetMonitorOnly(bool),
+    /// Blink period per LED in ms, 0 = solid.
+    SetBlinkPeriods([u16; BLINK_LED_COUNT]),
+    /// LEDs that fade in and out (still ANDed with the mask), and the
+    /// period of one breath in ms.
+    SetBreathe { mask: u16, period_ms: u16 },
+    ReadAll,
+    SetReconnectPolicy(ReconnectPolicy),
+    /// New `RssiSmoother` weight; restarts the averages.
+    SetRssiSmoothing(f32),
+    /// Live scan with duplicate advertisements reported (see `restart_scan_for_duplicates`).
+    SetScanDuplicates(bool),
+    /// Allow/deny list for scan results from now on.
+    SetDeviceFilter(DeviceFilter),
+    /// LED characteristic UUIDs to try on the next connect.
+    SetLedCharUuids(Vec<Uuid>),
+    /// Missed heartbeats before the watchdog reconnects (0 = off).
+    SetHeartbeatLimit(u32),
+    /// Notification streams to subscribe to, now and on later connects.
+    SetNotifyStreams(Streams),
+    SetQualityWeights(quality::Weights),
+    /// Start noting user commands (see `session`).
+    StartRecording,
+    /// Stop recording and save to the path, or discard with `None`.
+    StopRecording(Option<PathBuf>),
+    /// Replay a recording with its original timing.
+    Replay(PathBuf),
+    StopReplay,
+}
+
+impl Cmd {
+    /// Whether running this writes to the board (subscribing doesn't count).
+    fn writes(&self) -> bool {
+        matches!(
+            self,
+            Cmd::Soak { .. }
+                | Cmd::SetMask(_)
+                | Cmd::SetLed { .. }
+                | Cmd::SetTestMode(_)
+                | Cmd::SetLocked(_)
+                | Cmd::SetStaging(_)
+                | Cmd::ApplyStaged
+                | Cmd::SetPlaylist(_)
+                | Cmd::SetAutoplay(_)
+                | Cmd::SetConnParams(_)
+                | Cmd::Reboot
+                | Cmd::SelfTest
+                | Cmd::SetBlinkPeriods(_)
+                | Cmd::SetBreathe { .. }
+        )
+    }
+}
+
+/// Streams to subscribe to: `streams`, plus LED mask changes while only
+/// monitoring, since watching them is the point.
+fn effective_streams(streams: Streams, monitor_only: bool) -> Streams {
+    Streams { led: streams.led || monitor_only, ..streams }
 }
 
 #[derive(Debug)]
 enum UiMsg {
     Log(String),
+    /// A failure the firmware reported; logged in red.
+    FirmwareError(String),
+    /// Routine confirmation (write done, scan finished), shown briefly over
+    /// the window instead of being logged.
+    Toast(String),
     ScanResults(Vec<DeviceInfo>),
     Connected(bool),
+    /// The adapter reported `addr` connecting or disconnecting, whoever
+    /// initiated it. Our own link is still reported via `Connected(..)`.
+    ConnState { addr: String, connected: bool },
+    /// A connect attempt to `addr` started; ends with `Connected(..)`.
+    Connecting { addr: String },
+    /// Current LED mask as reported by the board; `notified` when it came
+    /// from a notification rather than a read.
+    MaskState { mask: u16, notified: bool },
+    /// Whether we're subscribed to LED mask notifications.
+    Subscribed(bool),
+    /// Number of LEDs the connected board reports.
+    LedCount(u8),
+    /// Which of them are fitted (bit n => LED n+1); all of them on boards
+    /// that don't say.
+    PresentLeds(u16),
+    /// Connected, but the LED characteristic wasn't among those discovered.
+    LedCharNotFound { addr: String },
+    /// Auto-reconnect is about to make attempt `attempt` of `max`.
+    Reconnecting { attempt: u32, max: u32 },
+    /// Auto-reconnect ran out of attempts.
+    ReconnectFailed,
+    Telemetry(Telemetry),
+    /// Mask writes since connecting (`writes_unacked` of the successful ones
+    /// sent without response), and successful ones per second over the last
+    /// `STATS_INTERVAL`.
+    Stats { writes_ok: u64, writes_unacked: u64, writes_err: u64, per_sec: f64 },
+    /// Heartbeats missed in a row; `None` if the board doesn't send them.
+    Heartbeat(Option<u32>),
+    /// Link quality score, 0..=100 (see `quality`).
+    LinkQuality(u8),
+    /// A Bluetooth adapter appeared (true) or went away (false).
+    AdapterPresent(bool),
+    /// A one-off scan (`Cmd::Scan`) started or ended, however it ended.
+    Scanning(bool),
+    /// Select `addr` in the device list (quick connect found several boards).
+    SelectDevice(String),
+    /// A session recording started (true) or ended (false).
+    Recording(bool),
+    /// A replay started (true) or finished or was stopped (false).
+    Replaying(bool),
+    /// A soak test started (true) or ended (false).
+    Soaking(bool),
+    /// Mask read back after a verified write; a mismatch if `read` isn't
+    /// `Some(sent)` (the firmware clamped or ignored the write). `tries` is
+    /// more than 1 only in verified mode.
+    WriteVerified { sent: u16, read: Option<u16>, tries: u32 },
+    /// The latest mask write went out differently from the one before.
+    WriteMode(WriteMode),
+    /// Capabilities read on connect; `None` if the board doesn't report them.
+    Features(Option<features::Features>),
+    /// Blink periods read on connect; `None` if the board can't blink LEDs.
+    BlinkPeriods(Option<[u16; BLINK_LED_COUNT]>),
+    /// Breathing mask and period read on connect; `None` if the board can't
+    /// breathe LEDs.
+    Breathe(Option<(u16, u16)>),
+    /// Lock state read on connect; `None` if the board can't be locked.
+    Locked(Option<bool>),
+    /// Whether the board is holding writes for `apply`; `None` if it can't.
+    Staging(Option<bool>),
+    /// Autoplay steps and whether the board is playing them, read on
+    /// connect; `None` if it can't autoplay.
+    Playlist(Option<(Vec<playlist::Step>, bool)>),
+    /// Autoplay state after a failed write.
+    Autoplay(bool),
+    /// Connection parameters as the board reports them; `None` on connect if
+    /// it doesn't.
+    ConnParams(Option<conn_params::ConnParams>),
+    /// Output self-test result: LEDs tested and LEDs that passed.
+    SelfTest { tested: u16, passed: u16 },
+}
+
+type EventStream = std::pin::Pin<Box<dyn futures::Stream<Item = CentralEvent> + Send>>;
+
+/// An open connection to a board, owned by the BLE worker.
+struct Link {
+    peri: Peripheral,
+    addr: String,
+    led: Characteristic,
+    /// Battery Level (0x2A19), if the board has one.
+    battery: Option<Characteristic>,
+    /// Latest notified battery level, while subscribed (see `Streams`).
+    battery_level: Arc<Mutex<Option<u8>>>,
+    battery_task: Option<tokio::task::JoinHandle<()>>,
+    /// Test-mode switch; older firmware doesn't have it.
+    test_mode: Option<Characteristic>,
+    /// Kiosk lock; older firmware doesn't have it.
+    locked: Option<Characteristic>,
+    /// `staging` and `apply`, if the firmware can hold writes.
+    staging: Option<(Characteristic, Characteristic)>,
+    /// `playlist` and `autoplay`, if the firmware can play masks by itself.
+    autoplay: Option<(Characteristic, Characteristic)>,
+    /// Per-LED blink periods, if the firmware has them.
+    blink: Option<Characteristic>,
+    /// Breathing mask and period, if the firmware has them.
+    breathe: Option<(Characteristic, Characteristic)>,
+    /// Output self-test result, if the firmware has the test.
+    self_test: Option<Characteristic>,
+    /// `control` characteristic and the negotiated frame version, if the
+    /// board supports the versioned protocol.
+    control: Option<(Characteristic, u8)>,
+    /// Forwards LED mask notifications to the UI; aborted on disconnect.
+    notify_task: Option<tokio::task::JoinHandle<()>>,
+    /// Logs rejected `control` requests; aborted on disconnect.
+    control_task: Option<tokio::task::JoinHandle<()>>,
+    /// Heartbeat characteristic, and the watchdog state while subscribed.
+    heartbeat_ch: Option<Characteristic>,
+    heartbeat: Option<Heartbeat>,
+    /// Firmware log (NUS TX) and its forwarder to the log view.
+    fw_log: Option<Characteristic>,
+    fw_log_task: Option<tokio::task::JoinHandle<()>>,
+    /// `last_error`, and the task logging the failures it reports.
+    last_error: Option<Characteristic>,
+    last_error_task: Option<tokio::task::JoinHandle<()>>,
+    /// Stack use, read with the rest of the telemetry.
+    memory: Option<Characteristic>,
+    /// Connection parameters, read with the telemetry and writable.
+    conn_params: Option<Characteristic>,
+    /// Latest smoothed RSSI, for the link quality score.
+    rssi: Option<i16>,
+}
+
+/// Counts heartbeat notifications so the worker can spot a board that is
+/// still connected but no longer running its firmware loop.
+struct Heartbeat {
+    /// Bumped by `task` on every notification.
+    beats: Arc<AtomicU32>,
+    /// `beats` at the previous check.
+    seen: u32,
+    /// Checks in a row without a new beat.
+    missed: u32,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Heartbeat {
+    /// Called every `HEARTBEAT_PERIOD`; returns the missed count.
+    fn check(&mut self) -> u32 {
+        let beats = self.beats.load(Ordering::Relaxed);
+        if beats == self.seen {
+            self.missed += 1;
+        } else {
+            self.seen = beats;
+            self.missed = 0;
+        }
+        self.missed
+    }
+}
+
+/// Callbacks behind the per-row buttons in the device list.
+#[derive(Clone)]
+struct RowActions {
+    toggle_favorite: Rc<dyn Fn(&str)>,
+    connect: Rc<dyn Fn(&str)>,
+    /// Right-click "Hide this device": onto the deny list.
+    hide: Rc<dyn Fn(&str)>,
+    /// Tiny LED preview for the connected board's row.
+    mask_preview: Rc<dyn Fn() -> gtk::DrawingArea>,
+}
+
+/// Devices seen per adapter (keyed by `adapter_info`), so a returning
+/// adapter or a failed scan can list them at once instead of starting from
+/// an empty list.
+#[derive(Default)]
+struct ScanCache {
+    /// Each device with when a scan last saw it.
+    by_adapter: HashMap<String, Vec<(DeviceInfo, Instant)>>,
+}
+
+impl ScanCache {
+    /// Note a fresh scan; returns it followed by the cached devices it
+    /// didn't see.
+    fn merge(&mut self, adapter: &str, fresh: &[DeviceInfo]) -> Vec<DeviceInfo> {
+        let now = Instant::now();
+        let entries = self.by_adapter.entry(adapter.to_string()).or_default();
+        entries.retain(|(d, seen)| now - *seen < SCAN_CACHE_TTL && !fresh.iter().any(|f| f.addr == d.addr));
+        let mut list = fresh.to_vec();
+        list.extend(entries.iter().map(|(d, _)| DeviceInfo { cached: true, ..d.clone() }));
+        entries.extend(fresh.iter().map(|d| (d.clone(), now)));
+        list
+    }
+
+    /// Everything still cached for `adapter`.
+    fn cached(&mut self, adapter: &str) -> Vec<DeviceInfo> {
+        let now = Instant::now();
+        let Some(entries) = self.by_adapter.get_mut(adapter) else { return Vec::new() };
+        entries.retain(|(_, seen)| now - *seen < SCAN_CACHE_TTL);
+        entries.iter().map(|(d, _)| DeviceInfo { cached: true, ..d.clone() }).collect()
+    }
+}
+
+/// Outcome of the command being handled, for a `Cmd::WithReply` sender.
+/// Sent on drop, so every way out of the handler (`continue` included)
+/// answers; unless a handler says otherwise that's a generic failure.
+struct Reply {
+    tx: Option<oneshot::Sender<Result<String, String>>>,
+    outcome: Result<String, String>,
+}
+
+impl Reply {
+    fn new(tx: Option<oneshot::Sender<Result<String, String>>>) -> Self {
+        Self { tx, outcome: Err("failed; see the GUI log".into()) }
+    }
+
+    fn ok(&mut self, detail: impl Into<String>) {
+        self.outcome = Ok(detail.into());
+    }
+
+    fn fail(&mut self, reason: impl Into<String>) {
+        self.outcome = Err(reason.into());
+    }
+}
+
+impl Drop for Reply {
+    fn drop(&mut self) {
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send(std::mem::replace(&mut self.outcome, Ok(String::new())));
+        }
+    }
+}
+
+/// Mask write counters for the current connection.
+#[derive(Debug, Default)]
+struct WriteStats {
+    ok: u64,
+    /// Of `ok`, sent as burst writes without response.
+    unacked: u64,
+    err: u64,
+    /// When the previous mask write went out, to spot bursts.
+    last_at: Option<Instant>,
+    /// `ok` and `err` at the previous report, for the rate and link quality.
+    ok_at_last_report: u64,
+    err_at_last_report: u64,
+}
+
+/// How mask writes are going out, shown on the LED frame so an unconfirmed
+/// write isn't mistaken for one the board acknowledged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WriteMode {
+    /// With response (or through `control`): the board ACKed the write.
+    Acked,
+    /// Burst writes without response: sent, but nothing came back.
+    Unacked,
+    /// Verified mode: each write is read back.
+    Verified,
+}
+
+impl WriteMode {
+    fn frame_label(self) -> &'static str {
+        match self {
+            WriteMode::Acked => "LEDs \u{2014} writes acknowledged",
+            WriteMode::Unacked => "LEDs \u{2014} writes unconfirmed (burst)",
+            WriteMode::Verified => "LEDs \u{2014} writes verified by read-back",
+        }
+    }
+}
+
+/// How a cancellable connect attempt ended. The result is boxed: a `Link`
+/// is large and `Cancelled` carries nothing.
+enum ConnectOutcome {
+    Finished(Box<Result<Option<Link>>>),
+    Cancelled,
+}
+
+/// Pending automatic reconnect after the link dropped unexpectedly.
+struct Reconnect {
+    peri: Peripheral,
+    addr: String,
+    attempt: u32,
+    next_at: tokio::time::Instant,
+}
+
+impl Link {
+    /// Write `m`: without response for a burst, otherwise through `control`
+    /// if the board has it, else straight to the LED characteristic.
+    async fn write_mask(&self, m: u16, burst: bool, ui_tx: &mpsc::Sender<UiMsg>) -> btleplug::Result<()> {
+        if burst {
+            return write_traced_as(&self.peri, &self.led, &mask_bytes(m), WriteType::WithoutResponse, ui_tx).await;
+        }
+        match self.send_control(protocol::Request::SetMask(m), ui_tx).await {
+            Some(res) => res,
+            None => write_traced(&self.peri, &self.led, &mask_bytes(m), ui_tx).await,
+        }
+    }
+
+    /// The mask the board reports; `None` (logged) if the read fails.
+    async fn read_mask(&self, ui_tx: &mpsc::Sender<UiMsg>) -> Option<u16> {
+        match read_traced(&self.peri, &self.led, ui_tx).await {
+            Ok(bytes) => mask_from_bytes(&bytes),
+            Err(e) => {
+                let _ = ui_tx.send(UiMsg::Log(format!("Verify read failed: {e:?}")));
+                None
+            }
+        }
+    }
+
+    /// Subscribe to the enabled streams the board has and unsubscribe from
+    /// the rest. Called on connect and whenever the preference changes.
+    async fn set_streams(&mut self, streams: Streams, ui_tx: &mpsc::Sender<UiMsg>) {
+        let peri = &self.peri;
+        let failed = |what: &str, e: anyhow::Error| {
+            let _ = ui_tx.send(UiMsg::Log(format!("{what} subscribe failed: {e:#}")));
+        };
+
+        let led = Some(&self.led).filter(|c| c.properties.contains(CharPropFlags::NOTIFY));
+        if let Some(ch) = led {
+            match (streams.led, self.notify_task.take()) {
+                (true, None) => match subscribe_mask_notifications(peri, ch, ui_tx.clone()).await {
+                    Ok(task) => {
+                        self.notify_task = Some(task);
+                        let _ = ui_tx.send(UiMsg::Subscribed(true));
+                    }
+                    Err(e) => failed("LED", e),
+                },
+                (false, Some(task)) => {
+                    stop_stream(peri, ch, task).await;
+                    let _ = ui_tx.send(UiMsg::Subscribed(false));
+                }
+                (_, task) => self.notify_task = task,
+            }
+        }
+
+        let battery = self.battery.as_ref().filter(|c| c.properties.contains(CharPropFlags::NOTIFY));
+        if let Some(ch) = battery {
+            match (streams.battery, self.battery_task.take()) {
+                (true, None) => match subscribe_battery(peri, ch, self.battery_level.clone(), ui_tx.clone()).await {
+                    Ok(task) => self.battery_task = Some(task),
+                    Err(e) => failed("Battery", e),
+                },
+                (false, Some(task)) => {
+                    stop_stream(peri, ch, task).await;
+                    // Back to polling.
+                    *self.battery_level.lock().unwrap() = None;
+                }
+                (_, task) => self.battery_task = task,
+            }
+        }
+
+        // Replies only make sense once a frame version was agreed on.
+        let control = self.control.as_ref().map(|(c, _)| c).filter(|c| c.properties.contains(CharPropFlags::NOTIFY));
+        if let Some(ch) = control {
+            match (streams.control, self.control_task.take()) {
+                (true, None) => match subscribe_control_replies(peri, ch, ui_tx.clone()).await {
+                    Ok(task) => self.control_task = Some(task),
+                    Err(e) => failed("Control", e),
+                },
+                (false, Some(task)) => stop_stream(peri, ch, task).await,
+                (_, task) => self.control_task = task,
+            }
+        }
+
+        if let Some(ch) = &self.heartbeat_ch {
+            match (streams.heartbeat, self.heartbeat.take()) {
+                (true, None) => {
+                    let beats = Arc::new(AtomicU32::new(0));
+                    match subscribe_heartbeat(peri, ch, beats.clone(), ui_tx.clone()).await {
+                        Ok(task) => self.heartbeat = Some(Heartbeat { beats, seen: 0, missed: 0, task }),
+                        Err(e) => failed("Heartbeat", e),
+                    }
+                }
+                (false, Some(h)) => stop_stream(peri, ch, h.task).await,
+                (_, h) => self.heartbeat = h,
+            }
+        }
+        let _ = ui_tx.send(UiMsg::Heartbeat(self.heartbeat.as_ref().map(|h| h.missed)));
+
+        if let Some(ch) = &self.fw_log {
+            match (streams.fw_log, self.fw_log_task.take()) {
+                (true, None) => match subscribe_fw_log(peri, ch, ui_tx.clone()).await {
+                    Ok(task) => self.fw_log_task = Some(task),
+                    Err(e) => failed("Firmware log", e),
+                },
+                (false, Some(task)) => stop_stream(peri, ch, task).await,
+                (_, task) => self.fw_log_task = task,
+            }
+        }
+
+        if let Some(ch) = &self.last_error {
+            match (streams.errors, self.last_error_task.take()) {
+                (true, None) => match subscribe_last_error(peri, ch, ui_tx.clone()).await {
+                    Ok(task) => self.last_error_task = Some(task),
+                    Err(e) => failed("Last error", e),
+                },
+                (false, Some(task)) => stop_stream(peri, ch, task).await,
+                (_, task) => self.last_error_task = task,
+            }
+        }
+    }
+
+    async fn close(self) {
+        let heartbeat_task = self.heartbeat.map(|h| h.task);
+        let tasks = [
+            self.notify_task,
+            self.battery_task,
+            self.control_task,
+            heartbeat_task,
+            self.fw_log_task,
+            self.last_error_task,
+        ];
+        for t in tasks.into_iter().flatten() {
+            t.abort();
+        }
+        self.peri.disconnect().await.ok();
+    }
+
+    /// Send `req` as a `control` frame; `None` if the board has no `control`
+    /// and the caller should fall back to the per-feature characteristic.
+    async fn send_control(&self, req: protocol::Request, ui_tx: &mpsc::Sender<UiMsg>) -> Option<btleplug::Result<()>> {
+        let (ch, version) = self.control.as_ref()?;
+        Some(write_traced(&self.peri, ch, &req.encode(*version), ui_tx).await)
+    }
 }
 
+/// Entry point of a headless subcommand, given the arguments after its name.
+type Subcommand = fn(&[String]) -> Result<()>;
+
 fn main() {
+    // Headless subcommands bypass GTK entirely.
+    let args: Vec<String> = std::env::args().collect();
+    let subcommand: Option<Subcommand> = match args.get(1).map(String::as_str) {
+        Some("inventory") => Some(inventory::run),
+        Some("pulse") => Some(pulse::run),
+        Some("soak") => Some(soak::run),
+        _ => None,
+    };
+    if let Some(run) = subcommand {
+        if let Err(e) = run(&args[2..]) {
+            eprintln!("{} failed: {e:#}", args[1]);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Our own flags; GTK would reject them as unknown options.
+    let auto_scan = args.iter().any(|a| a == "--auto-scan");
+    let gtk_args: Vec<String> = args.into_iter().filter(|a| a != "--auto-scan").collect();
+
     let app = gtk::Application::builder()
         .application_id("com.terence.nrf52840-led-gui")
         .build();
 
-    app.connect_activate(build_ui);
-    app.run();
+    app.connect_activate(move |app| build_ui(app, auto_scan));
+    app.run_with_args(&gtk_args);
 }
 
-fn build_ui(app: &gtk::Application) {
+fn build_ui(app: &gtk::Application, auto_scan: bool) {
     // GTK -> BLE worker command channel (tokio unbounded)
     let (cmd_tx, cmd_rx) = tokio_mpsc::unbounded_channel::<Cmd>();
 
     // BLE worker -> GTK messages (std channel; UI polls it)
     let (ui_tx, ui_rx) = mpsc::channel::<UiMsg>();
 
+    let settings = Rc::new(RefCell::new(Settings::load()));
+    let worker_settings = settings.borrow().clone();
+    // Scan once the worker reports an adapter, if asked to.
+    let auto_scan_pending = Rc::new(Cell::new(auto_scan || worker_settings.auto_scan));
+
+    #[cfg(feature = "gamepad")]
+    gamepad::spawn(&worker_settings, cmd_tx.clone());
+    #[cfg(feature = "tcp")]
+    tcp::spawn(&worker_settings, cmd_tx.clone());
+
     // Spawn BLE worker thread with tokio runtime
     std::thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().expect("tokio runtime");
+        let worker_ui_tx = ui_tx.clone();
         rt.block_on(async move {
-            if let Err(e) = ble_worker(cmd_rx, ui_tx).await {
+            if let Err(e) = ble_worker(cmd_rx, worker_ui_tx, worker_settings).await {
                 eprintln!("BLE worker error: {e:?}");
+                let _ = ui_tx.send(UiMsg::Log(format!("BLE worker stopped: {e:#}")));
             }
         });
     });
@@ -79,13 +900,97 @@ fn build_ui(app: &gtk::Application) {
     // Top controls row
     let top = gtk::Box::new(gtk::Orientation::Horizontal, 8);
 
+    // Scanning is a window action so the button, the menu entry and the
+    // shortcuts share one enabled state.
+    let scan_action = gtk::gio::SimpleAction::new("scan", None);
+    window.add_action(&scan_action);
+    app.set_accels_for_action("win.scan", &["<Control>r", "F5"]);
+    let device_menu = gtk::gio::Menu::new();
+    device_menu.append(Some("_Scan"), Some("win.scan"));
+    // Scans too, so enabled along with "scan".
+    let quick_connect_action = gtk::gio::SimpleAction::new("quick-connect", None);
+    window.add_action(&quick_connect_action);
+    device_menu.append(Some("_Quick Connect"), Some("win.quick-connect"));
+    // Shares the scan's enabled state: both need the adapter to themselves.
+    let bench_action = gtk::gio::SimpleAction::new("benchmark-discovery", None);
+    window.add_action(&bench_action);
+    device_menu.append(Some("_Benchmark Discovery"), Some("win.benchmark-discovery"));
+    let soak_action = gtk::gio::SimpleAction::new("soak-test", None);
+    window.add_action(&soak_action);
+    let stop_soak_action = gtk::gio::SimpleAction::new("stop-soak-test", None);
+    stop_soak_action.set_enabled(false);
+    window.add_action(&stop_soak_action);
+    device_menu.append(Some("Soak _Test"), Some("win.soak-test"));
+    device_menu.append(Some("Stop Soak Test"), Some("win.stop-soak-test"));
+    let export_settings_action = gtk::gio::SimpleAction::new("export-settings", None);
+    window.add_action(&export_settings_action);
+    let import_settings_action = gtk::gio::SimpleAction::new("import-settings", None);
+    window.add_action(&import_settings_action);
+    let file_menu = gtk::gio::Menu::new();
+    file_menu.append(Some("_Export Settings\u{2026}"), Some("win.export-settings"));
+    file_menu.append(Some("_Import Settings\u{2026}"), Some("win.import-settings"));
+    let menubar = gtk::gio::Menu::new();
+    menubar.append_submenu(Some("_File"), &file_menu);
+    menubar.append_submenu(Some("_Device"), &device_menu);
+    app.set_menubar(Some(&menubar));
+    window.set_show_menubar(true);
+
     let scan_btn = gtk::Button::with_label("Scan");
+    scan_btn.set_action_name(Some("win.scan"));
+    scan_btn.set_tooltip_text(Some("Scan for nearby boards (Ctrl+R or F5)"));
+    let quick_connect_btn = gtk::Button::with_label("Quick Connect");
+    quick_connect_btn.set_action_name(Some("win.quick-connect"));
+    quick_connect_btn.add_css_class("suggested-action");
+    quick_connect_btn.set_tooltip_text(Some("Scan briefly and connect to the LED board nearby, if there's just one"));
     let connect_btn = gtk::Button::with_label("Connect");
+    // Only shown while a connect is in flight.
+    let cancel_connect_btn = gtk::Button::with_label("Cancel");
+    cancel_connect_btn.set_visible(false);
     let disconnect_btn = gtk::Button::with_label("Disconnect");
+    // Connects to whatever we were last connected to, no scan needed.
+    let reconnect_btn = gtk::Button::with_label("Reconnect");
+    reconnect_btn.set_sensitive(false);
+    let read_all_btn = gtk::Button::with_label("Read All");
+    let export_btn = gtk::Button::with_label("Export scan\u{2026}");
+    let record_btn = gtk::ToggleButton::with_label("Record");
+    record_btn.set_tooltip_text(Some("Record scans, connects and LED writes for replay"));
+    let replay_btn = gtk::Button::with_label("Replay\u{2026}");
+    export_btn.set_tooltip_text(Some("Save the device list as CSV, or JSON with a .json name"));
+    let prefs_btn = gtk::Button::with_label("Preferences");
 
+    let live_scan_check = gtk::CheckButton::with_label("Live scan");
+    let verbose_check = gtk::CheckButton::with_label("Verbose (hex)");
+    verbose_check.set_tooltip_text(Some("Log the raw bytes of every read, write and notification"));
+    let verify_check = gtk::CheckButton::with_label("Verify writes");
+    verify_check.set_tooltip_text(Some("Read the mask back after every write and flag mismatches (slower)"));
+    let monitor_check = gtk::CheckButton::with_label("Monitor only");
+    monitor_check.set_tooltip_text(Some("Watch the board without writing to it, e.g. while another central drives it"));
+    let verified_check = gtk::CheckButton::with_label("Verified mode");
+    verified_check.set_tooltip_text(Some(&format!(
+        "Rewrite the whole mask until the board reads it back unchanged (up to {VERIFIED_MAX_TRIES} tries)"
+    )));
+
+    top.append(&quick_connect_btn);
     top.append(&scan_btn);
     top.append(&connect_btn);
+    top.append(&cancel_connect_btn);
     top.append(&disconnect_btn);
+    top.append(&reconnect_btn);
+    top.append(&read_all_btn);
+    top.append(&live_scan_check);
+    top.append(&verbose_check);
+    top.append(&verify_check);
+    top.append(&verified_check);
+    top.append(&monitor_check);
+    top.append(&export_btn);
+    top.append(&record_btn);
+    top.append(&replay_btn);
+    top.append(&prefs_btn);
+
+    let notify_label = gtk::Label::new(Some("Notifications: off"));
+    top.append(&notify_label);
+    let telemetry_label = gtk::Label::new(None);
+    top.append(&telemetry_label);
 
     // Devices list
     let devices_list = gtk::ListBox::new();
@@ -104,22 +1009,164 @@ fn build_ui(app: &gtk::Application) {
     led_grid.set_margin_bottom(8);
     led_grid.set_margin_start(8);
     led_grid.set_margin_end(8);
-    led_frame.set_child(Some(&led_grid));
 
-    let led1 = gtk::ToggleButton::with_label("LED1");
-    let led2 = gtk::ToggleButton::with_label("LED2");
-    let led3 = gtk::ToggleButton::with_label("LED3");
-    let led4 = gtk::ToggleButton::with_label("LED4");
+    // Board-reported state (from reads/notifications), as opposed to the
+    // toggles, which show what we've asked for.
+    let board_mask = Rc::new(Cell::new(0u16));
+    // LEDs fitted on the board; toggles for the others stay insensitive.
+    let present_leds = Rc::new(Cell::new(u16::MAX));
+    // Outline the preview until then, to show notifications are arriving.
+    let notify_flash_until: Rc<Cell<Option<Instant>>> = Rc::new(Cell::new(None));
+    let led_preview = gtk::DrawingArea::new();
+    led_preview.set_content_height(28);
+    led_preview.set_margin_start(8);
+    led_preview.set_margin_bottom(8);
+
+    let test_mode_check = gtk::CheckButton::with_label("Test mode (no physical change)");
+    test_mode_check.set_sensitive(false);
+    let lock_check = gtk::CheckButton::with_label("Lock LEDs");
+    lock_check.set_tooltip_text(Some("Board ignores everyone's mask writes and keeps its LEDs lit after disconnect"));
+    lock_check.set_sensitive(false);
+    let reboot_btn = gtk::Button::with_label("Reboot device");
+    reboot_btn.set_sensitive(false);
+    let self_test_btn = gtk::Button::with_label("Self-test");
+    self_test_btn.set_tooltip_text(Some("Drive each LED pin on and off and check it follows"));
+    self_test_btn.set_sensitive(false);
+    let self_test_label = gtk::Label::new(None);
+    let stage_check = gtk::CheckButton::with_label("Stage changes");
+    stage_check.set_tooltip_text(Some("Hold LED, blink and breathe changes on the board until Apply"));
+    stage_check.set_visible(false);
+    let apply_btn = gtk::Button::with_label("Apply");
+    apply_btn.set_tooltip_text(Some("Show all staged changes at once"));
+    apply_btn.set_visible(false);
+    apply_btn.set_sensitive(false);
+
+    let device_row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    device_row.set_margin_start(8);
+    device_row.set_margin_bottom(8);
+    device_row.append(&test_mode_check);
+    device_row.append(&lock_check);
+    device_row.append(&stage_check);
+    device_row.append(&apply_btn);
+    device_row.append(&reboot_btn);
+    device_row.append(&self_test_btn);
+    device_row.append(&self_test_label);
+
+    // Per-LED blink period; the board ANDs the blink with the mask.
+    let blink_grid = gtk::Grid::new();
+    blink_grid.set_column_spacing(8);
+    blink_grid.set_margin_start(8);
+    blink_grid.set_margin_end(8);
+    blink_grid.set_margin_bottom(8);
+    let mut blink_labels = Vec::new();
+    let mut blink_scales = Vec::new();
+    for i in 0..BLINK_LED_COUNT {
+        let label = gtk::Label::new(Some(&blink_caption(&settings.borrow().led_labels, i)));
+        label.set_xalign(0.0);
+        let scale = gtk::Scale::with_range(gtk::Orientation::Horizontal, 0.0, BLINK_MAX_MS as f64, 50.0);
+        scale.set_hexpand(true);
+        scale.set_value_pos(gtk::PositionType::Right);
+        scale.set_sensitive(false);
+        blink_grid.attach(&label, 0, i as i32, 1, 1);
+        blink_grid.attach(&scale, 1, i as i32, 1, 1);
+        blink_labels.push(label);
+        blink_scales.push(scale);
+    }
+    let blink_scales = Rc::new(blink_scales);
+
+    // Breathing: which LEDs fade in and out, and how fast.
+    let breathe_row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    breathe_row.set_margin_start(8);
+    breathe_row.set_margin_end(8);
+    breathe_row.set_margin_bottom(8);
+    breathe_row.set_sensitive(false);
+    breathe_row.append(&gtk::Label::new(Some("Breathe")));
+    let breathe_checks: Vec<gtk::CheckButton> = (0..BLINK_LED_COUNT)
+        .map(|i| gtk::CheckButton::with_label(&led_label(&settings.borrow().led_labels, i)))
+        .collect();
+    for check in &breathe_checks {
+        breathe_row.append(check);
+    }
+    let breathe_checks = Rc::new(breathe_checks);
+    let breathe_period = gtk::Scale::with_range(
+        gtk::Orientation::Horizontal,
+        BREATHE_MIN_MS as f64,
+        BREATHE_MAX_MS as f64,
+        100.0,
+    );
+    breathe_period.set_hexpand(true);
+    breathe_period.set_value_pos(gtk::PositionType::Right);
+    breathe_period.set_tooltip_text(Some("Breathing period (ms)"));
+    breathe_row.append(&breathe_period);
+
+    // Autoplay: steps the board shows by itself, connected or not.
+    let playlist_row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    playlist_row.set_margin_start(8);
+    playlist_row.set_margin_end(8);
+    playlist_row.set_margin_bottom(8);
+    playlist_row.set_sensitive(false);
+    playlist_row.append(&gtk::Label::new(Some("Playlist")));
+    let playlist_entry = gtk::Entry::new();
+    playlist_entry.set_hexpand(true);
+    playlist_entry.set_placeholder_text(Some("mask:ms, e.g. 1:500, 2:500, 4:500, 8:500"));
+    playlist_entry.set_tooltip_text(Some("Hex LED mask and milliseconds per step, up to 16 steps"));
+    let upload_playlist_btn = gtk::Button::with_label("Upload");
+    upload_playlist_btn.set_tooltip_text(Some("Replace the board's playlist; it keeps it across resets"));
+    let autoplay_check = gtk::CheckButton::with_label("Autoplay");
+    autoplay_check.set_tooltip_text(Some("Board plays the playlist on its own, ignoring mask writes and its buttons"));
+    playlist_row.append(&playlist_entry);
+    playlist_row.append(&upload_playlist_btn);
+    playlist_row.append(&autoplay_check);
+
+    // Connection interval and latency the board asks the central for.
+    let conn_row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    conn_row.set_margin_start(8);
+    conn_row.set_margin_end(8);
+    conn_row.set_margin_bottom(8);
+    conn_row.set_sensitive(false);
+    conn_row.append(&gtk::Label::new(Some("Connection")));
+    let conn_interval = gtk::Scale::with_range(
+        gtk::Orientation::Horizontal,
+        conn_params::MIN_INTERVAL_MS,
+        conn_params::MAX_INTERVAL_MS,
+        1.25,
+    );
+    conn_interval.set_value(30.0);
+    conn_interval.set_hexpand(true);
+    conn_interval.set_value_pos(gtk::PositionType::Right);
+    conn_interval.set_tooltip_text(Some("Connection interval (ms)"));
+    let conn_latency = gtk::Scale::with_range(gtk::Orientation::Horizontal, 0.0, conn_params::MAX_LATENCY as f64, 1.0);
+    conn_latency.set_hexpand(true);
+    conn_latency.set_value_pos(gtk::PositionType::Right);
+    conn_latency.set_tooltip_text(Some("Peripheral latency: connection events the board may sleep through"));
+    let request_conn_btn = gtk::Button::with_label("Request");
+    request_conn_btn.set_tooltip_text(Some("Ask the central for these; it may pick other values"));
+    let conn_params_label = gtk::Label::new(None);
+    conn_row.append(&conn_interval);
+    conn_row.append(&conn_latency);
+    conn_row.append(&request_conn_btn);
+    conn_row.append(&conn_params_label);
+
+    // Everything below the preview writes to the board; monitor mode greys
+    // it out along with the LED toggles, which keep showing the board's state.
+    let write_controls = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    write_controls.append(&device_row);
+    write_controls.append(&blink_grid);
+    write_controls.append(&breathe_row);
+    write_controls.append(&playlist_row);
+    write_controls.append(&conn_row);
+
+    let led_box = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    led_box.append(&led_grid);
+    led_box.append(&led_preview);
+    led_box.append(&write_controls);
+    led_frame.set_child(Some(&led_box));
+
+    // One toggle per LED; rebuilt on connect once the board reports its count.
+    let leds: Rc<RefCell<Vec<gtk::ToggleButton>>> = Rc::new(RefCell::new(Vec::new()));
     let all_on = gtk::Button::with_label("All On");
     let all_off = gtk::Button::with_label("All Off");
 
-    led_grid.attach(&led1, 0, 0, 1, 1);
-    led_grid.attach(&led2, 1, 0, 1, 1);
-    led_grid.attach(&led3, 2, 0, 1, 1);
-    led_grid.attach(&led4, 3, 0, 1, 1);
-    led_grid.attach(&all_on, 0, 1, 2, 1);
-    led_grid.attach(&all_off, 2, 1, 2, 1);
-
     // Log window
     let log_frame = gtk::Frame::builder().label("Log").build();
     let log_view = gtk::TextView::new();
@@ -131,334 +1178,3687 @@ fn build_ui(app: &gtk::Application) {
         .vexpand(true)
         .child(&log_view)
         .build();
-    log_frame.set_child(Some(&log_scroller));
+
+    // Search highlights matches in place; the buffer itself is never filtered.
+    let log_search = gtk::SearchEntry::new();
+    log_search.set_placeholder_text(Some("Search log"));
+    log_search.set_hexpand(true);
+    let search_prev_btn = gtk::Button::from_icon_name("go-up-symbolic");
+    search_prev_btn.set_tooltip_text(Some("Previous match"));
+    let search_next_btn = gtk::Button::from_icon_name("go-down-symbolic");
+    search_next_btn.set_tooltip_text(Some("Next match (Enter)"));
+    let search_count_label = gtk::Label::new(None);
+    log_buf.create_tag(Some(LOG_MATCH_TAG), &[("background", &"#f6d32d")]);
+    log_buf.create_tag(Some(LOG_ERROR_TAG), &[("foreground", &"#c01c28")]);
+
+    let search_row = gtk::Box::new(gtk::Orientation::Horizontal, 4);
+    search_row.set_margin_start(4);
+    search_row.set_margin_end(4);
+    search_row.set_margin_top(4);
+    search_row.append(&log_search);
+    search_row.append(&search_count_label);
+    search_row.append(&search_prev_btn);
+    search_row.append(&search_next_btn);
+
+    // Shown while scrolled up and new lines arrive below; appends only
+    // follow the tail if the view was already there.
+    let new_lines_btn = gtk::Button::with_label("New messages \u{2193}");
+    new_lines_btn.add_css_class("flat");
+    new_lines_btn.set_tooltip_text(Some("Jump to the newest log line"));
+    new_lines_btn.set_visible(false);
+    search_row.append(&new_lines_btn);
+    watch_log_tail(&log_view, &new_lines_btn);
+
+    let log_box = gtk::Box::new(gtk::Orientation::Vertical, 4);
+    log_box.append(&search_row);
+    log_box.append(&log_scroller);
+    log_frame.set_child(Some(&log_box));
+
+    // Connection lifecycle only (connects, drops, reconnects), timestamped,
+    // for auditing link stability without the writes and scans in between.
+    let conn_log_view = gtk::TextView::new();
+    conn_log_view.set_editable(false);
+    conn_log_view.set_monospace(true);
+    let conn_log_buf = conn_log_view.buffer();
+    let conn_log_scroller = gtk::ScrolledWindow::builder()
+        .min_content_height(90)
+        .child(&conn_log_view)
+        .build();
+    let conn_log_expander = gtk::Expander::builder()
+        .label("Connection log")
+        .child(&conn_log_scroller)
+        .build();
+
+    // Status bar
+    let status_label = gtk::Label::new(Some("Idle"));
+    status_label.set_xalign(0.0);
+    status_label.set_hexpand(true);
+    let stats_label = gtk::Label::new(None);
+    // Red below 40, amber below 70 (replacing the default offsets), green
+    // above.
+    let quality_bar = gtk::LevelBar::for_interval(0.0, 100.0);
+    for offset in [gtk::LEVEL_BAR_OFFSET_LOW, gtk::LEVEL_BAR_OFFSET_HIGH, gtk::LEVEL_BAR_OFFSET_FULL] {
+        quality_bar.remove_offset_value(Some(offset));
+    }
+    quality_bar.add_offset_value(gtk::LEVEL_BAR_OFFSET_LOW, 40.0);
+    quality_bar.add_offset_value(gtk::LEVEL_BAR_OFFSET_HIGH, 70.0);
+    quality_bar.add_offset_value(gtk::LEVEL_BAR_OFFSET_FULL, 100.0);
+    quality_bar.set_size_request(80, -1);
+    quality_bar.set_valign(gtk::Align::Center);
+    quality_bar.set_visible(false);
+    let heartbeat_label = gtk::Label::new(None);
+    let verify_label = gtk::Label::new(None);
+    let monitor_label = gtk::Label::new(None);
+    monitor_label.set_markup("<b>Monitor only: not writing</b>");
+    monitor_label.set_visible(false);
+    let status_row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    status_row.append(&status_label);
+    status_row.append(&stats_label);
+    status_row.append(&quality_bar);
+    status_row.append(&verify_label);
+    status_row.append(&monitor_label);
+    status_row.append(&heartbeat_label);
 
     root.append(&top);
     root.append(&devices_scroller);
     root.append(&led_frame);
     root.append(&log_frame);
+    root.append(&conn_log_expander);
+    root.append(&status_row);
+
+    // Toasts float over the bottom of the window, above the status row.
+    let toast_label = gtk::Label::new(None);
+    toast_label.add_css_class("app-notification");
+    let toast = gtk::Revealer::builder()
+        .transition_type(gtk::RevealerTransitionType::Crossfade)
+        .halign(gtk::Align::Center)
+        .valign(gtk::Align::End)
+        .margin_bottom(48)
+        .can_target(false)
+        .child(&toast_label)
+        .build();
+    // Bumped per toast, so only the latest one's timer hides it.
+    let toast_serial = Rc::new(Cell::new(0u64));
+    let overlay = gtk::Overlay::new();
+    overlay.set_child(Some(&root));
+    overlay.add_overlay(&toast);
 
-    window.set_child(Some(&root));
+    window.set_child(Some(&overlay));
     window.present();
 
     // ===== UI state =====
     let devices: Rc<RefCell<Vec<DeviceInfo>>> = Rc::new(RefCell::new(Vec::new()));
-    let connected = Rc::new(Cell::new(false));
+    let link_state = Rc::new(Cell::new(LinkState::Disconnected));
+    // Guards programmatic widget updates so their handlers don't echo the
+    // change back to the board.
+    let echo_guard = Rc::new(EchoGuard::new());
+    // Addresses seen in any scan so far, and highlight deadlines for new ones.
+    let seen_devices: Rc<RefCell<HashSet<String>>> = Rc::new(RefCell::new(HashSet::new()));
+    let new_until: Rc<RefCell<HashMap<String, Instant>>> = Rc::new(RefCell::new(HashMap::new()));
+    // Set when a star is toggled or a device hidden; the poller re-sorts and re-renders the list
+    // (rows can't rebuild the list from inside their own signal handlers).
+    let favorites_dirty = Rc::new(Cell::new(false));
+    // Address of the linked board (for the row marker), and of the attempt in
+    // flight until `Connected` says how it went.
+    let connected_addr: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+    // Last board we connected to this session, for the Reconnect button.
+    let last_connected_addr: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+    let connecting_addr: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+    // When the current link came up, for "connected for ..." on disconnect.
+    let connected_since: Rc<Cell<Option<Instant>>> = Rc::new(Cell::new(None));
+    // Together they decide whether the scan action is enabled.
+    let adapter_present = Rc::new(Cell::new(false));
+    let scanning = Rc::new(Cell::new(false));
+    // The connected row's LED preview; redrawn rather than the whole list
+    // rebuilt when the mask changes.
+    let row_preview: Rc<RefCell<gtk::glib::WeakRef<gtk::DrawingArea>>> = Rc::default();
 
-    set_led_controls_enabled(&[&led1, &led2, &led3, &led4], &all_on, &all_off, false);
+    let row_actions = RowActions {
+        toggle_favorite: {
+            let settings = settings.clone();
+            let favorites_dirty = favorites_dirty.clone();
+            let log_buf = log_buf.clone();
+            let log_view = log_view.clone();
+            Rc::new(move |addr: &str| {
+                let mut s = settings.borrow_mut();
+                if let Some(pos) = s.favorites.iter().position(|a| a == addr) {
+                    s.favorites.remove(pos);
+                } else {
+                    s.favorites.push(addr.to_string());
+                }
+                if let Err(e) = s.save() {
+                    append_log(&log_buf, &log_view, &format!("Saving settings failed: {e:#}"));
+                }
+                favorites_dirty.set(true);
+            })
+        },
+        connect: {
+            let cmd_tx = cmd_tx.clone();
+            Rc::new(move |addr: &str| {
+                let _ = cmd_tx.send(Cmd::Connect { addr: addr.to_string() });
+            })
+        },
+        hide: {
+            let settings = settings.clone();
+            let cmd_tx = cmd_tx.clone();
+            let devices = devices.clone();
+            let favorites_dirty = favorites_dirty.clone();
+            let log_buf = log_buf.clone();
+            let log_view = log_view.clone();
+            Rc::new(move |addr: &str| {
+                let mut s = settings.borrow_mut();
+                s.device_deny.push(addr.to_string());
+                if let Err(e) = s.save() {
+                    append_log(&log_buf, &log_view, &format!("Saving settings failed: {e:#}"));
+                }
+                let _ = cmd_tx.send(Cmd::SetDeviceFilter(DeviceFilter::from_settings(&s)));
+                devices.borrow_mut().retain(|d| d.addr != addr);
+                append_log(&log_buf, &log_view, &format!("Hiding {addr}; Preferences > Device list brings it back."));
+                favorites_dirty.set(true);
+            })
+        },
+        mask_preview: {
+            let board_mask = board_mask.clone();
+            let leds = leds.clone();
+            let row_preview = row_preview.clone();
+            Rc::new(move || {
+                let area = gtk::DrawingArea::new();
+                area.set_content_height(12);
+                area.set_content_width(leds.borrow().len() as i32 * 12 + 4);
+                area.set_valign(gtk::Align::Center);
+                area.set_tooltip_text(Some("LEDs as the board reports them"));
+                let board_mask = board_mask.clone();
+                let leds = leds.clone();
+                area.set_draw_func(move |_, cr, _w, h| {
+                    draw_led_preview(cr, h, leds.borrow().len(), board_mask.get(), false);
+                });
+                row_preview.replace(area.downgrade());
+                area
+            })
+        },
+    };
 
-    // ===== Button handlers =====
+    // Section headers: starred devices, then "Controllable" boards, then
+    // everything else.
     {
-        let cmd_tx = cmd_tx.clone();
-        scan_btn.connect_clicked(move |_| {
-            let _ = cmd_tx.send(Cmd::Scan);
+        let devices = devices.clone();
+        let settings = settings.clone();
+        devices_list.set_header_func(move |row, before| {
+            let devs = devices.borrow();
+            let s = settings.borrow();
+            let group = |r: &gtk::ListBoxRow| row_device(r, &devs).map(|d| device_group(d, &s.favorites));
+
+            let this = group(row);
+            if before.and_then(group) == this {
+                row.set_header(None::<&gtk::Widget>);
+                return;
+            }
+
+            let title = match this {
+                Some(0) => "Favorites",
+                Some(1) => "Controllable",
+                _ => "Other devices",
+            };
+            let header = gtk::Label::new(Some(title));
+            header.set_xalign(0.0);
+            header.add_css_class("heading");
+            row.set_header(Some(&header));
         });
     }
 
-    {
+    // Toggle buttons -> compute mask -> send
+    let send_mask: Rc<dyn Fn()> = {
         let cmd_tx = cmd_tx.clone();
-        let devices = devices.clone();
-        let devices_list = devices_list.clone();
-        connect_btn.connect_clicked(move |_| {
-            let row = match devices_list.selected_row() {
-                Some(r) => r,
-                None => return,
-            };
-            let idx = row.index();
-            if idx < 0 {
+        let leds = leds.clone();
+        let echo_guard = echo_guard.clone();
+        let link_state = link_state.clone();
+        Rc::new(move || {
+            if !echo_guard.should_echo() || link_state.get() != LinkState::Connected {
                 return;
             }
-            let devs = devices.borrow();
-            let Some(d) = devs.get(idx as usize) else { return; };
-            let _ = cmd_tx.send(Cmd::Connect { addr: d.addr.clone() });
+            let m = toggles_mask(&leds.borrow());
+            let _ = cmd_tx.send(Cmd::SetMask(m));
+        })
+    };
+
+    let labels = settings.borrow().led_labels.clone();
+    rebuild_led_toggles(&led_grid, &all_on, &all_off, &leds, DEFAULT_LED_COUNT, &labels, &send_mask);
+
+    {
+        let board_mask = board_mask.clone();
+        let notify_flash_until = notify_flash_until.clone();
+        let leds = leds.clone();
+        led_preview.set_draw_func(move |_, cr, _w, h| {
+            let flash = notify_flash_until.get().is_some_and(|until| until > Instant::now());
+            draw_led_preview(cr, h, leds.borrow().len(), board_mask.get(), flash);
         });
     }
+    set_led_controls_enabled(&leds.borrow(), &all_on, &all_off, false, present_leds.get());
 
+    // ===== Button handlers =====
     {
         let cmd_tx = cmd_tx.clone();
-        disconnect_btn.connect_clicked(move |_| {
-            let _ = cmd_tx.send(Cmd::Disconnect);
+        let settings = settings.clone();
+        let status_label = status_label.clone();
+        scan_action.connect_activate(move |action, _| {
+            // Until the worker says the scan is over; a queued second scan
+            // would only repeat it.
+            action.set_enabled(false);
+            let _ = cmd_tx.send(Cmd::Scan(RssiFilter::from_settings(&settings.borrow())));
+            status_label.set_text("Scanning...");
         });
     }
 
-   // Toggle buttons -> compute mask -> send
-{
-    let cmd_tx = cmd_tx.clone();
-    let connected = connected.clone();
+    {
+        let cmd_tx = cmd_tx.clone();
+        let settings = settings.clone();
+        let status_label = status_label.clone();
+        quick_connect_action.connect_activate(move |action, _| {
+            action.set_enabled(false);
+            let _ = cmd_tx.send(Cmd::QuickConnect(RssiFilter::from_settings(&settings.borrow())));
+            status_label.set_text("Scanning...");
+        });
+    }
 
-    // clones used INSIDE the send_mask closure
-    let led1_for_mask = led1.clone();
-    let led2_for_mask = led2.clone();
-    let led3_for_mask = led3.clone();
-    let led4_for_mask = led4.clone();
+    {
+        let cmd_tx = cmd_tx.clone();
+        let settings = settings.clone();
+        live_scan_check.connect_toggled(move |c| {
+            let filter = c.is_active().then(|| RssiFilter::from_settings(&settings.borrow()));
+            let _ = cmd_tx.send(Cmd::LiveScan(filter));
+        });
+    }
 
-    let send_mask = Rc::new(move || {
-        let mut m = 0u8;
-        if led1_for_mask.is_active() { m |= 0x01; }
-        if led2_for_mask.is_active() { m |= 0x02; }
-        if led3_for_mask.is_active() { m |= 0x04; }
-        if led4_for_mask.is_active() { m |= 0x08; }
-        let _ = cmd_tx.send(Cmd::SetMask(m));
-    });
+    for scale in blink_scales.iter() {
+        let cmd_tx = cmd_tx.clone();
+        let blink_scales = blink_scales.clone();
+        let echo_guard = echo_guard.clone();
+        scale.connect_value_changed(move |_| {
+            if !echo_guard.should_echo() {
+                return;
+            }
+            let mut periods = [0u16; BLINK_LED_COUNT];
+            for (p, s) in periods.iter_mut().zip(blink_scales.iter()) {
+                *p = s.value() as u16;
+            }
+            let _ = cmd_tx.send(Cmd::SetBlinkPeriods(periods));
+        });
+    }
 
-    // separate clones used to register signal handlers
-    {
-        let f = send_mask.clone();
-        let led = led1.clone();
-        led.connect_toggled(move |_| f());
+    let send_breathe: Rc<dyn Fn()> = {
+        let cmd_tx = cmd_tx.clone();
+        let breathe_checks = breathe_checks.clone();
+        let breathe_period = breathe_period.clone();
+        let echo_guard = echo_guard.clone();
+        Rc::new(move || {
+            if !echo_guard.should_echo() {
+                return;
+            }
+            let mask = breathe_checks.iter().enumerate().fold(0u16, |m, (i, c)| m | (c.is_active() as u16) << i);
+            let period_ms = breathe_period.value() as u16;
+            let _ = cmd_tx.send(Cmd::SetBreathe { mask, period_ms });
+        })
+    };
+    for check in breathe_checks.iter() {
+        let send_breathe = send_breathe.clone();
+        check.connect_toggled(move |_| send_breathe());
     }
+    breathe_period.connect_value_changed(move |_| send_breathe());
+
     {
-        let f = send_mask.clone();
-        let led = led2.clone();
-        led.connect_toggled(move |_| f());
+        let log_buf = log_buf.clone();
+        let search_count_label = search_count_label.clone();
+        log_search.connect_search_changed(move |e| {
+            let count = highlight_log_matches(&log_buf, &e.text());
+            search_count_label.set_text(&match count {
+                _ if e.text().is_empty() => String::new(),
+                1 => "1 match".to_string(),
+                n => format!("{n} matches"),
+            });
+        });
+    }
+    for (btn, forward) in [(&search_next_btn, true), (&search_prev_btn, false)] {
+        let log_buf = log_buf.clone();
+        let log_view = log_view.clone();
+        let log_search = log_search.clone();
+        btn.connect_clicked(move |_| jump_to_log_match(&log_buf, &log_view, &log_search.text(), forward));
     }
     {
-        let f = send_mask.clone();
-        let led = led3.clone();
-        led.connect_toggled(move |_| f());
+        let log_buf = log_buf.clone();
+        let log_view = log_view.clone();
+        log_search.connect_activate(move |e| jump_to_log_match(&log_buf, &log_view, &e.text(), true));
     }
+
+    verbose_check.connect_toggled(|c| WIRE_TRACE.store(c.is_active(), Ordering::Relaxed));
+
     {
-        let f = send_mask.clone();
-        let led = led4.clone();
-        led.connect_toggled(move |_| f());
+        let cmd_tx = cmd_tx.clone();
+        let verify_label = verify_label.clone();
+        verify_check.connect_toggled(move |c| {
+            verify_label.set_text("");
+            let _ = cmd_tx.send(Cmd::SetVerifyWrites(c.is_active()));
+        });
     }
-}
-    // All On
-{
-    let cmd_tx = cmd_tx.clone();
-    let led1 = led1.clone();
-    let led2 = led2.clone();
-    let led3 = led3.clone();
-    let led4 = led4.clone();
-
-    all_on.connect_clicked(move |_| {
-        led1.set_active(true);
-        led2.set_active(true);
-        led3.set_active(true);
-        led4.set_active(true);
-        let _ = cmd_tx.send(Cmd::SetMask(0x0f));
-    });
-}
 
-// All Off
-{
-    let cmd_tx = cmd_tx.clone();
-    let led1 = led1.clone();
-    let led2 = led2.clone();
-    let led3 = led3.clone();
-    let led4 = led4.clone();
-
-    all_off.connect_clicked(move |_| {
-        led1.set_active(false);
-        led2.set_active(false);
-        led3.set_active(false);
-        led4.set_active(false);
-        let _ = cmd_tx.send(Cmd::SetMask(0x00));
-    });
-}
+    {
+        let cmd_tx = cmd_tx.clone();
+        let led_grid = led_grid.clone();
+        let write_controls = write_controls.clone();
+        let monitor_label = monitor_label.clone();
+        monitor_check.connect_toggled(move |c| {
+            let on = c.is_active();
+            led_grid.set_sensitive(!on);
+            write_controls.set_sensitive(!on);
+            monitor_label.set_visible(on);
+            let _ = cmd_tx.send(Cmd::SetMonitorOnly(on));
+        });
+    }
 
+    {
+        let cmd_tx = cmd_tx.clone();
+        let verify_label = verify_label.clone();
+        verified_check.connect_toggled(move |c| {
+            verify_label.set_text("");
+            let _ = cmd_tx.send(Cmd::SetVerifiedMode(c.is_active()));
+        });
+    }
 
-    // ===== UI poller: pump UiMsg from std::mpsc into GTK =====
     {
         let devices = devices.clone();
-        let devices_list = devices_list.clone();
-        let connected_state = connected.clone();
-
+        let window = window.clone();
         let log_buf = log_buf.clone();
         let log_view = log_view.clone();
+        export_btn.connect_clicked(move |_| {
+            if devices.borrow().is_empty() {
+                append_log(&log_buf, &log_view, "Nothing to export; scan first.");
+                return;
+            }
+            let dialog = gtk::FileDialog::builder().title("Export scan").initial_name("scan.csv").modal(true).build();
+            let devices = devices.clone();
+            let log_buf = log_buf.clone();
+            let log_view = log_view.clone();
+            dialog.save(Some(&window), None::<&gtk::gio::Cancellable>, move |res| {
+                // Err is also how a cancelled dialog reports back.
+                let Some(path) = res.ok().and_then(|f| f.path()) else { return };
+                let line = match scan_export::write(&path, &devices.borrow()) {
+                    Ok(()) => format!("Exported {} device(s) to {}", devices.borrow().len(), path.display()),
+                    Err(e) => format!("Export failed: {e:#}"),
+                };
+                append_log(&log_buf, &log_view, &line);
+            });
+        });
+    }
 
-        let led1 = led1.clone();
-        let led2 = led2.clone();
-        let led3 = led3.clone();
-        let led4 = led4.clone();
-        let all_on = all_on.clone();
-        let all_off = all_off.clone();
-
-        gtk::glib::timeout_add_local(Duration::from_millis(50), move || {
-            while let Ok(msg) = ui_rx.try_recv() {
-                match msg {
+    let replaying = Rc::new(Cell::new(false));
+    {
+        let cmd_tx = cmd_tx.clone();
+        let window = window.clone();
+        let echo_guard = echo_guard.clone();
+        record_btn.connect_toggled(move |b| {
+            if !echo_guard.should_echo() {
+                return;
+            }
+            if b.is_active() {
+                let _ = cmd_tx.send(Cmd::StartRecording);
+                return;
+            }
+            let dialog =
+                gtk::FileDialog::builder().title("Save recording").initial_name("session.json").modal(true).build();
+            let cmd_tx = cmd_tx.clone();
+            dialog.save(Some(&window), None::<&gtk::gio::Cancellable>, move |res| {
+                // Cancelling the dialog discards the recording.
+                let _ = cmd_tx.send(Cmd::StopRecording(res.ok().and_then(|f| f.path())));
+            });
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        let window = window.clone();
+        let replaying = replaying.clone();
+        replay_btn.connect_clicked(move |_| {
+            if replaying.get() {
+                let _ = cmd_tx.send(Cmd::StopReplay);
+                return;
+            }
+            let dialog = gtk::FileDialog::builder().title("Replay recording").modal(true).build();
+            let cmd_tx = cmd_tx.clone();
+            dialog.open(Some(&window), None::<&gtk::gio::Cancellable>, move |res| {
+                let Some(path) = res.ok().and_then(|f| f.path()) else { return };
+                let _ = cmd_tx.send(Cmd::Replay(path));
+            });
+        });
+    }
+
+    // New settings (Preferences, import): push whatever the worker needs.
+    let apply_settings: Rc<dyn Fn(&Settings)> = {
+        let cmd_tx = cmd_tx.clone();
+        let live_scan_check = live_scan_check.clone();
+        let leds = leds.clone();
+        let breathe_checks = breathe_checks.clone();
+        Rc::new(move |s: &Settings| {
+            // Only the captions change; the bit mapping stays by position.
+            for (idx, t) in leds.borrow().iter().enumerate() {
+                t.set_label(&led_label(&s.led_labels, idx));
+            }
+            for (idx, l) in blink_labels.iter().enumerate() {
+                l.set_text(&blink_caption(&s.led_labels, idx));
+            }
+            for (idx, c) in breathe_checks.iter().enumerate() {
+                c.set_label(Some(&led_label(&s.led_labels, idx)));
+            }
+            let _ = cmd_tx.send(Cmd::SetReconnectPolicy(ReconnectPolicy::from_settings(s)));
+            let _ = cmd_tx.send(Cmd::SetLedCharUuids(s.led_char_uuids.clone()));
+            let _ = cmd_tx.send(Cmd::SetRssiSmoothing(s.rssi_smoothing));
+            let _ = cmd_tx.send(Cmd::SetScanDuplicates(s.scan_duplicates));
+            let _ = cmd_tx.send(Cmd::SetDeviceFilter(DeviceFilter::from_settings(s)));
+            let _ = cmd_tx.send(Cmd::SetHeartbeatLimit(s.heartbeat_max_missed));
+            let _ = cmd_tx.send(Cmd::SetNotifyStreams(s.notify_streams));
+            let _ = cmd_tx.send(Cmd::SetQualityWeights(quality::Weights::from_settings(s)));
+            if live_scan_check.is_active() {
+                let _ = cmd_tx.send(Cmd::LiveScan(Some(RssiFilter::from_settings(s))));
+            }
+        })
+    };
+
+    {
+        let settings = settings.clone();
+        let window = window.clone();
+        let apply_settings = apply_settings.clone();
+        let toast = toast.clone();
+        let toast_label = toast_label.clone();
+        let toast_serial = toast_serial.clone();
+        let on_saved: Rc<dyn Fn(&Settings)> = Rc::new(move |s: &Settings| {
+            apply_settings(s);
+            show_toast(&toast, &toast_label, &toast_serial, "Preferences saved.");
+        });
+        prefs_btn.connect_clicked(move |_| preferences::show(&window, &settings, on_saved.clone()));
+    }
+
+    {
+        let settings = settings.clone();
+        let window = window.clone();
+        let log_buf = log_buf.clone();
+        let log_view = log_view.clone();
+        export_settings_action.connect_activate(move |_, _| {
+            let dialog = gtk::FileDialog::builder()
+                .title("Export settings")
+                .initial_name("nrf52840_led_gui-settings.json")
+                .modal(true)
+                .build();
+            let settings = settings.clone();
+            let log_buf = log_buf.clone();
+            let log_view = log_view.clone();
+            dialog.save(Some(&window), None::<&gtk::gio::Cancellable>, move |res| {
+                let Some(path) = res.ok().and_then(|f| f.path()) else { return };
+                let settings = settings.borrow();
+                let line = match settings_export::write(&path, &settings) {
+                    Ok(()) if !settings.influx_token.is_empty() => {
+                        format!("Exported settings to {} (without the InfluxDB token)", path.display())
+                    }
+                    Ok(()) => format!("Exported settings to {}", path.display()),
+                    Err(e) => format!("Settings export failed: {e:#}"),
+                };
+                append_log(&log_buf, &log_view, &line);
+            });
+        });
+    }
+
+    // Import replaces the settings file, like saving Preferences would.
+    {
+        let settings = settings.clone();
+        let window = window.clone();
+        let log_buf = log_buf.clone();
+        let log_view = log_view.clone();
+        let toast = toast.clone();
+        let toast_label = toast_label.clone();
+        let toast_serial = toast_serial.clone();
+        import_settings_action.connect_activate(move |_, _| {
+            let dialog = gtk::FileDialog::builder().title("Import settings").modal(true).build();
+            let settings = settings.clone();
+            let apply_settings = apply_settings.clone();
+            let log_buf = log_buf.clone();
+            let log_view = log_view.clone();
+            let toast = toast.clone();
+            let toast_label = toast_label.clone();
+            let toast_serial = toast_serial.clone();
+            dialog.open(Some(&window), None::<&gtk::gio::Cancellable>, move |res| {
+                let Some(path) = res.ok().and_then(|f| f.path()) else { return };
+                let imported = settings_export::read(&path, &settings.borrow());
+                let (s, skipped) = match imported {
+                    Ok(r) => r,
+                    Err(e) => return append_log(&log_buf, &log_view, &format!("Settings import failed: {e:#}")),
+                };
+                if let Err(e) = s.save() {
+                    return append_log(&log_buf, &log_view, &format!("Saving settings failed: {e:#}"));
+                }
+                settings.replace(s);
+                apply_settings(&settings.borrow());
+                for reason in &skipped {
+                    append_log(&log_buf, &log_view, &format!("Import skipped {reason}"));
+                }
+                let text = match skipped.len() {
+                    0 => format!("Imported settings from {}", path.display()),
+                    n => format!("Imported settings from {} ({n} skipped, see log)", path.display()),
+                };
+                show_toast(&toast, &toast_label, &toast_serial, &text);
+            });
+        });
+    }
+
+    // Selecting a highlighted device acknowledges it.
+    {
+        let devices = devices.clone();
+        let new_until = new_until.clone();
+        devices_list.connect_row_selected(move |_, row| {
+            let Some(row) = row else { return };
+            let devs = devices.borrow();
+            let Some(d) = row_device(row, &devs) else { return };
+            if new_until.borrow_mut().remove(&d.addr).is_some()
+                && let Some(label) = row_label(row)
+            {
+                label.set_text(&device_row_text(d));
+            }
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        let devices = devices.clone();
+        let devices_list = devices_list.clone();
+        let connected_addr = connected_addr.clone();
+        let log_buf = log_buf.clone();
+        let log_view = log_view.clone();
+        connect_btn.connect_clicked(move |_| {
+            let Some(row) = devices_list.selected_row() else { return };
+            let devs = devices.borrow();
+            let Some(d) = row_device(&row, &devs) else { return };
+            if connected_addr.borrow().as_deref() == Some(d.addr.as_str()) {
+                append_log(&log_buf, &log_view, &format!("Already connected to {}.", d.addr));
+                return;
+            }
+            let _ = cmd_tx.send(Cmd::Connect { addr: d.addr.clone() });
+        });
+    }
+
+    // Discovery latency of the selected board, for tuning its advertising.
+    {
+        let cmd_tx = cmd_tx.clone();
+        let devices_list = devices_list.clone();
+        let devices = devices.clone();
+        let log_buf = log_buf.clone();
+        let log_view = log_view.clone();
+        bench_action.connect_activate(move |_, _| {
+            let Some(row) = devices_list.selected_row() else {
+                append_log(&log_buf, &log_view, "Select the board to benchmark first.");
+                return;
+            };
+            let devs = devices.borrow();
+            let Some(d) = row_device(&row, &devs) else { return };
+            let _ = cmd_tx.send(Cmd::BenchmarkDiscovery { addr: d.addr.clone(), name: d.name.clone() });
+        });
+    }
+
+    // Repeated connect/disconnect of the selected board, for link stability.
+    {
+        let cmd_tx = cmd_tx.clone();
+        let devices_list = devices_list.clone();
+        let devices = devices.clone();
+        let log_buf = log_buf.clone();
+        let log_view = log_view.clone();
+        soak_action.connect_activate(move |_, _| {
+            let Some(row) = devices_list.selected_row() else {
+                append_log(&log_buf, &log_view, "Select the board to soak test first.");
+                return;
+            };
+            let devs = devices.borrow();
+            let Some(d) = row_device(&row, &devs) else { return };
+            let _ = cmd_tx.send(Cmd::Soak { addr: d.addr.clone(), cycles: soak::DEFAULT_CYCLES });
+        });
+    }
+    {
+        let cmd_tx = cmd_tx.clone();
+        stop_soak_action.connect_activate(move |_, _| {
+            let _ = cmd_tx.send(Cmd::StopSoak);
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        cancel_connect_btn.connect_clicked(move |_| {
+            let _ = cmd_tx.send(Cmd::CancelConnect);
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        let link_state = link_state.clone();
+        let leds = leds.clone();
+        let all_on = all_on.clone();
+        let all_off = all_off.clone();
+        let test_mode_check = test_mode_check.clone();
+        let lock_check = lock_check.clone();
+        let stage_check = stage_check.clone();
+        let apply_btn = apply_btn.clone();
+        let reboot_btn = reboot_btn.clone();
+        let self_test_btn = self_test_btn.clone();
+        let blink_scales = blink_scales.clone();
+        let breathe_row = breathe_row.clone();
+        let playlist_row = playlist_row.clone();
+        let conn_row = conn_row.clone();
+        let status_label = status_label.clone();
+        disconnect_btn.connect_clicked(move |_| {
+            // Lock the controls now rather than when the worker confirms;
+            // anything sent in between would hit a closing link.
+            if link_state.get() == LinkState::Connected {
+                link_state.set(LinkState::Disconnecting);
+                set_led_controls_enabled(&leds.borrow(), &all_on, &all_off, false, u16::MAX);
+                test_mode_check.set_sensitive(false);
+                lock_check.set_sensitive(false);
+                stage_check.set_sensitive(false);
+                apply_btn.set_sensitive(false);
+                reboot_btn.set_sensitive(false);
+                self_test_btn.set_sensitive(false);
+                for scale in blink_scales.iter() {
+                    scale.set_sensitive(false);
+                }
+                breathe_row.set_sensitive(false);
+                playlist_row.set_sensitive(false);
+                conn_row.set_sensitive(false);
+                status_label.set_text("Disconnecting...");
+            }
+            let _ = cmd_tx.send(Cmd::Disconnect);
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        let last_connected_addr = last_connected_addr.clone();
+        reconnect_btn.connect_clicked(move |_| {
+            if let Some(addr) = last_connected_addr.borrow().clone() {
+                let _ = cmd_tx.send(Cmd::Connect { addr });
+            }
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        read_all_btn.connect_clicked(move |_| {
+            let _ = cmd_tx.send(Cmd::ReadAll);
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        test_mode_check.connect_toggled(move |c| {
+            let _ = cmd_tx.send(Cmd::SetTestMode(c.is_active()));
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        let echo_guard = echo_guard.clone();
+        lock_check.connect_toggled(move |c| {
+            if echo_guard.should_echo() {
+                let _ = cmd_tx.send(Cmd::SetLocked(c.is_active()));
+            }
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        let echo_guard = echo_guard.clone();
+        stage_check.connect_toggled(move |c| {
+            if echo_guard.should_echo() {
+                let _ = cmd_tx.send(Cmd::SetStaging(c.is_active()));
+            }
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        apply_btn.connect_clicked(move |_| {
+            let _ = cmd_tx.send(Cmd::ApplyStaged);
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        let playlist_entry = playlist_entry.clone();
+        let log_buf = log_buf.clone();
+        let log_view = log_view.clone();
+        upload_playlist_btn.connect_clicked(move |_| match playlist::parse(&playlist_entry.text()) {
+            Ok(steps) => {
+                let _ = cmd_tx.send(Cmd::SetPlaylist(steps));
+            }
+            Err(e) => append_log(&log_buf, &log_view, &format!("Playlist not uploaded: {e}")),
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        let conn_interval = conn_interval.clone();
+        let conn_latency = conn_latency.clone();
+        request_conn_btn.connect_clicked(move |_| {
+            let params = conn_params::ConnParams::request(conn_interval.value(), conn_latency.value() as u16);
+            let _ = cmd_tx.send(Cmd::SetConnParams(params));
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        let echo_guard = echo_guard.clone();
+        autoplay_check.connect_toggled(move |c| {
+            if echo_guard.should_echo() {
+                let _ = cmd_tx.send(Cmd::SetAutoplay(c.is_active()));
+            }
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        let window = window.clone();
+        reboot_btn.connect_clicked(move |_| {
+            let dialog = gtk::AlertDialog::builder()
+                .message("Reboot device?")
+                .detail("The board turns its LEDs off and resets. The connection drops and auto-reconnect takes over.")
+                .buttons(["Cancel", "Reboot"])
+                .cancel_button(0)
+                .default_button(0)
+                .modal(true)
+                .build();
+            let cmd_tx = cmd_tx.clone();
+            dialog.choose(Some(&window), None::<&gtk::gio::Cancellable>, move |res| {
+                if let Ok(1) = res {
+                    let _ = cmd_tx.send(Cmd::Reboot);
+                }
+            });
+        });
+    }
+
+    {
+        let cmd_tx = cmd_tx.clone();
+        let self_test_label = self_test_label.clone();
+        self_test_btn.connect_clicked(move |_| {
+            self_test_label.set_text("Testing...");
+            let _ = cmd_tx.send(Cmd::SelfTest);
+        });
+    }
+
+    // All On
+    {
+        let cmd_tx = cmd_tx.clone();
+        let echo_guard = echo_guard.clone();
+        let leds = leds.clone();
+        let present_leds = present_leds.clone();
+        all_on.connect_clicked(move |_| {
+            let leds = leds.borrow();
+            let mask = full_mask(leds.len()) & present_leds.get();
+            set_toggles_from_code(&leds, mask, &echo_guard);
+            let _ = cmd_tx.send(Cmd::SetMask(mask));
+        });
+    }
+
+    // All Off
+    {
+        let cmd_tx = cmd_tx.clone();
+        let echo_guard = echo_guard.clone();
+        let leds = leds.clone();
+        all_off.connect_clicked(move |_| {
+            set_toggles_from_code(&leds.borrow(), 0x00, &echo_guard);
+            let _ = cmd_tx.send(Cmd::SetMask(0x00));
+        });
+    }
+
+    // ===== UI poller: pump UiMsg from std::mpsc into GTK =====
+    {
+        let devices = devices.clone();
+        let devices_list = devices_list.clone();
+        let link_state = link_state.clone();
+        let echo_guard = echo_guard.clone();
+        let notify_label = notify_label.clone();
+        let live_scan_check = live_scan_check.clone();
+        let status_label = status_label.clone();
+        let cancel_connect_btn = cancel_connect_btn.clone();
+        let stats_label = stats_label.clone();
+        let quality_bar = quality_bar.clone();
+        let scan_action = scan_action.clone();
+        let quick_connect_action = quick_connect_action.clone();
+        let bench_action = bench_action.clone();
+        let soak_action = soak_action.clone();
+        let stop_soak_action = stop_soak_action.clone();
+        let led_frame = led_frame.clone();
+        let adapter_present = adapter_present.clone();
+        let scanning = scanning.clone();
+        let auto_scan_pending = auto_scan_pending.clone();
+        let heartbeat_label = heartbeat_label.clone();
+        let telemetry_label = telemetry_label.clone();
+        let seen_devices = seen_devices.clone();
+        let new_until = new_until.clone();
+        let settings = settings.clone();
+        let favorites_dirty = favorites_dirty.clone();
+        let row_actions = row_actions.clone();
+        let connected_addr = connected_addr.clone();
+        let connecting_addr = connecting_addr.clone();
+        let last_connected_addr = last_connected_addr.clone();
+        let reconnect_btn = reconnect_btn.clone();
+
+        let connected_since = connected_since.clone();
+
+        let log_buf = log_buf.clone();
+        let log_view = log_view.clone();
+        let conn_log_buf = conn_log_buf.clone();
+        let conn_log_view = conn_log_view.clone();
+        let toast = toast.clone();
+        let toast_label = toast_label.clone();
+        let toast_serial = toast_serial.clone();
+
+        let window = window.clone();
+        let cmd_tx = cmd_tx.clone();
+        let led_grid = led_grid.clone();
+        let leds = leds.clone();
+        let board_mask = board_mask.clone();
+        let present_leds = present_leds.clone();
+        let notify_flash_until = notify_flash_until.clone();
+        let led_preview = led_preview.clone();
+        let row_preview = row_preview.clone();
+        let all_on = all_on.clone();
+        let all_off = all_off.clone();
+        let test_mode_check = test_mode_check.clone();
+        let reboot_btn = reboot_btn.clone();
+        let self_test_btn = self_test_btn.clone();
+        let self_test_label = self_test_label.clone();
+        let lock_check = lock_check.clone();
+        let stage_check = stage_check.clone();
+        let apply_btn = apply_btn.clone();
+        let blink_scales = blink_scales.clone();
+        let blink_grid = blink_grid.clone();
+        let breathe_row = breathe_row.clone();
+        let breathe_checks = breathe_checks.clone();
+        let breathe_period = breathe_period.clone();
+        let playlist_row = playlist_row.clone();
+        let playlist_entry = playlist_entry.clone();
+        let autoplay_check = autoplay_check.clone();
+        let conn_row = conn_row.clone();
+        let conn_params_label = conn_params_label.clone();
+        let monitor_check = monitor_check.clone();
+        let verify_label = verify_label.clone();
+        let record_btn = record_btn.clone();
+        let replay_btn = replay_btn.clone();
+        let replaying = replaying.clone();
+
+        gtk::glib::timeout_add_local(Duration::from_millis(50), move || {
+            while let Ok(msg) = ui_rx.try_recv() {
+                match msg {
                     UiMsg::Log(line) => append_log(&log_buf, &log_view, &line),
+                    UiMsg::FirmwareError(line) => append_log_tagged(&log_buf, &log_view, &line, LOG_ERROR_TAG),
+                    UiMsg::Toast(text) => show_toast(&toast, &toast_label, &toast_serial, &text),
+
+                    UiMsg::ScanResults(mut list) => {
+                        // Highlight devices we haven't seen before (but not on
+                        // the very first scan, where everything would be "new").
+                        {
+                            let mut seen = seen_devices.borrow_mut();
+                            let first_scan = seen.is_empty();
+                            let now = Instant::now();
+                            for d in &list {
+                                if seen.insert(d.addr.clone()) && !first_scan {
+                                    new_until.borrow_mut().insert(d.addr.clone(), now + NEW_DEVICE_HIGHLIGHT);
+                                }
+                            }
+                            new_until.borrow_mut().retain(|_, until| *until > now);
+                        }
+
+                        let favorites = settings.borrow().favorites.clone();
+                        pin_favorites(&mut list, &favorites);
+                        devices.replace(list);
+                        render_device_rows(&devices_list, &devices.borrow(), &new_until.borrow(), &favorites, connected_addr.borrow().as_deref(), &row_actions);
+
+                        // Schedule a redraw to drop highlights once they expire.
+                        if !new_until.borrow().is_empty() {
+                            let devices = devices.clone();
+                            let devices_list = devices_list.clone();
+                            let new_until = new_until.clone();
+                            let settings = settings.clone();
+                            let row_actions = row_actions.clone();
+                            let connected_addr = connected_addr.clone();
+                            gtk::glib::timeout_add_local_once(NEW_DEVICE_HIGHLIGHT, move || {
+                                let now = Instant::now();
+                                new_until.borrow_mut().retain(|_, until| *until > now);
+                                let favorites = settings.borrow().favorites.clone();
+                                render_device_rows(&devices_list, &devices.borrow(), &new_until.borrow(), &favorites, connected_addr.borrow().as_deref(), &row_actions);
+                            });
+                        }
+
+                        if status_label.text() == "Scanning..." {
+                            status_label.set_text("Idle");
+                        }
+
+                        // Live scan refreshes every few seconds; don't flood the log.
+                        if !live_scan_check.is_active() {
+                            let text = format!("Scan finished: {} device(s)", devices.borrow().len());
+                            show_toast(&toast, &toast_label, &toast_serial, &text);
+                        }
+                    }
+
+                    UiMsg::Connected(is_connected) => {
+                        link_state.set(if is_connected { LinkState::Connected } else { LinkState::Disconnected });
+                        append_log(&log_buf, &log_view, if is_connected { "Connected." } else { "Disconnected." });
+                        status_label.set_text(if is_connected { "Connected" } else { "Disconnected" });
+                        cancel_connect_btn.set_visible(false);
+                        let addr = if is_connected { connecting_addr.take() } else { None };
+                        let conn_line = match (is_connected, connected_since.take()) {
+                            (true, _) => {
+                                connected_since.set(Some(Instant::now()));
+                                // Auto-reconnect doesn't go through `Connecting`.
+                                let who = addr.clone().or_else(|| last_connected_addr.borrow().clone());
+                                format!("{} connected.", who.as_deref().unwrap_or("Board"))
+                            }
+                            (false, Some(at)) => {
+                                let who = connected_addr.borrow().clone().unwrap_or_else(|| "Board".into());
+                                format!("{who} disconnected (connected for {}).", format_duration(at.elapsed()))
+                            }
+                            (false, None) => match connecting_addr.take() {
+                                Some(who) => format!("Connecting to {who} failed."),
+                                None => "Not connected.".into(),
+                            },
+                        };
+                        append_conn_log(&conn_log_buf, &conn_log_view, &conn_line);
+                        if *connected_addr.borrow() != addr {
+                            connected_addr.replace(addr);
+                            let favorites = settings.borrow().favorites.clone();
+                            render_device_rows(&devices_list, &devices.borrow(), &new_until.borrow(), &favorites, connected_addr.borrow().as_deref(), &row_actions);
+                        }
+                        if let Some(addr) = connected_addr.borrow().clone() {
+                            reconnect_btn.set_tooltip_text(Some(&format!("Connect to {addr} again")));
+                            last_connected_addr.replace(Some(addr));
+                        }
+                        reconnect_btn.set_sensitive(!is_connected && last_connected_addr.borrow().is_some());
+                        if !is_connected {
+                            board_mask.set(0);
+                            led_preview.queue_draw();
+                            telemetry_label.set_text("");
+                            stats_label.set_text("");
+                            quality_bar.set_visible(false);
+                            heartbeat_label.set_text("");
+                            self_test_label.set_text("");
+                            led_frame.set_label(Some("LEDs"));
+                            present_leds.set(u16::MAX);
+                        }
+
+                        set_led_controls_enabled(&leds.borrow(), &all_on, &all_off, is_connected, present_leds.get());
+                        test_mode_check.set_sensitive(is_connected);
+                        reboot_btn.set_sensitive(is_connected);
+                        self_test_btn.set_sensitive(is_connected);
+                        if !is_connected {
+                            lock_check.set_sensitive(false);
+                            stage_check.set_sensitive(false);
+                            apply_btn.set_sensitive(false);
+                            for scale in blink_scales.iter() {
+                                scale.set_sensitive(false);
+                            }
+                            breathe_row.set_sensitive(false);
+                            playlist_row.set_sensitive(false);
+                            conn_row.set_sensitive(false);
+                            conn_params_label.set_text("");
+                        }
+                        // The board resets test mode on every connection; re-send it.
+                        if is_connected && test_mode_check.is_active() && !monitor_check.is_active() {
+                            let _ = cmd_tx.send(Cmd::SetTestMode(true));
+                        }
+                    }
+
+                    UiMsg::ConnState { addr, connected } => {
+                        // Our own link is logged via `Connected`.
+                        let ours = [&connected_addr, &connecting_addr, &last_connected_addr]
+                            .iter()
+                            .any(|a| a.borrow().as_deref() == Some(addr.as_str()));
+                        if !ours {
+                            let state = if connected { "connected" } else { "disconnected" };
+                            let line = format!("{addr} {state} (outside this app).");
+                            append_log(&log_buf, &log_view, &line);
+                            append_conn_log(&conn_log_buf, &conn_log_view, &line);
+                        }
+                    }
+
+                    UiMsg::Connecting { addr } => {
+                        status_label.set_text(&format!("Connecting to {addr}..."));
+                        append_conn_log(&conn_log_buf, &conn_log_view, &format!("Connecting to {addr}..."));
+                        connecting_addr.replace(Some(addr));
+                        cancel_connect_btn.set_visible(true);
+                    }
+
+                    UiMsg::Reconnecting { attempt, max } => {
+                        status_label.set_text(&format!("Reconnect attempt {attempt}/{max}..."));
+                        append_conn_log(&conn_log_buf, &conn_log_view, &format!("Reconnect attempt {attempt}/{max}..."));
+                    }
+
+                    UiMsg::ReconnectFailed => {
+                        status_label.set_text("Reconnect failed");
+                        cancel_connect_btn.set_visible(false);
+                        append_log(&log_buf, &log_view, "Auto-reconnect gave up.");
+                        append_conn_log(&conn_log_buf, &conn_log_view, "Auto-reconnect gave up.");
+                    }
+
+                    UiMsg::Telemetry(t) => {
+                        let battery = match (t.has_battery, t.battery) {
+                            (false, _) => "not present".into(),
+                            (true, Some(b)) => format!("{b}%"),
+                            (true, None) => "?".into(),
+                        };
+                        let rssi = rssi_text(t.rssi, t.rssi_raw).unwrap_or_else(|| "?".into());
+                        let mut text = format!("Battery: {battery}  RSSI: {rssi}");
+                        if let Some((size, peak)) = t.stack {
+                            // Creeping up over a long run means something keeps growing.
+                            let (peak_kib, size_kib) = (peak as f64 / 1024.0, size as f64 / 1024.0);
+                            text.push_str(&format!("  Stack: {peak_kib:.1}/{size_kib:.1} KiB peak"));
+                        }
+                        telemetry_label.set_text(&text);
+                    }
+
+                    UiMsg::Stats { writes_ok, writes_unacked, writes_err, per_sec } => {
+                        let unacked = match writes_unacked {
+                            0 => String::new(),
+                            n => format!(" ({n} without response)"),
+                        };
+                        stats_label.set_text(&format!(
+                            "Writes: {writes_ok} ok{unacked}, {writes_err} failed ({per_sec:.1}/s)"
+                        ));
+                    }
+
+                    UiMsg::LinkQuality(q) => {
+                        quality_bar.set_value(q as f64);
+                        quality_bar.set_tooltip_text(Some(&format!("Link quality: {q}/100")));
+                        quality_bar.set_visible(true);
+                    }
+
+                    UiMsg::WriteMode(mode) => led_frame.set_label(Some(mode.frame_label())),
+
+                    UiMsg::WriteVerified { sent, read, tries } if read == Some(sent) => {
+                        verify_label.set_text(&match tries {
+                            1 => "Verify: ok".to_string(),
+                            n => format!("Verify: ok after {n} tries"),
+                        });
+                    }
+
+                    UiMsg::WriteVerified { sent, read, tries } => {
+                        let read = read.map(|m| format!("0x{m:04x}")).unwrap_or_else(|| "nothing".into());
+                        let text = format!("Verify: wrote 0x{sent:04x}, read {read}");
+                        verify_label.set_markup(&format!("<span foreground=\"#c01c28\">{text}</span>"));
+                        let line = match tries {
+                            1 => format!("Write verify failed: sent 0x{sent:04x}, board reports {read}"),
+                            n => format!("LED mask 0x{sent:04x} NOT applied after {n} tries; board reports {read}"),
+                        };
+                        append_log(&log_buf, &log_view, &line);
+                    }
+
+                    UiMsg::Heartbeat(missed) => {
+                        heartbeat_label.set_text(&match missed {
+                            Some(0) => "Heartbeat: ok".to_string(),
+                            Some(n) => format!("Heartbeat: {n} missed"),
+                            None => String::new(),
+                        });
+                    }
+
+                    UiMsg::Features(f) => {
+                        // Without a report, each control is still gated on
+                        // its characteristic being present.
+                        let has = |bit| f.is_none_or(|f| f.has(bit));
+                        test_mode_check.set_visible(has(features::TEST_MODE));
+                        reboot_btn.set_visible(has(features::CONTROL));
+                        // Never probed: only boards that report it have it.
+                        self_test_btn.set_visible(f.is_some_and(|f| f.has(features::OUTPUT_TEST)));
+                        blink_grid.set_visible(has(features::BLINK));
+                        breathe_row.set_visible(has(features::BREATHE));
+                        playlist_row.set_visible(has(features::PLAYLIST));
+                        conn_row.set_visible(has(features::CONN_PARAMS));
+                        heartbeat_label.set_visible(has(features::HEARTBEAT));
+                    }
+
+                    UiMsg::BlinkPeriods(periods) => {
+                        echo_guard.quietly(|| {
+                            for (i, scale) in blink_scales.iter().enumerate() {
+                                scale.set_sensitive(periods.is_some());
+                                scale.set_value(periods.map_or(0, |p| p[i]) as f64);
+                            }
+                        });
+                    }
+
+                    UiMsg::Locked(state) => {
+                        lock_check.set_visible(state.is_some());
+                        lock_check.set_sensitive(state.is_some());
+                        echo_guard.quietly(|| lock_check.set_active(state == Some(true)));
+                        if state == Some(true) {
+                            append_log(&log_buf, &log_view, "Board is locked: LED writes will be ignored.");
+                        }
+                    }
+
+                    UiMsg::Staging(state) => {
+                        stage_check.set_visible(state.is_some());
+                        stage_check.set_sensitive(state.is_some());
+                        apply_btn.set_visible(state.is_some());
+                        apply_btn.set_sensitive(state == Some(true));
+                        echo_guard.quietly(|| stage_check.set_active(state == Some(true)));
+                    }
+
+                    UiMsg::Playlist(state) => {
+                        playlist_row.set_sensitive(state.is_some());
+                        let (steps, on) = state.unwrap_or_default();
+                        playlist_entry.set_text(&playlist::format(&steps));
+                        echo_guard.quietly(|| autoplay_check.set_active(on));
+                        if on {
+                            let line = "Board is on autoplay: LED writes and its buttons will be ignored.";
+                            append_log(&log_buf, &log_view, line);
+                        }
+                    }
+
+                    UiMsg::ConnParams(params) => {
+                        conn_row.set_sensitive(params.is_some());
+                        conn_params_label.set_text(&params.map(|p| format!("Now: {p}")).unwrap_or_default());
+                    }
+
+                    UiMsg::Autoplay(on) => {
+                        echo_guard.quietly(|| autoplay_check.set_active(on));
+                    }
+
+                    UiMsg::SelfTest { tested, passed } => {
+                        let labels = settings.borrow().led_labels.clone();
+                        let items: Vec<String> = (0..16)
+                            .filter(|i| tested & (1 << i) != 0)
+                            .map(|i| {
+                                let mark = if passed & (1 << i) != 0 { "\u{2714}" } else { "\u{2718}" };
+                                format!("{} {mark}", led_label(&labels, i))
+                            })
+                            .collect();
+                        self_test_label.set_text(&items.join("  "));
+                        let (ok, total) = (passed.count_ones(), tested.count_ones());
+                        append_log(&log_buf, &log_view, &format!("Self-test: {ok}/{total} LED output(s) passed."));
+                    }
+
+                    UiMsg::Breathe(state) => {
+                        breathe_row.set_sensitive(state.is_some());
+                        let (mask, period_ms) = state.unwrap_or((0, BREATHE_MIN_MS));
+                        echo_guard.quietly(|| {
+                            for (i, check) in breathe_checks.iter().enumerate() {
+                                check.set_active(mask & (1 << i) != 0);
+                            }
+                            breathe_period.set_value(period_ms as f64);
+                        });
+                    }
+
+                    UiMsg::Recording(on) => {
+                        echo_guard.quietly(|| record_btn.set_active(on));
+                        record_btn.set_label(if on { "Stop recording" } else { "Record" });
+                    }
+
+                    UiMsg::Replaying(on) => {
+                        replaying.set(on);
+                        replay_btn.set_label(if on { "Stop replay" } else { "Replay\u{2026}" });
+                        record_btn.set_sensitive(!on);
+                    }
+
+                    UiMsg::Soaking(on) => {
+                        soak_action.set_enabled(!on);
+                        stop_soak_action.set_enabled(on);
+                    }
+
+                    UiMsg::AdapterPresent(present) => {
+                        status_label.set_text(if present { "Idle" } else { "No Bluetooth adapter" });
+                        adapter_present.set(present);
+                        scan_action.set_enabled(present && !scanning.get());
+                        quick_connect_action.set_enabled(present && !scanning.get());
+                        bench_action.set_enabled(present && !scanning.get());
+                        if present && auto_scan_pending.take() {
+                            let _ = cmd_tx.send(Cmd::Scan(RssiFilter::from_settings(&settings.borrow())));
+                            status_label.set_text("Scanning...");
+                        }
+                    }
+
+                    UiMsg::Scanning(on) => {
+                        scanning.set(on);
+                        scan_action.set_enabled(adapter_present.get() && !on);
+                        quick_connect_action.set_enabled(adapter_present.get() && !on);
+                        bench_action.set_enabled(adapter_present.get() && !on);
+                    }
+
+                    UiMsg::SelectDevice(addr) => {
+                        let row = (0..).map_while(|i| devices_list.row_at_index(i)).find(|r| r.widget_name() == addr);
+                        if let Some(row) = row {
+                            devices_list.select_row(Some(&row));
+                            row.grab_focus();
+                        }
+                    }
+
+                    UiMsg::Subscribed(on) => {
+                        notify_label.set_text(if on { "Notifications: on" } else { "Notifications: off" });
+                    }
+
+                    UiMsg::LedCount(count) => {
+                        if count as usize != leds.borrow().len() {
+                            append_log(&log_buf, &log_view, &format!("Board has {count} LED(s)."));
+                            let labels = settings.borrow().led_labels.clone();
+                            rebuild_led_toggles(&led_grid, &all_on, &all_off, &leds, count, &labels, &send_mask);
+                            let enabled = link_state.get() == LinkState::Connected;
+                            set_led_controls_enabled(&leds.borrow(), &all_on, &all_off, enabled, present_leds.get());
+                            led_preview.queue_draw();
+                        }
+                    }
+
+                    UiMsg::PresentLeds(present) => {
+                        let count = leds.borrow().len();
+                        let absent: Vec<String> =
+                            (0..count).filter(|i| present & (1 << i) == 0).map(|i| format!("LED{}", i + 1)).collect();
+                        if !absent.is_empty() {
+                            let line = format!("Not fitted on this board: {}.", absent.join(", "));
+                            append_log(&log_buf, &log_view, &line);
+                        }
+                        present_leds.set(present);
+                        let enabled = link_state.get() == LinkState::Connected;
+                        set_led_controls_enabled(&leds.borrow(), &all_on, &all_off, enabled, present);
+                    }
+
+                    UiMsg::LedCharNotFound { addr } => {
+                        // Discovery sometimes races on BlueZ; a second connect
+                        // usually sees the full attribute table.
+                        let dialog = gtk::AlertDialog::builder()
+                            .message("LED characteristic not found")
+                            .detail(format!(
+                                "{addr} connected, but doesn't expose the LED characteristic \
+                                 (see the log for what was found).\n\n\
+                                 Service discovery can be incomplete on the first try. Re-discover?"
+                            ))
+                            .buttons(["Cancel", "Re-discover"])
+                            .cancel_button(0)
+                            .default_button(1)
+                            .modal(true)
+                            .build();
+                        let cmd_tx = cmd_tx.clone();
+                        dialog.choose(Some(&window), None::<&gtk::gio::Cancellable>, move |res| {
+                            if let Ok(1) = res {
+                                let _ = cmd_tx.send(Cmd::Connect { addr });
+                            }
+                        });
+                    }
+
+                    UiMsg::MaskState { mask, notified } => {
+                        append_log(&log_buf, &log_view, &format!("Board LED mask: 0x{mask:04x}"));
+                        set_toggles_from_code(&leds.borrow(), mask, &echo_guard);
+                        board_mask.set(mask);
+                        led_preview.queue_draw();
+                        if let Some(preview) = row_preview.borrow().upgrade() {
+                            preview.queue_draw();
+                        }
+                        if notified {
+                            // A later notification pushes the deadline out, so
+                            // only the last timeout actually clears the outline.
+                            notify_flash_until.set(Some(Instant::now() + NOTIFY_FLASH));
+                            let led_preview = led_preview.clone();
+                            gtk::glib::timeout_add_local_once(NOTIFY_FLASH, move || led_preview.queue_draw());
+                        }
+                    }
+                }
+            }
+
+            if favorites_dirty.take() {
+                let favorites = settings.borrow().favorites.clone();
+                pin_favorites(&mut devices.borrow_mut(), &favorites);
+                render_device_rows(&devices_list, &devices.borrow(), &new_until.borrow(), &favorites, connected_addr.borrow().as_deref(), &row_actions);
+            }
+
+            gtk::glib::ControlFlow::Continue
+        });
+    }
+
+    append_log(&log_buf, &log_view, "Ready. Click Scan.");
+}
+
+fn device_row_text(d: &DeviceInfo) -> String {
+    let name = match &d.name {
+        Some(name) => name.clone(),
+        None if d.resolving => "(resolving...)".into(),
+        None => "(no name)".into(),
+    };
+    let rssi = rssi_text(d.rssi, d.rssi_raw).unwrap_or_else(|| "? dBm".into());
+    let badge = if d.controllable { "  [LED]" } else { "" };
+    let cached = if d.cached { "  (cached)" } else { "" };
+    format!("{name}  |  {}  |  {rssi}{badge}{cached}", d.addr)
+}
+
+/// "-62 dBm", plus the raw reading when smoothing has moved away from it.
+fn rssi_text(smoothed: Option<i16>, raw: Option<i16>) -> Option<String> {
+    let smoothed = smoothed?;
+    Some(match raw {
+        Some(raw) if raw != smoothed => format!("{smoothed} dBm (now {raw})"),
+        _ => format!("{smoothed} dBm"),
+    })
+}
+
+/// List section for a device: favorites, then controllable boards, then the rest.
+fn device_group(d: &DeviceInfo, favorites: &[String]) -> u8 {
+    if favorites.contains(&d.addr) {
+        0
+    } else if d.controllable {
+        1
+    } else {
+        2
+    }
+}
+
+/// Move favorites to the front, otherwise keeping the worker's sort order.
+fn pin_favorites(devices: &mut [DeviceInfo], favorites: &[String]) {
+    devices.sort_by_key(|d| !favorites.contains(&d.addr));
+}
+
+/// Rebuild the device rows, keeping the selection on the same address.
+/// Devices still listed in `new_until` are shown bold with a NEW badge.
+/// Each row gets a star toggle; favorites also get a one-click Connect.
+/// Right-clicking a row offers to hide the device.
+fn render_device_rows(
+    list: &gtk::ListBox,
+    devices: &[DeviceInfo],
+    new_until: &HashMap<String, Instant>,
+    favorites: &[String],
+    connected: Option<&str>,
+    actions: &RowActions,
+) {
+    // Rows are named after the device address so we can find the selection again.
+    let selected = list.selected_row().map(|r| r.widget_name().to_string());
+
+    // Clear listbox (GTK4: remove children manually)
+    while let Some(child) = list.first_child() {
+        list.remove(&child);
+    }
+
+    for d in devices {
+        let is_connected = connected == Some(d.addr.as_str());
+        let text = gtk::glib::markup_escape_text(&device_row_text(d));
+        let mut markup = if new_until.contains_key(&d.addr) { format!("<b>{text}  [NEW]</b>") } else { text.to_string() };
+        if d.cached {
+            markup = format!("<span alpha=\"55%\">{markup}</span>");
+        }
+        if is_connected {
+            markup.push_str("  <span foreground=\"#2e9e44\">\u{25cf} connected</span>");
+        }
+        let label = gtk::Label::new(None);
+        label.set_markup(&markup);
+        label.set_xalign(0.0);
+        label.set_hexpand(true);
+
+        let favorite = favorites.contains(&d.addr);
+        let star = gtk::ToggleButton::with_label(if favorite { "\u{2605}" } else { "\u{2606}" });
+        star.set_active(favorite);
+        star.set_has_frame(false);
+        star.set_tooltip_text(Some(if favorite { "Unpin" } else { "Pin to top" }));
+        {
+            let toggle = actions.toggle_favorite.clone();
+            let addr = d.addr.clone();
+            star.connect_toggled(move |_| toggle(&addr));
+        }
+
+        let content = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        content.append(&star);
+        content.append(&label);
+        if is_connected {
+            content.append(&(actions.mask_preview)());
+        }
+        if favorite {
+            // Connecting again would only drop and re-open the same link.
+            let connect = gtk::Button::with_label(if is_connected { "Connected" } else { "Connect" });
+            connect.set_sensitive(!is_connected);
+            let on_connect = actions.connect.clone();
+            let addr = d.addr.clone();
+            connect.connect_clicked(move |_| on_connect(&addr));
+            content.append(&connect);
+        }
+
+        let row = gtk::ListBoxRow::new();
+        row.set_widget_name(&d.addr);
+        row.set_child(Some(&content));
+        row.add_controller(hide_menu(&row, &d.addr, &actions.hide));
+        list.append(&row);
+
+        if selected.as_deref() == Some(d.addr.as_str()) {
+            list.select_row(Some(&row));
+        }
+    }
+}
+
+/// Right-click handler for a device row: a popover with "Hide this device".
+/// The popover is built per click and unparented when it closes, so rows
+/// can be dropped by the next render without leaving it behind.
+fn hide_menu(row: &gtk::ListBoxRow, addr: &str, hide: &Rc<dyn Fn(&str)>) -> gtk::GestureClick {
+    let gesture = gtk::GestureClick::new();
+    gesture.set_button(gtk::gdk::BUTTON_SECONDARY);
+    let row = row.downgrade();
+    let addr = addr.to_string();
+    let hide = hide.clone();
+    gesture.connect_pressed(move |_, _, x, y| {
+        let Some(row) = row.upgrade() else { return };
+        let button = gtk::Button::with_label("Hide this device");
+        button.set_has_frame(false);
+        let popover = gtk::Popover::new();
+        popover.set_child(Some(&button));
+        popover.set_parent(&row);
+        popover.set_pointing_to(Some(&gtk::gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+        popover.connect_closed(|p| {
+            let p = p.clone();
+            gtk::glib::idle_add_local_once(move || p.unparent());
+        });
+        {
+            let popover = popover.clone();
+            let hide = hide.clone();
+            let addr = addr.clone();
+            button.connect_clicked(move |_| {
+                popover.popdown();
+                hide(&addr);
+            });
+        }
+        popover.popup();
+    });
+    gesture
+}
+
+/// The device behind a row built by `render_device_rows`, by address (the
+/// row's widget name) rather than position, so it stays right whatever the
+/// list order is.
+fn row_device<'a>(row: &gtk::ListBoxRow, devices: &'a [DeviceInfo]) -> Option<&'a DeviceInfo> {
+    device_by_addr(&row.widget_name(), devices)
+}
+
+fn device_by_addr<'a>(addr: &str, devices: &'a [DeviceInfo]) -> Option<&'a DeviceInfo> {
+    devices.iter().find(|d| d.addr == addr)
+}
+
+/// The text label inside a row built by `render_device_rows`.
+fn row_label(row: &gtk::ListBoxRow) -> Option<gtk::Label> {
+    let content = row.child().and_downcast::<gtk::Box>()?;
+    content.first_child()?.next_sibling().and_downcast()
+}
+
+/// Replace the LED toggles with `count` fresh ones (clamped to the 16 LEDs a
+/// two-byte mask can address), with All On / All Off split across the row below.
+fn rebuild_led_toggles(
+    grid: &gtk::Grid,
+    all_on: &gtk::Button,
+    all_off: &gtk::Button,
+    leds: &RefCell<Vec<gtk::ToggleButton>>,
+    count: u8,
+    labels: &[String],
+    on_toggle: &Rc<dyn Fn()>,
+) {
+    for t in leds.borrow_mut().drain(..) {
+        grid.remove(&t);
+    }
+    for b in [all_on, all_off] {
+        if b.parent().is_some() {
+            grid.remove(b);
+        }
+    }
+
+    let count = count.clamp(1, MAX_LED_COUNT) as i32;
+    for i in 0..count {
+        let t = gtk::ToggleButton::with_label(&led_label(labels, i as usize));
+        let f = on_toggle.clone();
+        t.connect_toggled(move |_| f());
+        grid.attach(&t, i, 0, 1, 1);
+        leds.borrow_mut().push(t);
+    }
+
+    let half = count.max(2) / 2;
+    grid.attach(all_on, 0, 1, half, 1);
+    grid.attach(all_off, half, 1, (count - half).max(1), 1);
+}
+
+/// Caption for toggle `idx`: the user's label, or "LED<n>".
+fn led_label(labels: &[String], idx: usize) -> String {
+    match labels.get(idx) {
+        Some(label) if !label.is_empty() => label.clone(),
+        _ => format!("LED{}", idx + 1),
+    }
+}
+
+fn blink_caption(labels: &[String], idx: usize) -> String {
+    format!("{} blink (ms, 0 = solid)", led_label(labels, idx))
+}
+
+/// Link state as the UI sees it. `Disconnecting` starts with the Disconnect
+/// click and lasts until the worker's `Connected(false)`; LED input in that
+/// window is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LinkState {
+    Disconnected,
+    Connected,
+    Disconnecting,
+}
+
+/// One circle per LED, lit green when its bit in `mask` is set. `flash`
+/// adds a faint outline around the row (a notification just arrived).
+fn draw_led_preview(cr: &gtk::cairo::Context, height: i32, count: usize, mask: u16, flash: bool) {
+    let radius = (height as f64 / 2.0 - 2.0).max(2.0);
+    let spacing = radius * 3.0;
+
+    if flash && count > 0 {
+        let width = (count - 1) as f64 * spacing + 2.0 * radius + 3.0;
+        cr.rectangle(0.5, 0.5, width, height as f64 - 1.0);
+        cr.set_source_rgba(0.2, 0.85, 0.3, 0.45);
+        cr.set_line_width(1.0);
+        let _ = cr.stroke();
+    }
+
+    for i in 0..count {
+        let cx = radius + 2.0 + i as f64 * spacing;
+        let cy = height as f64 / 2.0;
+        cr.arc(cx, cy, radius, 0.0, std::f64::consts::TAU);
+        if mask & (1 << i) != 0 {
+            cr.set_source_rgb(0.2, 0.85, 0.3);
+        } else {
+            cr.set_source_rgb(0.25, 0.25, 0.25);
+        }
+        let _ = cr.fill_preserve();
+        cr.set_source_rgb(0.1, 0.1, 0.1);
+        cr.set_line_width(1.0);
+        let _ = cr.stroke();
+    }
+}
+
+/// Mask with one bit set per active toggle (toggle i => bit i).
+fn toggles_mask(toggles: &[gtk::ToggleButton]) -> u16 {
+    toggles
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| t.is_active())
+        .fold(0u16, |m, (i, _)| m | (1 << i))
+}
+
+/// Mask with the low `count` bits set.
+fn full_mask(count: usize) -> u16 {
+    ((1u32 << count.min(16)) - 1) as u16
+}
+
+/// LED mask as written to the board: one byte when it fits, so firmware
+/// with the original single-byte characteristic keeps working.
+fn mask_bytes(mask: u16) -> Vec<u8> {
+    match u8::try_from(mask) {
+        Ok(low) => vec![low],
+        Err(_) => mask.to_le_bytes().to_vec(),
+    }
+}
+
+/// LED mask as read or notified: low byte, then an optional high byte.
+fn mask_from_bytes(bytes: &[u8]) -> Option<u16> {
+    let lo = *bytes.first()?;
+    let hi = bytes.get(1).copied().unwrap_or(0);
+    Some(u16::from_le_bytes([lo, hi]))
+}
+
+fn set_led_controls_enabled(
+    toggles: &[gtk::ToggleButton],
+    all_on: &gtk::Button,
+    all_off: &gtk::Button,
+    enabled: bool,
+    present: u16,
+) {
+    for (idx, t) in toggles.iter().enumerate() {
+        let fitted = present & (1 << idx) != 0;
+        t.set_sensitive(enabled && fitted);
+        t.set_tooltip_text((!fitted).then_some("Not fitted on this board"));
+    }
+    all_on.set_sensitive(enabled);
+    all_off.set_sensitive(enabled);
+}
+
+/// Reflect `mask` on the LED toggles without triggering a write from their
+/// `toggled` handlers (see `echo_guard` in `build_ui`).
+fn set_toggles_from_code(toggles: &[gtk::ToggleButton], mask: u16, echo_guard: &EchoGuard) {
+    echo_guard.quietly(|| {
+        for (i, t) in toggles.iter().enumerate() {
+            t.set_active(mask & (1 << i) != 0);
+        }
+    });
+}
+
+/// Show `text` for `TOAST_TIME`, replacing any toast still up.
+fn show_toast(toast: &gtk::Revealer, label: &gtk::Label, serial: &Rc<Cell<u64>>, text: &str) {
+    label.set_text(text);
+    toast.set_reveal_child(true);
+    let this = serial.get() + 1;
+    serial.set(this);
+    let toast = toast.clone();
+    let serial = serial.clone();
+    gtk::glib::timeout_add_local_once(TOAST_TIME, move || {
+        if serial.get() == this {
+            toast.set_reveal_child(false);
+        }
+    });
+}
+
+/// Append one line; scrolls to it only if the view was already showing the
+/// end, so reading further up isn't interrupted.
+fn append_log(buf: &gtk::TextBuffer, view: &gtk::TextView, line: &str) {
+    append_log_tagged(buf, view, line, "");
+}
+
+/// Tag for firmware-reported errors.
+const LOG_ERROR_TAG: &str = "error";
+
+/// `append_log` with `tag` applied to the line; "" for none.
+fn append_log_tagged(buf: &gtk::TextBuffer, view: &gtk::TextView, line: &str, tag: &str) {
+    let mut text = line.to_string();
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+
+    let follow = view.vadjustment().is_none_or(|adj| is_at_bottom(&adj));
+    let mut end = buf.end_iter();
+    if tag.is_empty() {
+        buf.insert(&mut end, &text);
+    } else {
+        buf.insert_with_tags_by_name(&mut end, &text, &[tag]);
+    }
+
+    if follow {
+        scroll_log_to_end(view);
+    }
+}
+
+/// Named mark kept at the end of a log buffer for scrolling.
+const LOG_END_MARK: &str = "log-end";
+
+fn scroll_log_to_end(view: &gtk::TextView) {
+    let buf = view.buffer();
+    let end = buf.end_iter();
+    let mark = buf
+        .mark(LOG_END_MARK)
+        .unwrap_or_else(|| buf.create_mark(Some(LOG_END_MARK), &end, false));
+    buf.move_mark(&mark, &end);
+    view.scroll_mark_onscreen(&mark);
+}
+
+/// How far (px) from the end still counts as "at the bottom".
+const LOG_BOTTOM_SLACK: f64 = 24.0;
+
+fn is_at_bottom(adj: &gtk::Adjustment) -> bool {
+    adj.value() + adj.page_size() >= adj.upper() - LOG_BOTTOM_SLACK
+}
+
+/// Show `indicator` when lines are appended to `view` while it's scrolled
+/// up; hide it again once the user is back at the bottom (or clicks it).
+fn watch_log_tail(view: &gtk::TextView, indicator: &gtk::Button) {
+    let Some(adj) = view.vadjustment() else { return };
+    {
+        // Runs before `append_log` scrolls, so this is the state the line
+        // arrived in.
+        let adj = adj.clone();
+        let indicator = indicator.clone();
+        view.buffer().connect_changed(move |_| {
+            if !is_at_bottom(&adj) {
+                indicator.set_visible(true);
+            }
+        });
+    }
+    {
+        let indicator = indicator.clone();
+        adj.connect_value_changed(move |adj| {
+            if is_at_bottom(adj) {
+                indicator.set_visible(false);
+            }
+        });
+    }
+    let view = view.clone();
+    indicator.connect_clicked(move |b| {
+        scroll_log_to_end(&view);
+        b.set_visible(false);
+    });
+}
+
+/// `append_log` for the connection log: each line gets the local time.
+fn append_conn_log(buf: &gtk::TextBuffer, view: &gtk::TextView, line: &str) {
+    let now = gtk::glib::DateTime::now_local().and_then(|t| t.format("%H:%M:%S"));
+    let stamp = now.map(|s| s.to_string()).unwrap_or_default();
+    append_log(buf, view, &format!("{stamp} {line}"));
+}
+
+/// "45s", "3m12s", "2h05m".
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Tag `highlight_log_matches` puts on search hits.
+const LOG_MATCH_TAG: &str = "search-match";
+
+/// Re-highlight every case-insensitive occurrence of `needle` in the log;
+/// returns how many there are. Lines logged afterwards aren't highlighted
+/// until the search changes.
+fn highlight_log_matches(buf: &gtk::TextBuffer, needle: &str) -> usize {
+    let (start, end) = buf.bounds();
+    buf.remove_tag_by_name(LOG_MATCH_TAG, &start, &end);
+    if needle.is_empty() {
+        return 0;
+    }
+    let mut count = 0;
+    let mut from = buf.start_iter();
+    while let Some((m_start, m_end)) = from.forward_search(needle, gtk::TextSearchFlags::CASE_INSENSITIVE, None) {
+        buf.apply_tag_by_name(LOG_MATCH_TAG, &m_start, &m_end);
+        count += 1;
+        from = m_end;
+    }
+    count
+}
+
+/// Select the match after (or before) the current selection, wrapping
+/// around at either end, and scroll it into view.
+fn jump_to_log_match(buf: &gtk::TextBuffer, view: &gtk::TextView, needle: &str, forward: bool) {
+    if needle.is_empty() {
+        return;
+    }
+    let flags = gtk::TextSearchFlags::CASE_INSENSITIVE;
+    let cursor = buf.iter_at_mark(&buf.get_insert());
+    let found = if forward {
+        let from = buf.selection_bounds().map_or(cursor, |(_, end)| end);
+        from.forward_search(needle, flags, None).or_else(|| buf.start_iter().forward_search(needle, flags, None))
+    } else {
+        let from = buf.selection_bounds().map_or(cursor, |(start, _)| start);
+        from.backward_search(needle, flags, None).or_else(|| buf.end_iter().backward_search(needle, flags, None))
+    };
+    let Some((mut start, end)) = found else { return };
+    buf.select_range(&start, &end);
+    view.scroll_to_iter(&mut start, 0.1, false, 0.0, 0.0);
+}
+
+async fn ble_worker(
+    mut rx: tokio_mpsc::UnboundedReceiver<Cmd>,
+    ui_tx: mpsc::Sender<UiMsg>,
+    settings: Settings,
+) -> Result<()> {
+    let manager = Manager::new().await.context("btleplug Manager::new")?;
+
+    // A USB dongle can come and go; without one the worker idles and keeps
+    // looking (see `adapter_tick`).
+    let (mut adapter, mut events) = match open_adapter(&manager).await {
+        Ok((a, e)) => (Some(a), Some(e)),
+        Err(e) => {
+            let _ = ui_tx.send(UiMsg::Log(format!("{e:#}; waiting for a Bluetooth adapter...")));
+            (None, None)
+        }
+    };
+
+    let _ = ui_tx.send(UiMsg::Log("BLE worker started.".into()));
+    let _ = ui_tx.send(UiMsg::AdapterPresent(adapter.is_some()));
+
+    let mut scan_cache = ScanCache::default();
+    let mut adapter_id = match &adapter {
+        Some(a) => adapter_id_of(a).await,
+        None => String::new(),
+    };
+    // Filter of the latest scan, for the background refresh.
+    let mut scan_filter = RssiFilter::from_settings(&settings);
+    // Set while that refresh runs (see `adapter_tick`).
+    let mut cache_refresh_until: Option<Instant> = None;
+
+    let mut led_char_uuids = settings.led_char_uuids.clone();
+
+    // Optional exporter; sees the same telemetry as the UI.
+    #[cfg(feature = "influx")]
+    let telemetry_export = influx::InfluxConfig::from_settings(&settings).map(influx::spawn);
+    #[cfg(not(feature = "influx"))]
+    let telemetry_export: Option<tokio_mpsc::UnboundedSender<Telemetry>> = None;
+
+    let mut last_scan: Vec<(DeviceInfo, Peripheral)> = Vec::new();
+    let mut connected: Option<Link> = None;
+    // Set while live scanning; the filter is re-applied on every refresh.
+    let mut live_scan: Option<RssiFilter> = None;
+    let mut policy = ReconnectPolicy::from_settings(&settings);
+    let mut rssi_smoother = RssiSmoother::new(settings.rssi_smoothing);
+    let mut scan_duplicates = settings.scan_duplicates;
+    let mut device_filter = DeviceFilter::from_settings(&settings);
+    let mut reconnect: Option<Reconnect> = None;
+    // Commands that arrived while a connect was in flight, run afterwards.
+    let mut deferred: VecDeque<Cmd> = VecDeque::new();
+    // The next scan was started by `Cmd::QuickConnect`.
+    let mut quick_connect = false;
+
+    let mut adapter_tick = tokio::time::interval(ADAPTER_CHECK);
+    let mut telemetry_tick = tokio::time::interval(TELEMETRY_INTERVAL);
+    let mut stats_tick = tokio::time::interval(STATS_INTERVAL);
+    // Idle while disconnected; `reset()` on connect gives the board a full
+    // period before the first check.
+    let mut heartbeat_tick = tokio::time::interval(HEARTBEAT_PERIOD);
+    heartbeat_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut heartbeat_limit = settings.heartbeat_max_missed;
+    let mut notify_streams = settings.notify_streams;
+    let mut monitor_only = false;
+    let mut quality_weights = quality::Weights::from_settings(&settings);
+    let mut write_stats = WriteStats::default();
+    // Off by default: the readback costs a round trip per write.
+    let mut verify_writes = false;
+    let mut verified_mode = false;
+    // Of the latest mask write, to tell the UI when it changes.
+    let mut write_mode: Option<WriteMode> = None;
+    let mut recording: Option<session::Recorder> = None;
+    // Steps still to replay, each with the gap before it; the front one is
+    // due at `replay_next`.
+    let mut replay: VecDeque<(Duration, Cmd)> = VecDeque::new();
+    let mut replay_next: Option<tokio::time::Instant> = None;
+
+    loop {
+        let reconnect_at = reconnect.as_ref().map(|r| r.next_at);
+        let replay_at = replay_next;
+        // Live scan, or the background scan after an adapter came back.
+        let refreshing = live_scan.is_some() || cache_refresh_until.is_some();
+        let cmd = if let Some(cmd) = deferred.pop_front() {
+            Some(cmd)
+        } else {
+            tokio::select! {
+                cmd = rx.recv() => cmd,
+
+                _ = tokio::time::sleep_until(replay_at.unwrap_or_else(tokio::time::Instant::now)), if replay_at.is_some() => {
+                    let (_, cmd) = replay.pop_front().unwrap();
+                    replay_next = replay.front().map(|(gap, _)| tokio::time::Instant::now() + *gap);
+                    if replay_next.is_none() {
+                        let _ = ui_tx.send(UiMsg::Log("Replay finished.".into()));
+                        let _ = ui_tx.send(UiMsg::Replaying(false));
+                    }
+                    Some(cmd)
+                }
+
+                // The adapter's view of every link, ours or not; also how we
+                // notice ours dropping underneath us.
+                event = next_event(&mut events) => {
+                    // The stream ends when the adapter goes away; `adapter_tick` cleans up.
+                    let Some(event) = event else {
+                        events = None;
+                        continue;
+                    };
+                    let (id, is_up) = match event {
+                        CentralEvent::DeviceConnected(id) => (id, true),
+                        CentralEvent::DeviceDisconnected(id) => (id, false),
+                        _ => continue,
+                    };
+                    let _ = ui_tx.send(UiMsg::ConnState { addr: id.to_string(), connected: is_up });
+                    if is_up || !connected.as_ref().is_some_and(|l| l.peri.id() == id) {
+                        continue;
+                    }
+
+                    // Not user-initiated: Cmd::Disconnect takes the link first.
+                    let link = connected.take().unwrap();
+                    let line = format!("Connection to {} lost.", link.addr);
+                    reconnect = drop_lost_link(link, line, &policy, &ui_tx).await;
+                    continue;
+                }
+
+                _ = heartbeat_tick.tick(), if connected.as_ref().is_some_and(|l| l.heartbeat.is_some()) => {
+                    let link = connected.as_mut().unwrap();
+                    let missed = link.heartbeat.as_mut().unwrap().check();
+                    let _ = ui_tx.send(UiMsg::Heartbeat(Some(missed)));
+                    if heartbeat_limit == 0 || missed < heartbeat_limit {
+                        continue;
+                    }
+
+                    // Still "connected" as far as the stack knows, but the
+                    // firmware has stopped; don't wait for a write to fail.
+                    let link = connected.take().unwrap();
+                    let line = format!("{} missed {missed} heartbeats; dropping the link.", link.addr);
+                    reconnect = drop_lost_link(link, line, &policy, &ui_tx).await;
+                    continue;
+                }
+
+                _ = tokio::time::sleep_until(reconnect_at.unwrap_or_else(tokio::time::Instant::now)), if reconnect_at.is_some() => {
+                    let r = reconnect.take().unwrap();
+                    let _ = ui_tx.send(UiMsg::Reconnecting { attempt: r.attempt, max: policy.max_attempts });
+                    let _ = ui_tx.send(UiMsg::Log(format!("Reconnecting to {} (attempt {}/{})...", r.addr, r.attempt, policy.max_attempts)));
+
+                    let _ = ui_tx.send(UiMsg::Connecting { addr: r.addr.clone() });
+                    let streams = effective_streams(notify_streams, monitor_only);
+                    let open = open_link(r.peri.clone(), &r.addr, &led_char_uuids, streams, &ui_tx);
+                    let result = match cancellable_connect(open, &mut rx, &mut deferred).await {
+                        ConnectOutcome::Finished(result) => *result,
+                        ConnectOutcome::Cancelled => {
+                            r.peri.disconnect().await.ok();
+                            let _ = ui_tx.send(UiMsg::Log("Reconnect cancelled.".into()));
+                            let _ = ui_tx.send(UiMsg::Connected(false));
+                            continue;
+                        }
+                    };
+                    match result {
+                        Ok(Some(link)) => {
+                            connected = Some(link);
+                            write_stats = WriteStats::default();
+                            write_mode = None;
+                            heartbeat_tick.reset();
+                            let _ = ui_tx.send(UiMsg::Connected(true));
+                        }
+                        // Board is reachable but no longer has the LED characteristic; retrying won't help.
+                        Ok(None) => {}
+                        Err(e) => {
+                            let _ = ui_tx.send(UiMsg::Log(format!("Reconnect failed: {e:#}")));
+                            r.peri.disconnect().await.ok();
+                            if r.attempt < policy.max_attempts {
+                                let attempt = r.attempt + 1;
+                                let next_at = tokio::time::Instant::now() + policy.delay(attempt);
+                                reconnect = Some(Reconnect { attempt, next_at, ..r });
+                            } else {
+                                let _ = ui_tx.send(UiMsg::ReconnectFailed);
+                            }
+                        }
+                    }
+                    continue;
+                }
+
+                _ = telemetry_tick.tick(), if connected.is_some() => {
+                    let link = connected.as_mut().unwrap();
+                    let mut t = read_telemetry(link, &ui_tx).await;
+                    t.rssi = t.rssi_raw.map(|r| rssi_smoother.update(&t.addr, r));
+                    link.rssi = t.rssi;
+                    if let Some(export) = &telemetry_export {
+                        let _ = export.send(t.clone());
+                    }
+                    let _ = ui_tx.send(UiMsg::Telemetry(t));
+                    if let Some(ch) = &link.conn_params {
+                        if let Some(params) = read_conn_params(&link.peri, ch, &ui_tx).await {
+                            let _ = ui_tx.send(UiMsg::ConnParams(Some(params)));
+                        }
+                    }
+                    continue;
+                }
+
+                _ = stats_tick.tick(), if connected.is_some() => {
+                    let per_sec = (write_stats.ok - write_stats.ok_at_last_report) as f64 / STATS_INTERVAL.as_secs_f64();
+                    let link = connected.as_ref().unwrap();
+                    let ok = write_stats.ok - write_stats.ok_at_last_report;
+                    let err = write_stats.err - write_stats.err_at_last_report;
+                    let missed = link.heartbeat.as_ref().map(|h| h.missed);
+                    write_stats.ok_at_last_report = write_stats.ok;
+                    write_stats.err_at_last_report = write_stats.err;
+                    let _ = ui_tx.send(UiMsg::Stats {
+                        writes_ok: write_stats.ok,
+                        writes_unacked: write_stats.unacked,
+                        writes_err: write_stats.err,
+                        per_sec,
+                    });
+                    if let Some(q) = quality::score(&quality_weights, link.rssi, (ok, err), missed) {
+                        let _ = ui_tx.send(UiMsg::LinkQuality(q));
+                    }
+                    continue;
+                }
+
+                _ = adapter_tick.tick() => {
+                    let Some(a) = &adapter else {
+                        let Ok((a, e)) = open_adapter(&manager).await else { continue };
+                        let _ = ui_tx.send(UiMsg::Log("Bluetooth adapter found.".into()));
+                        let _ = ui_tx.send(UiMsg::AdapterPresent(true));
+                        adapter_id = adapter_id_of(&a).await;
+                        if live_scan.is_some() {
+                            if let Err(e) = start_scan_healing(&a, &ui_tx).await {
+                                let _ = ui_tx.send(UiMsg::Log(format!("Live scan failed to restart: {e:#}")));
+                            }
+                        } else {
+                            // Show what this adapter saw last time while a
+                            // short scan in the background catches up.
+                            let mut cached = scan_cache.cached(&adapter_id);
+                            cached.retain(|d| device_filter.accepts(d));
+                            if !cached.is_empty() {
+                                let line = format!("Listing {} cached device(s) while rescanning...", cached.len());
+                                let _ = ui_tx.send(UiMsg::Log(line));
+                                let _ = ui_tx.send(UiMsg::ScanResults(cached));
+                                match start_scan_healing(&a, &ui_tx).await {
+                                    Ok(()) => cache_refresh_until = Some(Instant::now() + CACHE_REFRESH_TIME),
+                                    Err(e) => {
+                                        let _ = ui_tx.send(UiMsg::Log(format!("Rescan failed: {e:#}")));
+                                    }
+                                }
+                            }
+                        }
+                        (adapter, events) = (Some(a), Some(e));
+                        continue;
+                    };
+                    if events.is_some() && a.adapter_info().await.is_ok() {
+                        continue;
+                    }
+
+                    // Unplugged (or bluetoothd lost it). Everything we hold
+                    // belongs to the old adapter.
+                    if let Some(link) = connected.take() {
+                        link.close().await;
+                        let _ = ui_tx.send(UiMsg::Subscribed(false));
+                        let _ = ui_tx.send(UiMsg::Connected(false));
+                    }
+                    reconnect = None;
+                    last_scan.clear();
+                    cache_refresh_until = None;
+                    (adapter, events) = (None, None);
+                    let _ = ui_tx.send(UiMsg::Log("Bluetooth adapter gone; waiting for it to come back...".into()));
+                    let _ = ui_tx.send(UiMsg::AdapterPresent(false));
+                    continue;
+                }
+
+                _ = tokio::time::sleep(LIVE_SCAN_REFRESH), if refreshing && adapter.is_some() => {
+                    let adapter = adapter.as_ref().unwrap();
+                    let filter = live_scan.unwrap_or(scan_filter);
+                    if live_scan.is_none() && cache_refresh_until.is_some_and(|until| Instant::now() >= until) {
+                        cache_refresh_until = None;
+                        adapter.stop_scan().await.ok();
+                    }
+                    let (infos, peris) = match collect_devices(adapter, filter, &mut rssi_smoother).await {
+                        Ok(found) => found,
+                        Err(e) => {
+                            let _ = ui_tx.send(UiMsg::Log(format!("Live scan refresh failed: {e:#}")));
+                            continue;
+                        }
+                    };
+                    last_scan = infos.into_iter().zip(peris).collect();
+
+                    let just_infos: Vec<DeviceInfo> = last_scan.iter().map(|(i, _)| i.clone()).collect();
+                    let mut list = scan_cache.merge(&adapter_id, &just_infos);
+                    list.retain(|d| device_filter.accepts(d));
+                    let _ = ui_tx.send(UiMsg::ScanResults(list));
+                    if scan_duplicates && live_scan.is_some() {
+                        restart_scan_for_duplicates(adapter, &ui_tx).await;
+                    }
+                    continue;
+                }
+            }
+        };
+        let Some(cmd) = cmd else { break };
+        let (cmd, mut reply) = match cmd {
+            Cmd::WithReply(cmd, tx) => (*cmd, Reply::new(Some(tx))),
+            cmd => (cmd, Reply::new(None)),
+        };
+        if monitor_only && cmd.writes() {
+            let _ = ui_tx.send(UiMsg::Log("Monitor only: not writing to the board.".into()));
+            reply.fail("monitor only");
+            continue;
+        }
+        // Never set during a replay, so replayed steps aren't re-recorded.
+        if let Some(rec) = &mut recording {
+            rec.record(&cmd);
+        }
+
+        match cmd {
+            Cmd::Scan(filter) => {
+                let Some(adapter) = &adapter else {
+                    let _ = ui_tx.send(UiMsg::Log("Can't scan: no Bluetooth adapter.".into()));
+                    reply.fail("no Bluetooth adapter");
+                    continue;
+                };
+                let quick = std::mem::take(&mut quick_connect);
+                let scan_time = if quick { QUICK_CONNECT_SCAN_TIME } else { SCAN_TIME };
+                scan_filter = filter;
+                let _ = ui_tx.send(UiMsg::Log(format!("Scanning ({}s)...", scan_time.as_secs())));
+                let _ = ui_tx.send(UiMsg::Scanning(true));
+                if let Err(e) = start_scan_healing(adapter, &ui_tx).await {
+                    let _ = ui_tx.send(UiMsg::Log(format!("Scan failed: {e:#}")));
+                    let _ = ui_tx.send(UiMsg::Scanning(false));
+                    send_cached_scan(&mut scan_cache, &adapter_id, &device_filter, &ui_tx);
+                    reply.fail(format!("scan failed: {e:#}"));
+                    continue;
+                }
+                tokio::time::sleep(scan_time).await;
+
+                let (infos, peris) = match collect_devices(adapter, filter, &mut rssi_smoother).await {
+                    Ok(found) => found,
+                    Err(e) => {
+                        let _ = ui_tx.send(UiMsg::Log(format!("Scan failed: {e:#}")));
+                        let _ = ui_tx.send(UiMsg::Scanning(false));
+                        send_cached_scan(&mut scan_cache, &adapter_id, &device_filter, &ui_tx);
+                        reply.fail(format!("scan failed: {e:#}"));
+                        continue;
+                    }
+                };
+                last_scan = infos.into_iter().zip(peris).collect();
+                reply.ok(format!("{} device(s)", last_scan.len()));
+
+                let just_infos: Vec<DeviceInfo> = last_scan.iter().map(|(i, _)| i.clone()).collect();
+                let mut list = scan_cache.merge(&adapter_id, &just_infos);
+                list.retain(|d| device_filter.accepts(d));
+                let _ = ui_tx.send(UiMsg::ScanResults(list));
+                let _ = ui_tx.send(UiMsg::Scanning(false));
+
+                if quick {
+                    let mut boards: Vec<&DeviceInfo> = last_scan
+                        .iter()
+                        .map(|(i, _)| i)
+                        .filter(|i| i.controllable && device_filter.accepts(i))
+                        .collect();
+                    boards.sort_by_key(|d| std::cmp::Reverse(d.rssi));
+                    match boards.as_slice() {
+                        [] => {
+                            let msg = "Quick connect: no LED board found.";
+                            let _ = ui_tx.send(UiMsg::Log(msg.into()));
+                            let _ = ui_tx.send(UiMsg::Toast(msg.into()));
+                        }
+                        [only] => {
+                            let who = only.name.as_deref().unwrap_or("LED board");
+                            let _ = ui_tx.send(UiMsg::Log(format!("Quick connect: found {who} ({}).", only.addr)));
+                            deferred.push_front(Cmd::Connect { addr: only.addr.clone() });
+                        }
+                        [best, ..] => {
+                            let msg = format!("Quick connect: {} LED boards found; pick one.", boards.len());
+                            let _ = ui_tx.send(UiMsg::Log(msg.clone()));
+                            let _ = ui_tx.send(UiMsg::Toast(msg));
+                            let _ = ui_tx.send(UiMsg::SelectDevice(best.addr.clone()));
+                        }
+                    }
+                }
+            }
+
+            Cmd::QuickConnect(filter) => {
+                // The scan does the rest once it has the list.
+                quick_connect = true;
+                deferred.push_front(Cmd::Scan(filter));
+            }
+
+            Cmd::BenchmarkDiscovery { addr, name } => {
+                let Some(adapter) = &adapter else {
+                    let _ = ui_tx.send(UiMsg::Log("Can't benchmark: no Bluetooth adapter.".into()));
+                    continue;
+                };
+                // Each run restarts scanning, and the runs read the adapter's
+                // event stream themselves, so a link's events would be lost.
+                if live_scan.is_some() || connected.is_some() {
+                    let msg = "Stop live scan and disconnect before benchmarking discovery.";
+                    let _ = ui_tx.send(UiMsg::Log(msg.into()));
+                    continue;
+                }
+                let _ = ui_tx.send(UiMsg::Log(format!(
+                    "Benchmarking discovery of {addr}: {DISCOVERY_BENCH_RUNS} scans..."
+                )));
+                let _ = ui_tx.send(UiMsg::Scanning(true));
+                let mut times = Vec::new();
+                for run in 1..=DISCOVERY_BENCH_RUNS {
+                    let line = match time_discovery(adapter, &mut events, &addr, name.as_deref(), &ui_tx).await {
+                        Ok(Some(t)) => {
+                            times.push(t);
+                            format!("Discovery run {run}/{DISCOVERY_BENCH_RUNS}: {} ms", t.as_millis())
+                        }
+                        Ok(None) => format!(
+                            "Discovery run {run}/{DISCOVERY_BENCH_RUNS}: not heard within {}s",
+                            DISCOVERY_BENCH_TIMEOUT.as_secs()
+                        ),
+                        Err(e) => {
+                            let _ = ui_tx.send(UiMsg::Log(format!("Discovery benchmark failed: {e:#}")));
+                            break;
+                        }
+                    };
+                    let _ = ui_tx.send(UiMsg::Log(line));
+                }
+                let _ = ui_tx.send(UiMsg::Scanning(false));
+                if let Some(summary) = describe_discovery_times(&times) {
+                    let _ = ui_tx.send(UiMsg::Toast(format!("Discovery of {addr}: {summary}")));
+                    let _ = ui_tx.send(UiMsg::Log(format!(
+                        "Discovery of {addr}: {summary} ({}/{DISCOVERY_BENCH_RUNS} runs found it)",
+                        times.len()
+                    )));
+                }
+            }
+
+            Cmd::Soak { addr, cycles } => {
+                let Some(adapter) = &adapter else {
+                    let _ = ui_tx.send(UiMsg::Log("Can't soak test: no Bluetooth adapter.".into()));
+                    continue;
+                };
+                if connected.is_some() {
+                    let _ = ui_tx.send(UiMsg::Log("Disconnect before starting a soak test.".into()));
+                    continue;
+                }
+                reconnect = None;
+                let listed = last_scan.iter().find(|(i, _)| i.addr == addr).map(|(_, p)| p.clone());
+                let peri = match listed {
+                    Some(peri) => peri,
+                    None => match lookup_peripheral(adapter, &addr, &ui_tx).await {
+                        Some(peri) => peri,
+                        None => {
+                            let _ = ui_tx.send(UiMsg::Log(format!("{addr} not found nearby.")));
+                            continue;
+                        }
+                    },
+                };
+
+                let _ = ui_tx.send(UiMsg::Log(format!("Soak test of {addr}: {cycles} cycles...")));
+                let _ = ui_tx.send(UiMsg::Soaking(true));
+                let mut tally = soak::Tally::default();
+                'cycles: for n in 1..=cycles {
+                    let started = Instant::now();
+                    let run = soak::cycle(&peri, &led_char_uuids, soak::cycle_mask(n));
+                    tokio::pin!(run);
+                    // Other commands wait for the test, like during a connect.
+                    let outcome = loop {
+                        tokio::select! {
+                            outcome = &mut run => break outcome,
+                            cmd = rx.recv() => match cmd {
+                                Some(Cmd::StopSoak) | None => {
+                                    peri.disconnect().await.ok();
+                                    let _ = ui_tx.send(UiMsg::Log("Soak test stopped.".into()));
+                                    break 'cycles;
+                                }
+                                Some(other) => deferred.push_back(other),
+                            },
+                        }
+                    };
+                    let _ = ui_tx.send(UiMsg::Log(soak::describe(n, cycles, &outcome, started.elapsed())));
+                    tally.record(&outcome);
+                    tokio::time::sleep(soak::PAUSE).await;
+                }
+                let summary = format!("Soak test of {addr}: {}", tally.summary());
+                let _ = ui_tx.send(UiMsg::Log(summary.clone()));
+                let _ = ui_tx.send(UiMsg::Toast(summary));
+                let _ = ui_tx.send(UiMsg::Soaking(false));
+            }
+
+            // Nothing to stop; a running test picks this up itself.
+            Cmd::StopSoak => {}
+
+            Cmd::LiveScan(Some(filter)) => {
+                let Some(adapter) = &adapter else {
+                    // Started once an adapter shows up.
+                    let _ = ui_tx.send(UiMsg::Log("Live scan waiting for a Bluetooth adapter.".into()));
+                    live_scan = Some(filter);
+                    continue;
+                };
+                if let Err(e) = start_scan_healing(adapter, &ui_tx).await {
+                    let _ = ui_tx.send(UiMsg::Log(format!("Live scan failed to start: {e:#}")));
+                    continue;
+                }
+                if live_scan.is_none() {
+                    let _ = ui_tx.send(UiMsg::Log("Live scan started.".into()));
+                    if scan_duplicates {
+                        log_duplicate_support(&ui_tx);
+                    }
+                }
+                live_scan = Some(filter);
+            }
+
+            Cmd::LiveScan(None) => {
+                if live_scan.take().is_some() {
+                    if let Some(adapter) = &adapter {
+                        adapter.stop_scan().await.ok();
+                    }
+                    let _ = ui_tx.send(UiMsg::Log("Live scan stopped.".into()));
+                }
+            }
+
+            Cmd::SetReconnectPolicy(p) => policy = p,
+
+            Cmd::SetRssiSmoothing(alpha) => rssi_smoother = RssiSmoother::new(alpha),
+
+            Cmd::SetScanDuplicates(on) => {
+                if on && !scan_duplicates && live_scan.is_some() {
+                    log_duplicate_support(&ui_tx);
+                }
+                scan_duplicates = on;
+            }
+
+            Cmd::SetDeviceFilter(f) => device_filter = f,
+
+            Cmd::SetLedCharUuids(uuids) => led_char_uuids = uuids,
+
+            Cmd::SetHeartbeatLimit(n) => heartbeat_limit = n,
+
+            Cmd::SetNotifyStreams(streams) => {
+                notify_streams = streams;
+                if let Some(link) = connected.as_mut() {
+                    link.set_streams(effective_streams(streams, monitor_only), &ui_tx).await;
+                }
+            }
+
+            Cmd::SetMonitorOnly(on) => {
+                monitor_only = on;
+                let state = if on { "on: nothing will be written to the board" } else { "off" };
+                let _ = ui_tx.send(UiMsg::Log(format!("Monitor only {state}.")));
+                if let Some(link) = connected.as_mut() {
+                    link.set_streams(effective_streams(notify_streams, on), &ui_tx).await;
+                }
+            }
+
+            Cmd::SetQualityWeights(w) => quality_weights = w,
+
+            Cmd::Connect { addr } => {
+                let _ = ui_tx.send(UiMsg::Log(format!("Connect requested: {addr}")));
+                reconnect = None;
+
+                let listed = last_scan.iter().find(|(i, _)| i.addr == addr).map(|(_, p)| p.clone());
+                let peri = match (listed, &adapter) {
+                    (Some(peri), _) => peri,
+                    (None, Some(adapter)) => match lookup_peripheral(adapter, &addr, &ui_tx).await {
+                        Some(peri) => peri,
+                        None => {
+                            let _ = ui_tx.send(UiMsg::Log(format!("{addr} not found nearby.")));
+                            continue;
+                        }
+                    },
+                    (None, None) => {
+                        let _ = ui_tx.send(UiMsg::Log("Can't connect: no Bluetooth adapter.".into()));
+                        continue;
+                    }
+                };
+
+                let _ = ui_tx.send(UiMsg::Connecting { addr: addr.clone() });
+                let streams = effective_streams(notify_streams, monitor_only);
+                let open = open_link(peri.clone(), &addr, &led_char_uuids, streams, &ui_tx);
+                let result = match cancellable_connect(open, &mut rx, &mut deferred).await {
+                    ConnectOutcome::Finished(result) => *result,
+                    ConnectOutcome::Cancelled => {
+                        peri.disconnect().await.ok();
+                        let _ = ui_tx.send(UiMsg::Log("Connect cancelled.".into()));
+                        let _ = ui_tx.send(UiMsg::Connected(false));
+                        continue;
+                    }
+                };
+                match result {
+                    Ok(Some(link)) => {
+                        connected = Some(link);
+                        write_stats = WriteStats::default();
+                        write_mode = None;
+                        heartbeat_tick.reset();
+                        let _ = ui_tx.send(UiMsg::Connected(true));
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        let _ = ui_tx.send(UiMsg::Log(format!("Connect failed: {e:#}")));
+                        peri.disconnect().await.ok();
+                        let _ = ui_tx.send(UiMsg::Connected(false));
+                    }
+                }
+            }
+
+            // Nothing in flight any more (cancels are handled while connecting).
+            Cmd::CancelConnect => {}
+
+            Cmd::Disconnect => {
+                reconnect = None;
+                if let Some(link) = connected.take() {
+                    let _ = ui_tx.send(UiMsg::Log("Disconnecting...".into()));
+                    link.close().await;
+                }
+                let _ = ui_tx.send(UiMsg::Subscribed(false));
+                let _ = ui_tx.send(UiMsg::Connected(false));
+            }
+
+            Cmd::SetMask(m) => {
+                if let Some(link) = &connected {
+                    // Bursts go straight to the LED characteristic without
+                    // waiting for ACKs; single writes keep the response (and
+                    // the control protocol's status reply).
+                    let burst = !verified_mode
+                        && write_stats.last_at.is_some_and(|at| at.elapsed() < BURST_WINDOW)
+                        && link.led.properties.contains(CharPropFlags::WRITE_WITHOUT_RESPONSE);
+                    write_stats.last_at = Some(Instant::now());
+                    let mode = match (verified_mode, burst) {
+                        (true, _) => WriteMode::Verified,
+                        (false, true) => WriteMode::Unacked,
+                        (false, false) => WriteMode::Acked,
+                    };
+                    if write_mode.replace(mode) != Some(mode) {
+                        let _ = ui_tx.send(UiMsg::WriteMode(mode));
+                    }
+                    if verified_mode {
+                        // Judged by what the board reads back, not by the
+                        // write returning Ok.
+                        let (mut read, mut tries) = (None, 0);
+                        while read != Some(m) && tries < VERIFIED_MAX_TRIES {
+                            tries += 1;
+                            if let Err(e) = link.write_mask(m, false, &ui_tx).await {
+                                write_stats.err += 1;
+                                let _ = ui_tx.send(UiMsg::Log(format!("Write failed (try {tries}): {e:?}")));
+                                continue;
+                            }
+                            write_stats.ok += 1;
+                            read = link.read_mask(&ui_tx).await;
+                        }
+                        if read == Some(m) {
+                            let _ = ui_tx.send(UiMsg::Toast(format!("Wrote LED mask: 0x{m:04x} (verified)")));
+                            reply.ok(format!("verified after {tries} tries"));
+                        } else {
+                            reply.fail(format!("not confirmed after {tries} tries"));
+                        }
+                        let _ = ui_tx.send(UiMsg::WriteVerified { sent: m, read, tries });
+                        continue;
+                    }
+
+                    match link.write_mask(m, burst, &ui_tx).await {
+                        Ok(_) => {
+                            write_stats.ok += 1;
+                            write_stats.unacked += burst as u64;
+                            let toast = if burst {
+                                format!("Sent LED mask: 0x{m:04x} (unconfirmed)")
+                            } else {
+                                format!("Wrote LED mask: 0x{m:04x}")
+                            };
+                            let _ = ui_tx.send(UiMsg::Toast(toast));
+                            reply.ok("");
+                            if verify_writes {
+                                let read = link.read_mask(&ui_tx).await;
+                                let _ = ui_tx.send(UiMsg::WriteVerified { sent: m, read, tries: 1 });
+                            }
+                        }
+                        Err(e) => {
+                            write_stats.err += 1;
+                            let _ = ui_tx.send(UiMsg::Log(format!("Write failed: {e:?}")));
+                            reply.fail(format!("write failed: {e}"));
+                        }
+                    }
+                } else {
+                    let _ = ui_tx.send(UiMsg::Log("Not connected; ignoring LED write.".into()));
+                    reply.fail("not connected");
+                }
+            }
+
+            Cmd::SetLed { idx, on } => {
+                let Some(link) = &connected else {
+                    reply.fail("not connected");
+                    continue;
+                };
+                // Start from what the board shows, not what the GUI last sent.
+                let Some(current) = link.read_mask(&ui_tx).await else {
+                    reply.fail("couldn't read the LED mask");
+                    continue;
+                };
+                let m = if on { current | 1 << idx } else { current & !(1 << idx) };
+                write_stats.last_at = Some(Instant::now());
+                if write_mode.replace(WriteMode::Acked) != Some(WriteMode::Acked) {
+                    let _ = ui_tx.send(UiMsg::WriteMode(WriteMode::Acked));
+                }
+                match link.write_mask(m, false, &ui_tx).await {
+                    Ok(()) => {
+                        write_stats.ok += 1;
+                        let _ = ui_tx.send(UiMsg::Toast(format!("Wrote LED mask: 0x{m:04x}")));
+                        reply.ok(format!("mask {m:04X}"));
+                    }
+                    Err(e) => {
+                        write_stats.err += 1;
+                        let _ = ui_tx.send(UiMsg::Log(format!("Write failed: {e:?}")));
+                        reply.fail(format!("write failed: {e}"));
+                    }
+                }
+            }
+
+            Cmd::SetVerifyWrites(on) => verify_writes = on,
+
+            Cmd::SetVerifiedMode(on) => verified_mode = on,
+
+            Cmd::StartRecording => {
+                if replay_next.is_some() {
+                    let _ = ui_tx.send(UiMsg::Log("Can't record while a replay is running.".into()));
+                    let _ = ui_tx.send(UiMsg::Recording(false));
+                    continue;
+                }
+                recording = Some(session::Recorder::new());
+                let _ = ui_tx.send(UiMsg::Log("Recording commands...".into()));
+                let _ = ui_tx.send(UiMsg::Recording(true));
+            }
+
+            Cmd::StopRecording(path) => {
+                let Some(rec) = recording.take() else { continue };
+                let _ = ui_tx.send(UiMsg::Recording(false));
+                let line = match path {
+                    None => "Recording discarded.".to_string(),
+                    Some(path) => match rec.save(&path) {
+                        Ok(()) => format!("Saved {} step(s) to {}", rec.len(), path.display()),
+                        Err(e) => format!("Saving the recording failed: {e:#}"),
+                    },
+                };
+                let _ = ui_tx.send(UiMsg::Log(line));
+            }
+
+            Cmd::Replay(path) => {
+                if recording.is_some() || replay_next.is_some() {
+                    let _ = ui_tx.send(UiMsg::Log("Stop recording (or the current replay) first.".into()));
+                    continue;
+                }
+                match session::load(&path) {
+                    Ok(steps) if !steps.is_empty() => {
+                        let line = format!("Replaying {} step(s) from {}", steps.len(), path.display());
+                        let _ = ui_tx.send(UiMsg::Log(line));
+                        replay = steps.into();
+                        replay_next = Some(tokio::time::Instant::now() + replay[0].0);
+                        let _ = ui_tx.send(UiMsg::Replaying(true));
+                    }
+                    Ok(_) => {
+                        let _ = ui_tx.send(UiMsg::Log(format!("{} has no steps.", path.display())));
+                    }
+                    Err(e) => {
+                        let _ = ui_tx.send(UiMsg::Log(format!("Can't replay: {e:#}")));
+                    }
+                }
+            }
+
+            Cmd::StopReplay => {
+                if replay_next.take().is_some() {
+                    replay.clear();
+                    let _ = ui_tx.send(UiMsg::Log("Replay stopped.".into()));
+                    let _ = ui_tx.send(UiMsg::Replaying(false));
+                }
+            }
+
+            Cmd::SetTestMode(on) => {
+                let Some(link) = &connected else { continue };
+                let res = match (link.send_control(protocol::Request::SetTestMode(on), &ui_tx).await, &link.test_mode) {
+                    (Some(res), _) => res,
+                    (None, Some(ch)) => write_traced(&link.peri, ch, &[on as u8], &ui_tx).await,
+                    (None, None) => {
+                        let _ = ui_tx.send(UiMsg::Log("This firmware has no test mode.".into()));
+                        continue;
+                    }
+                };
+                match res {
+                    Ok(_) => {
+                        let state = if on { "on: writes won't change the LEDs" } else { "off" };
+                        let _ = ui_tx.send(UiMsg::Log(format!("Test mode {state}.")));
+                    }
+                    Err(e) => {
+                        let _ = ui_tx.send(UiMsg::Log(format!("Test mode write failed: {e:?}")));
+                    }
+                }
+            }
+
+            Cmd::SetLocked(on) => {
+                let Some(link) = &connected else { continue };
+                let Some(ch) = &link.locked else { continue };
+                match write_traced(&link.peri, ch, &[on as u8], &ui_tx).await {
+                    Ok(()) => {
+                        let state = if on { "locked: mask writes are ignored" } else { "unlocked" };
+                        let _ = ui_tx.send(UiMsg::Log(format!("LEDs {state}.")));
+                    }
+                    Err(e) => {
+                        let _ = ui_tx.send(UiMsg::Log(format!("Lock write failed: {e:?}")));
+                        let _ = ui_tx.send(UiMsg::Locked(Some(!on)));
+                    }
+                }
+            }
+
+            Cmd::SetStaging(on) => {
+                let Some(link) = &connected else { continue };
+                let Some((ch, _)) = &link.staging else { continue };
+                match write_traced(&link.peri, ch, &[on as u8], &ui_tx).await {
+                    Ok(()) => {
+                        let state = if on { "on: changes wait for Apply" } else { "off; staged changes dropped" };
+                        let _ = ui_tx.send(UiMsg::Log(format!("Staging {state}.")));
+                        let _ = ui_tx.send(UiMsg::Staging(Some(on)));
+                    }
+                    Err(e) => {
+                        let _ = ui_tx.send(UiMsg::Log(format!("Staging write failed: {e:?}")));
+                        let _ = ui_tx.send(UiMsg::Staging(Some(!on)));
+                    }
+                }
+            }
+
+            Cmd::ApplyStaged => {
+                let Some(link) = &connected else { continue };
+                let Some((_, ch)) = &link.staging else { continue };
+                match write_traced(&link.peri, ch, &[1], &ui_tx).await {
+                    // The board ends staging itself.
+                    Ok(()) => {
+                        let _ = ui_tx.send(UiMsg::Toast("Applied staged changes".into()));
+                        let _ = ui_tx.send(UiMsg::Staging(Some(false)));
+                    }
+                    Err(e) => {
+                        let _ = ui_tx.send(UiMsg::Log(format!("Apply failed: {e:?}")));
+                    }
+                }
+            }
+
+            Cmd::SetPlaylist(steps) => {
+                let Some(link) = &connected else { continue };
+                let Some((ch, _)) = &link.autoplay else { continue };
+                match write_traced(&link.peri, ch, &playlist::to_bytes(&steps), &ui_tx).await {
+                    Ok(()) => {
+                        let _ = ui_tx.send(UiMsg::Toast(format!("Uploaded {} playlist step(s)", steps.len())));
+                    }
+                    Err(e) => {
+                        let _ = ui_tx.send(UiMsg::Log(format!("Playlist write failed: {e:?}")));
+                    }
+                }
+            }
+
+            Cmd::SetAutoplay(on) => {
+                let Some(link) = &connected else { continue };
+                let Some((_, ch)) = &link.autoplay else { continue };
+                match write_traced(&link.peri, ch, &[on as u8], &ui_tx).await {
+                    Ok(()) => {
+                        let state = if on { "on: the board plays its playlist and ignores mask writes" } else { "off" };
+                        let _ = ui_tx.send(UiMsg::Log(format!("Autoplay {state}.")));
+                    }
+                    Err(e) => {
+                        let _ = ui_tx.send(UiMsg::Log(format!("Autoplay write failed: {e:?}")));
+                        let _ = ui_tx.send(UiMsg::Autoplay(!on));
+                    }
+                }
+            }
+
+            Cmd::SetConnParams(params) => {
+                let Some(link) = &connected else { continue };
+                let Some(ch) = &link.conn_params else { continue };
+                if let Err(e) = write_traced(&link.peri, ch, &params.to_bytes(), &ui_tx).await {
+                    let _ = ui_tx.send(UiMsg::Log(format!("Connection parameter write failed: {e:?}")));
+                    continue;
+                }
+                let _ = ui_tx.send(UiMsg::Log(format!("Requested connection parameters: {params}.")));
+                tokio::time::sleep(CONN_PARAMS_SETTLE).await;
+                let Some(now) = read_conn_params(&link.peri, ch, &ui_tx).await else { continue };
+                let outcome = if now == params { "as requested" } else { "the central chose otherwise" };
+                let _ = ui_tx.send(UiMsg::Log(format!("Connection parameters now: {now} ({outcome}).")));
+                let _ = ui_tx.send(UiMsg::ConnParams(Some(now)));
+            }
+
+            Cmd::SetBlinkPeriods(periods) => {
+                let Some(link) = &connected else { continue };
+                let Some(ch) = &link.blink else { continue };
+                let bytes: Vec<u8> = periods.iter().flat_map(|p| p.to_le_bytes()).collect();
+                if let Err(e) = write_traced(&link.peri, ch, &bytes, &ui_tx).await {
+                    let _ = ui_tx.send(UiMsg::Log(format!("Blink write failed: {e:?}")));
+                }
+            }
+
+            Cmd::SetBreathe { mask, period_ms } => {
+                let Some(link) = &connected else { continue };
+                let Some((mask_ch, period_ch)) = &link.breathe else { continue };
+                for (ch, value) in [(period_ch, period_ms), (mask_ch, mask)] {
+                    if let Err(e) = write_traced(&link.peri, ch, &value.to_le_bytes(), &ui_tx).await {
+                        let _ = ui_tx.send(UiMsg::Log(format!("Breathe write failed: {e:?}")));
+                        break;
+                    }
+                }
+            }
+
+            Cmd::Reboot => {
+                let Some(link) = &connected else { continue };
+                match link.send_control(protocol::Request::Reboot, &ui_tx).await {
+                    Some(Ok(())) => {
+                        let _ = ui_tx.send(UiMsg::Log("Reboot requested.".into()));
+                    }
+                    Some(Err(e)) => {
+                        let _ = ui_tx.send(UiMsg::Log(format!("Reboot request failed: {e:?}")));
+                    }
+                    None => {
+                        let _ = ui_tx.send(UiMsg::Log("This firmware doesn't support remote reboot.".into()));
+                    }
+                }
+            }
+
+            Cmd::SelfTest => {
+                let Some(link) = &connected else { continue };
+                let Some(ch) = &link.self_test else {
+                    let _ = ui_tx.send(UiMsg::Log("This firmware has no output self-test.".into()));
+                    continue;
+                };
+                if let Some(Err(e)) = link.send_control(protocol::Request::SelfTest, &ui_tx).await {
+                    let _ = ui_tx.send(UiMsg::Log(format!("Self-test request failed: {e:?}")));
+                    continue;
+                }
+                // The board zeroes the result when it starts and fills it in
+                // once every LED has been checked.
+                let mut result = None;
+                for _ in 0..SELF_TEST_POLLS {
+                    tokio::time::sleep(SELF_TEST_POLL).await;
+                    result = read_traced(&link.peri, ch, &ui_tx).await.ok().and_then(|b| self_test_from_bytes(&b));
+                    if result.is_some() {
+                        break;
+                    }
+                }
+                match result {
+                    Some((tested, passed)) => {
+                        let _ = ui_tx.send(UiMsg::SelfTest { tested, passed });
+                    }
+                    None => {
+                        let _ = ui_tx.send(UiMsg::Log("Self-test: no result from the board.".into()));
+                    }
+                }
+            }
+
+            Cmd::ReadAll => {
+                let Some(Link { peri, .. }) = &connected else {
+                    let _ = ui_tx.send(UiMsg::Log("Not connected; nothing to read.".into()));
+                    continue;
+                };
+
+                let readable: Vec<_> = peri
+                    .characteristics()
+                    .into_iter()
+                    .filter(|c| c.properties.contains(CharPropFlags::READ))
+                    .collect();
+                let _ = ui_tx.send(UiMsg::Log(format!("Reading {} characteristic(s)...", readable.len())));
+
+                // One failing read shouldn't abort the rest of the snapshot.
+                for ch in &readable {
+                    match read_traced(peri, ch, &ui_tx).await {
+                        Ok(bytes) => {
+                            let _ = ui_tx.send(UiMsg::Log(format!("  {} = [{}]", ch.uuid, hex_bytes(&bytes))));
+                        }
+                        Err(e) => {
+                            let _ = ui_tx.send(UiMsg::Log(format!("  {} read failed: {e:?}", ch.uuid)));
+                        }
+                    }
+                }
+            }
+            Cmd::WithReply(..) => unreachable!("unwrapped above"),
+        }
+    }
 
-                    UiMsg::ScanResults(list) => {
-                        devices.replace(list);
+    Ok(())
+}
 
-                        // Clear listbox (GTK4: remove children manually)
-                        while let Some(child) = devices_list.first_child() {
-                            devices_list.remove(&child);
-                        }
+/// Drive `open` to completion while still listening for commands. A
+/// `CancelConnect` or `Disconnect` drops the attempt; anything else is kept in
+/// `deferred` for the main loop. The caller cleans up the half-open link.
+async fn cancellable_connect(
+    open: impl std::future::Future<Output = Result<Option<Link>>>,
+    rx: &mut tokio_mpsc::UnboundedReceiver<Cmd>,
+    deferred: &mut VecDeque<Cmd>,
+) -> ConnectOutcome {
+    tokio::pin!(open);
+    loop {
+        tokio::select! {
+            result = &mut open => return ConnectOutcome::Finished(Box::new(result)),
+            cmd = rx.recv() => match cmd {
+                Some(Cmd::CancelConnect | Cmd::Disconnect) => return ConnectOutcome::Cancelled,
+                Some(other) => deferred.push_back(other),
+                // UI is gone; let the attempt finish and the main loop exit.
+                None => return ConnectOutcome::Finished(Box::new(open.await)),
+            },
+        }
+    }
+}
+
+/// First adapter the manager knows about, with its event stream.
+async fn open_adapter(manager: &Manager) -> Result<(Adapter, EventStream)> {
+    let adapters = manager.adapters().await.context("manager.adapters")?;
+    let adapter = adapters.into_iter().next().ok_or_else(|| anyhow!("No BLE adapters found"))?;
+    let events = adapter.events().await.context("adapter.events")?;
+    Ok((adapter, events))
+}
 
-                        for d in devices.borrow().iter() {
-                            let name = d.name.clone().unwrap_or_else(|| "(no name)".into());
-                            let rssi = d.rssi.map(|v| format!("{v} dBm")).unwrap_or_else(|| "? dBm".into());
-                            let label = gtk::Label::new(Some(&format!("{name}  |  {}  |  {rssi}", d.addr)));
-                            label.set_xalign(0.0);
+/// Key for `ScanCache`; empty if the backend can't describe the adapter.
+async fn adapter_id_of(adapter: &Adapter) -> String {
+    adapter.adapter_info().await.unwrap_or_default()
+}
 
-                            let row = gtk::ListBoxRow::new();
-                            row.set_child(Some(&label));
-                            devices_list.append(&row);
-                        }
+/// After a failed scan, list the cached devices rather than nothing.
+fn send_cached_scan(cache: &mut ScanCache, adapter_id: &str, filter: &DeviceFilter, ui_tx: &mpsc::Sender<UiMsg>) {
+    let mut cached = cache.cached(adapter_id);
+    cached.retain(|d| filter.accepts(d));
+    if !cached.is_empty() {
+        let _ = ui_tx.send(UiMsg::Log(format!("Listing {} cached device(s).", cached.len())));
+        let _ = ui_tx.send(UiMsg::ScanResults(cached));
+    }
+}
 
-                        append_log(&log_buf, &log_view, &format!("Scan results: {} device(s)", devices.borrow().len()));
-                    }
+/// Next adapter event; never resolves while there is no adapter.
+async fn next_event(events: &mut Option<EventStream>) -> Option<CentralEvent> {
+    match events {
+        Some(events) => events.next().await,
+        None => std::future::pending().await,
+    }
+}
 
-                    UiMsg::Connected(is_connected) => {
-                        connected_state.set(is_connected);
-                        append_log(&log_buf, &log_view, if is_connected { "Connected." } else { "Disconnected." });
+/// `start_scan`, recovering once from "scan already in progress" (double
+/// click, or a scan left running by an earlier interrupted attempt) by
+/// stopping the old scan and starting again.
+async fn start_scan_healing(adapter: &Adapter, ui_tx: &mpsc::Sender<UiMsg>) -> Result<()> {
+    let Err(e) = adapter.start_scan(ScanFilter::default()).await else { return Ok(()) };
 
-                        set_led_controls_enabled(
-                            &[&led1, &led2, &led3, &led4],
-                            &all_on,
-                            &all_off,
-                            is_connected,
-                        );
-                    }
+    // BlueZ reports this as org.bluez.Error.InProgress.
+    let msg = e.to_string();
+    if is_adapter_off(&msg) {
+        return power_on_and_scan(adapter, ui_tx).await;
+    }
+    if !(msg.contains("InProgress") || msg.to_lowercase().contains("in progress")) {
+        return Err(e).context("start_scan");
+    }
+
+    adapter.stop_scan().await.ok();
+    adapter.start_scan(ScanFilter::default()).await.context("start_scan (retry)")?;
+    let _ = ui_tx.send(UiMsg::Log("A scan was already running; restarted it.".into()));
+    Ok(())
+}
+
+/// Start a fresh scan and wait for the first advertisement from the target;
+/// `None` if it isn't heard within `DISCOVERY_BENCH_TIMEOUT`. The time runs
+/// from just before the scan is started.
+async fn time_discovery(
+    adapter: &Adapter,
+    events: &mut Option<EventStream>,
+    addr: &str,
+    name: Option<&str>,
+    ui_tx: &mpsc::Sender<UiMsg>,
+) -> Result<Option<Duration>> {
+    adapter.stop_scan().await.ok();
+    tokio::time::sleep(DISCOVERY_BENCH_GAP).await;
+    // Whatever queued up before this run doesn't count.
+    if let Some(stream) = events.as_mut() {
+        while let Some(Some(_)) = futures::FutureExt::now_or_never(stream.next()) {}
+    }
+
+    let started = Instant::now();
+    start_scan_healing(adapter, ui_tx).await?;
+    let deadline = tokio::time::sleep(DISCOVERY_BENCH_TIMEOUT);
+    tokio::pin!(deadline);
+    let found = loop {
+        tokio::select! {
+            _ = &mut deadline => break None,
+            event = next_event(events) => {
+                let Some(event) = event else { bail!("adapter went away") };
+                let id = match event {
+                    CentralEvent::DeviceDiscovered(id)
+                    | CentralEvent::DeviceUpdated(id)
+                    | CentralEvent::ManufacturerDataAdvertisement { id, .. }
+                    | CentralEvent::ServiceDataAdvertisement { id, .. }
+                    | CentralEvent::ServicesAdvertisement { id, .. } => id,
+                    _ => continue,
+                };
+                if is_discovery_target(adapter, &id, addr, name).await {
+                    break Some(started.elapsed());
                 }
             }
+        }
+    };
+    adapter.stop_scan().await.ok();
+    Ok(found)
+}
 
-            gtk::glib::ControlFlow::Continue
-        });
+/// Matched by address; by name too, for boards whose address changes.
+async fn is_discovery_target(adapter: &Adapter, id: &PeripheralId, addr: &str, name: Option<&str>) -> bool {
+    if id.to_string() == addr {
+        return true;
     }
+    let Some(name) = name else { return false };
+    let Ok(peri) = adapter.peripheral(id).await else { return false };
+    let props = peri.properties().await.ok().flatten();
+    props.and_then(|p| p.local_name).as_deref() == Some(name)
+}
 
-    append_log(&log_buf, &log_view, "Ready. Click Scan.");
+/// "mean 840 ms (min 310, max 1520)"; `None` without any times.
+fn describe_discovery_times(times: &[Duration]) -> Option<String> {
+    let min = times.iter().min()?.as_millis();
+    let max = times.iter().max()?.as_millis();
+    let mean = times.iter().sum::<Duration>().as_millis() / times.len() as u128;
+    Some(format!("mean {mean} ms (min {min}, max {max})"))
 }
 
-fn set_led_controls_enabled(
-    toggles: &[&gtk::ToggleButton],
-    all_on: &gtk::Button,
-    all_off: &gtk::Button,
-    enabled: bool,
-) {
-    for t in toggles {
-        t.set_sensitive(enabled);
+/// A powered-off adapter: BlueZ refuses discovery with
+/// org.bluez.Error.NotReady ("Resource Not Ready").
+fn is_adapter_off(msg: &str) -> bool {
+    let lower = msg.to_lowercase();
+    msg.contains("NotReady") || lower.contains("not ready") || lower.contains("powered off")
+}
+
+/// btleplug can't power an adapter on, so on Linux ask BlueZ through
+/// `bluetoothctl` (which acts on the default adapter, normally the one we
+/// use) and scan again. Elsewhere, or if that doesn't work, fail with what
+/// the user should do instead.
+async fn power_on_and_scan(adapter: &Adapter, ui_tx: &mpsc::Sender<UiMsg>) -> Result<()> {
+    const ADAPTER_OFF: &str = "Bluetooth adapter is off; enable Bluetooth and scan again";
+    if !cfg!(target_os = "linux") {
+        return Err(anyhow!(ADAPTER_OFF));
     }
-    all_on.set_sensitive(enabled);
-    all_off.set_sensitive(enabled);
+    let _ = ui_tx.send(UiMsg::Log("Bluetooth adapter is powered off; trying to power it on...".into()));
+    let out = tokio::task::spawn_blocking(|| std::process::Command::new("bluetoothctl").args(["power", "on"]).output())
+        .await;
+    // Older bluetoothctl exits 0 even on failure; the message is reliable.
+    let powered = matches!(&out, Ok(Ok(o)) if String::from_utf8_lossy(&o.stdout).contains("succeeded"));
+    if !powered {
+        return Err(anyhow!("{ADAPTER_OFF} (bluetoothctl power on didn't work; is it blocked by rfkill?)"));
+    }
+    tokio::time::sleep(ADAPTER_POWER_ON_SETTLE).await;
+    adapter.start_scan(ScanFilter::default()).await.map_err(|e| anyhow!("{ADAPTER_OFF} ({e})"))?;
+    let _ = ui_tx.send(UiMsg::Log("Bluetooth adapter powered on.".into()));
+    Ok(())
 }
 
-fn append_log(buf: &gtk::TextBuffer, view: &gtk::TextView, line: &str) {
-    let mut text = line.to_string();
-    if !text.ends_with('\n') {
-        text.push('\n');
+/// btleplug has no switch for duplicate filtering. BlueZ only reports an
+/// advertiser again once its data changes, but a fresh discovery session
+/// starts with an empty filter, so on Linux the live scan is restarted after
+/// every refresh. CoreBluetooth and WinRT already deliver repeats.
+async fn restart_scan_for_duplicates(adapter: &Adapter, ui_tx: &mpsc::Sender<UiMsg>) {
+    if !cfg!(target_os = "linux") {
+        return;
+    }
+    adapter.stop_scan().await.ok();
+    if let Err(e) = start_scan_healing(adapter, ui_tx).await {
+        let _ = ui_tx.send(UiMsg::Log(format!("Live scan failed to restart: {e:#}")));
     }
+}
 
-    let mut end = buf.end_iter();
-    buf.insert(&mut end, &text);
+fn log_duplicate_support(ui_tx: &mpsc::Sender<UiMsg>) {
+    let line = if cfg!(target_os = "linux") {
+        "Duplicate advertisements: restarting discovery every refresh (BlueZ can't be told directly)."
+    } else {
+        "Duplicate advertisements: left to the platform, which reports them by default."
+    };
+    let _ = ui_tx.send(UiMsg::Log(line.into()));
+}
 
-    let end2 = buf.end_iter();
-    let mark = buf.create_mark(None, &end2, false);
-    view.scroll_mark_onscreen(&mark);
+/// Find `addr` among the peripherals the adapter already knows, or scan
+/// briefly for it, so a board that dropped out of the last scan list can
+/// still be connected to.
+async fn lookup_peripheral(adapter: &Adapter, addr: &str, ui_tx: &mpsc::Sender<UiMsg>) -> Option<Peripheral> {
+    if let Some(peri) = known_peripheral(adapter, addr).await {
+        return Some(peri);
+    }
+    let _ = ui_tx.send(UiMsg::Log(format!("Looking for {addr}...")));
+    if let Err(e) = start_scan_healing(adapter, ui_tx).await {
+        let _ = ui_tx.send(UiMsg::Log(format!("Scan failed: {e:#}")));
+        return None;
+    }
+    let deadline = Instant::now() + LOOKUP_SCAN_TIME;
+    while Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        if let Some(peri) = known_peripheral(adapter, addr).await {
+            return Some(peri);
+        }
+    }
+    None
 }
 
-async fn ble_worker(
-    mut rx: tokio_mpsc::UnboundedReceiver<Cmd>,
-    ui_tx: mpsc::Sender<UiMsg>,
-) -> Result<()> {
-    let manager = Manager::new().await.context("btleplug Manager::new")?;
-    let adapters = manager.adapters().await.context("manager.adapters")?;
-    let adapter = adapters.into_iter().next().ok_or_else(|| anyhow!("No BLE adapters found"))?;
+async fn known_peripheral(adapter: &Adapter, addr: &str) -> Option<Peripheral> {
+    let peris = adapter.peripherals().await.ok()?;
+    peris.into_iter().find(|p| p.id().to_string() == addr)
+}
 
-    let _ = ui_tx.send(UiMsg::Log("BLE worker started.".into()));
+/// Service discovery on a fresh link, retried when it errors or comes back
+/// empty: some stacks do either now and then, and another try on the same
+/// link usually sees the full table. The error says which it was.
+async fn discover_with_retry(peri: &Peripheral, ui_tx: &mpsc::Sender<UiMsg>) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        let problem = match peri.discover_services().await {
+            Ok(()) if !peri.services().is_empty() => return Ok(()),
+            Ok(()) => "found no services".to_string(),
+            Err(e) => format!("failed: {e}"),
+        };
+        if attempt == DISCOVERY_ATTEMPTS {
+            bail!("service discovery {problem} (tried {DISCOVERY_ATTEMPTS} times)");
+        }
+        attempt += 1;
+        let line = format!("Service discovery {problem}; retrying ({attempt}/{DISCOVERY_ATTEMPTS})...");
+        let _ = ui_tx.send(UiMsg::Log(line));
+        tokio::time::sleep(DISCOVERY_RETRY_DELAY).await;
+    }
+}
 
-    let mut last_scan: Vec<(DeviceInfo, Peripheral)> = Vec::new();
-    let mut connected: Option<(Peripheral, btleplug::api::Characteristic)> = None;
-    let led_uuid = Uuid::parse_str(LED_CHAR_UUID).unwrap();
+/// Connect to `peri` and set up everything the UI needs (LED count, current
+/// mask, notifications). `Ok(None)` means the board has no LED characteristic;
+/// that has already been reported to the UI and the link closed.
+async fn open_link(
+    peri: Peripheral,
+    addr: &str,
+    led_char_uuids: &[Uuid],
+    streams: Streams,
+    ui_tx: &mpsc::Sender<UiMsg>,
+) -> Result<Option<Link>> {
+    let diag_uuid = Uuid::parse_str(DIAG_CHAR_UUID).unwrap();
+    let led_count_uuid = Uuid::parse_str(LED_COUNT_CHAR_UUID).unwrap();
+    let present_leds_uuid = Uuid::parse_str(PRESENT_LEDS_CHAR_UUID).unwrap();
 
-    while let Some(cmd) = rx.recv().await {
-        match cmd {
-            Cmd::Scan => {
-                let _ = ui_tx.send(UiMsg::Log("Scanning (5s)...".into()));
-                adapter.start_scan(ScanFilter::default()).await.context("start_scan")?;
-                tokio::time::sleep(Duration::from_secs(5)).await;
+    peri.connect().await.context("peripheral.connect")?;
+    discover_with_retry(&peri, ui_tx).await?;
 
-                let (infos, peris) = collect_devices(&adapter).await?;
-                last_scan = infos.into_iter().zip(peris.into_iter()).collect();
+    let chars = peri.characteristics();
+    let found = led_char_uuids
+        .iter()
+        .find_map(|u| chars.iter().find(|c| c.uuid == *u))
+        .cloned();
+    let Some(ch) = found else {
+        // List what *was* there, to make a changed firmware UUID obvious.
+        let tried: Vec<String> = led_char_uuids.iter().map(Uuid::to_string).collect();
+        let mut lines = vec![format!("LED characteristic not found (tried {}). Discovered:", tried.join(", "))];
+        if chars.is_empty() {
+            lines.push("  (no characteristics)".into());
+        }
+        for c in &chars {
+            lines.push(format!("  service {}  char {}  {:?}", c.service_uuid, c.uuid, c.properties));
+        }
+        let _ = ui_tx.send(UiMsg::Log(lines.join("\n")));
 
-                let just_infos: Vec<DeviceInfo> = last_scan.iter().map(|(i, _)| i.clone()).collect();
-                let _ = ui_tx.send(UiMsg::ScanResults(just_infos));
-            }
+        peri.disconnect().await.ok();
+        let _ = ui_tx.send(UiMsg::Connected(false));
+        let _ = ui_tx.send(UiMsg::LedCharNotFound { addr: addr.to_string() });
+        return Ok(None);
+    };
 
-            Cmd::Connect { addr } => {
-                let _ = ui_tx.send(UiMsg::Log(format!("Connect requested: {addr}")));
+    let _ = ui_tx.send(UiMsg::Log(format!("Using LED characteristic {}", ch.uuid)));
 
-                let Some((_, peri)) = last_scan.iter().find(|(i, _)| i.addr == addr).cloned()
-                else {
-                    let _ = ui_tx.send(UiMsg::Log("That device wasn't in the last scan list.".into()));
-                    continue;
-                };
+    if !(ch.properties.contains(CharPropFlags::WRITE)
+        || ch.properties.contains(CharPropFlags::WRITE_WITHOUT_RESPONSE))
+    {
+        let _ = ui_tx.send(UiMsg::Log(
+            "Warning: LED characteristic doesn't advertise WRITE; attempting anyway.".into(),
+        ));
+    }
 
-                peri.connect().await.context("peripheral.connect")?;
-                peri.discover_services().await.context("discover_services")?;
+    // Diagnostics are optional (older firmware doesn't have them).
+    if let Some(diag) = chars.iter().find(|c| c.uuid == diag_uuid) {
+        match read_traced(&peri, diag, ui_tx).await {
+            Ok(bytes) => {
+                let line = describe_diagnostics(&bytes)
+                    .unwrap_or_else(|| format!("Diagnostics: unexpected payload [{}]", hex_bytes(&bytes)));
+                let _ = ui_tx.send(UiMsg::Log(line));
+            }
+            Err(e) => {
+                let _ = ui_tx.send(UiMsg::Log(format!("Diagnostics read failed: {e:?}")));
+            }
+        }
+    }
+    let connections_uuid = Uuid::parse_str(CONNECTIONS_CHAR_UUID).unwrap();
+    if let Some(c) = chars.iter().find(|c| c.uuid == connections_uuid) {
+        match read_traced(&peri, c, ui_tx).await {
+            Ok(bytes) => {
+                let line = describe_connections(&bytes)
+                    .unwrap_or_else(|| format!("Connections: unexpected payload [{}]", hex_bytes(&bytes)));
+                let _ = ui_tx.send(UiMsg::Log(line));
+            }
+            Err(e) => {
+                let _ = ui_tx.send(UiMsg::Log(format!("Connection limits read failed: {e:?}")));
+            }
+        }
+    }
 
-                let chars = peri.characteristics();
-                let Some(ch) = chars.into_iter().find(|c| c.uuid == led_uuid) else {
-                    let _ = ui_tx.send(UiMsg::Log("LED characteristic not found on device.".into()));
-                    peri.disconnect().await.ok();
-                    let _ = ui_tx.send(UiMsg::Connected(false));
-                    continue;
-                };
+    // Size the controls before syncing their state.
+    let mut led_count = DEFAULT_LED_COUNT;
+    if let Some(c) = chars.iter().find(|c| c.uuid == led_count_uuid) {
+        match read_traced(&peri, c, ui_tx).await {
+            Ok(bytes) if !bytes.is_empty() => led_count = bytes[0],
+            Ok(_) => {}
+            Err(e) => {
+                let _ = ui_tx.send(UiMsg::Log(format!("LED count read failed: {e:?}")));
+            }
+        }
+    }
+    let _ = ui_tx.send(UiMsg::LedCount(led_count));
+    let mut present_leds = u16::MAX;
+    if let Some(c) = chars.iter().find(|c| c.uuid == present_leds_uuid) {
+        match read_traced(&peri, c, ui_tx).await {
+            Ok(bytes) if !bytes.is_empty() => present_leds = bytes[0] as u16,
+            Ok(_) => {}
+            Err(e) => {
+                let _ = ui_tx.send(UiMsg::Log(format!("Present LEDs read failed: {e:?}")));
+            }
+        }
+    }
+    let _ = ui_tx.send(UiMsg::PresentLeds(present_leds));
 
-                if !(ch.properties.contains(CharPropFlags::WRITE)
-                    || ch.properties.contains(CharPropFlags::WRITE_WITHOUT_RESPONSE))
-                {
-                    let _ = ui_tx.send(UiMsg::Log(
-                        "Warning: LED characteristic doesn't advertise WRITE; attempting anyway.".into(),
-                    ));
+    // Sync the toggles with what the board is actually showing.
+    if ch.properties.contains(CharPropFlags::READ) {
+        match read_traced(&peri, &ch, ui_tx).await {
+            Ok(bytes) => {
+                if let Some(mask) = mask_from_bytes(&bytes) {
+                    let _ = ui_tx.send(UiMsg::MaskState { mask, notified: false });
                 }
+            }
+            Err(e) => {
+                let _ = ui_tx.send(UiMsg::Log(format!("LED mask read failed: {e:?}")));
+            }
+        }
+    }
+
+    let battery = chars.iter().find(|c| c.uuid == uuid_from_u16(0x2A19)).cloned();
+    let test_mode_uuid = Uuid::parse_str(TEST_MODE_CHAR_UUID).unwrap();
+    let test_mode = chars.iter().find(|c| c.uuid == test_mode_uuid).cloned();
+
+    let locked_uuid = Uuid::parse_str(LOCKED_CHAR_UUID).unwrap();
+    let locked = chars.iter().find(|c| c.uuid == locked_uuid).cloned();
+    let mut lock_state = None;
+    if let Some(ch) = &locked {
+        match read_traced(&peri, ch, ui_tx).await {
+            Ok(bytes) => lock_state = bytes.first().map(|b| *b != 0),
+            Err(e) => {
+                let _ = ui_tx.send(UiMsg::Log(format!("Lock state read failed: {e:?}")));
+            }
+        }
+    }
+    let _ = ui_tx.send(UiMsg::Locked(locked.as_ref().map(|_| lock_state.unwrap_or(false))));
+
+    // Staging starts off on every connection.
+    let staging_uuid = Uuid::parse_str(STAGING_CHAR_UUID).unwrap();
+    let apply_uuid = Uuid::parse_str(APPLY_CHAR_UUID).unwrap();
+    let staging = match (chars.iter().find(|c| c.uuid == staging_uuid), chars.iter().find(|c| c.uuid == apply_uuid)) {
+        (Some(s), Some(a)) => Some((s.clone(), a.clone())),
+        _ => None,
+    };
+    let _ = ui_tx.send(UiMsg::Staging(staging.as_ref().map(|_| false)));
 
-                connected = Some((peri, ch));
-                let _ = ui_tx.send(UiMsg::Connected(true));
+    let playlist_uuid = Uuid::parse_str(PLAYLIST_CHAR_UUID).unwrap();
+    let autoplay_uuid = Uuid::parse_str(AUTOPLAY_CHAR_UUID).unwrap();
+    let autoplay = chars
+        .iter()
+        .find(|c| c.uuid == playlist_uuid)
+        .cloned()
+        .zip(chars.iter().find(|c| c.uuid == autoplay_uuid).cloned());
+    let mut autoplay_state = None;
+    if let Some((steps_ch, on_ch)) = &autoplay {
+        match (read_traced(&peri, steps_ch, ui_tx).await, read_traced(&peri, on_ch, ui_tx).await) {
+            (Ok(steps), Ok(on)) => {
+                autoplay_state = Some((playlist::from_bytes(&steps), on.first().is_some_and(|b| *b != 0)));
             }
+            (Err(e), _) | (_, Err(e)) => {
+                let _ = ui_tx.send(UiMsg::Log(format!("Playlist read failed: {e:?}")));
+            }
+        }
+    }
+    let _ = ui_tx.send(UiMsg::Playlist(autoplay.as_ref().map(|_| autoplay_state.unwrap_or_default())));
 
-            Cmd::Disconnect => {
-                if let Some((peri, _)) = connected.take() {
-                    let _ = ui_tx.send(UiMsg::Log("Disconnecting...".into()));
-                    peri.disconnect().await.ok();
+    // Device Information Service (newer firmware), just for the log.
+    let mut device_info = Vec::new();
+    for (uuid, what) in [(0x2A29, "manufacturer"), (0x2A24, "model"), (0x2A26, "firmware")] {
+        let Some(ch) = chars.iter().find(|c| c.uuid == uuid_from_u16(uuid)) else { continue };
+        if let Ok(bytes) = read_traced(&peri, ch, ui_tx).await {
+            device_info.push(format!("{what} {}", String::from_utf8_lossy(&bytes)));
+        }
+    }
+    if !device_info.is_empty() {
+        let _ = ui_tx.send(UiMsg::Log(format!("Device information: {}", device_info.join(", "))));
+    }
+
+    let features_uuid = Uuid::parse_str(FEATURES_CHAR_UUID).unwrap();
+    let mut features = None;
+    if let Some(ch) = chars.iter().find(|c| c.uuid == features_uuid) {
+        match read_traced(&peri, ch, ui_tx).await {
+            Ok(bytes) => features = features::Features::from_bytes(&bytes),
+            Err(e) => {
+                let _ = ui_tx.send(UiMsg::Log(format!("Features read failed: {e:?}")));
+            }
+        }
+    }
+    if let Some(f) = features {
+        let _ = ui_tx.send(UiMsg::Log(format!("Firmware features: {f}")));
+    }
+    let _ = ui_tx.send(UiMsg::Features(features));
+
+    let blink_uuid = Uuid::parse_str(BLINK_CHAR_UUID).unwrap();
+    let blink = chars.iter().find(|c| c.uuid == blink_uuid).cloned();
+    let mut blink_periods = None;
+    if let Some(ch) = &blink {
+        match read_traced(&peri, ch, ui_tx).await {
+            Ok(bytes) => blink_periods = blink_periods_from_bytes(&bytes),
+            Err(e) => {
+                let _ = ui_tx.send(UiMsg::Log(format!("Blink periods read failed: {e:?}")));
+            }
+        }
+    }
+    let _ = ui_tx.send(UiMsg::BlinkPeriods(blink.as_ref().map(|_| blink_periods.unwrap_or_default())));
+
+    let breathe_mask_uuid = Uuid::parse_str(BREATHE_MASK_CHAR_UUID).unwrap();
+    let breathe_period_uuid = Uuid::parse_str(BREATHE_PERIOD_CHAR_UUID).unwrap();
+    let breathe = chars
+        .iter()
+        .find(|c| c.uuid == breathe_mask_uuid)
+        .cloned()
+        .zip(chars.iter().find(|c| c.uuid == breathe_period_uuid).cloned());
+    let mut breathe_state = None;
+    if let Some((mask_ch, period_ch)) = &breathe {
+        let mask = read_traced(&peri, mask_ch, ui_tx).await;
+        let period = read_traced(&peri, period_ch, ui_tx).await;
+        match (mask, period) {
+            // Both are a u16 LE, same as the LED mask.
+            (Ok(mask), Ok(period)) => breathe_state = mask_from_bytes(&mask).zip(mask_from_bytes(&period)),
+            (Err(e), _) | (_, Err(e)) => {
+                let _ = ui_tx.send(UiMsg::Log(format!("Breathe settings read failed: {e:?}")));
+            }
+        }
+    }
+    let self_test_uuid = Uuid::parse_str(SELF_TEST_CHAR_UUID).unwrap();
+    let self_test = chars.iter().find(|c| c.uuid == self_test_uuid).cloned();
+
+    let _ = ui_tx.send(UiMsg::Breathe(breathe.as_ref().map(|_| breathe_state.unwrap_or((0, BREATHE_MIN_MS)))));
+
+    // Versioned control channel (newer firmware): agree on a frame version.
+    let control_uuid = Uuid::parse_str(CONTROL_CHAR_UUID).unwrap();
+    let version_uuid = Uuid::parse_str(PROTOCOL_VERSION_CHAR_UUID).unwrap();
+    let mut control = None;
+    if let (Some(ctl), Some(ver)) = (
+        chars.iter().find(|c| c.uuid == control_uuid),
+        chars.iter().find(|c| c.uuid == version_uuid),
+    ) {
+        match read_traced(&peri, ver, ui_tx).await {
+            Ok(bytes) => match bytes.first().copied().and_then(protocol::negotiate) {
+                Some(v) => {
+                    let _ = ui_tx.send(UiMsg::Log(format!(
+                        "Control protocol v{v} (board v{}, host v{}).",
+                        bytes[0],
+                        protocol::PROTOCOL_VERSION
+                    )));
+                    control = Some((ctl.clone(), v));
                 }
-                let _ = ui_tx.send(UiMsg::Connected(false));
+                None => {
+                    let _ = ui_tx.send(UiMsg::Log(format!(
+                        "Unusable control protocol version [{}]; using legacy characteristics.",
+                        hex_bytes(&bytes)
+                    )));
+                }
+            },
+            Err(e) => {
+                let _ = ui_tx.send(UiMsg::Log(format!("Protocol version read failed: {e:?}")));
             }
+        }
+    }
 
-            Cmd::SetMask(m) => {
-                if let Some((peri, ch)) = &connected {
-                    let data = [m];
-                    match peri.write(ch, &data, WriteType::WithResponse).await {
-                        Ok(_) => {
-                            let _ = ui_tx.send(UiMsg::Log(format!("Wrote LED mask: 0x{m:02x}")));
-                        }
-                        Err(e) => {
-                            let _ = ui_tx.send(UiMsg::Log(format!("Write failed: {e:?}")));
-                        }
-                    }
-                } else {
-                    let _ = ui_tx.send(UiMsg::Log("Not connected; ignoring LED write.".into()));
+    // Notification-only extras of newer firmware; `set_streams` picks the
+    // ones to subscribe to.
+    let heartbeat_uuid = Uuid::parse_str(HEARTBEAT_CHAR_UUID).unwrap();
+    let heartbeat_ch = chars.iter().find(|c| c.uuid == heartbeat_uuid).cloned();
+    let nus_tx_uuid = Uuid::parse_str(NUS_TX_CHAR_UUID).unwrap();
+    let fw_log = chars.iter().find(|c| c.uuid == nus_tx_uuid).cloned();
+
+    // Firmware-side failures: anything from before this connection is read
+    // here, new ones arrive as notifications.
+    let last_error_uuid = Uuid::parse_str(LAST_ERROR_CHAR_UUID).unwrap();
+    let last_error = chars.iter().find(|c| c.uuid == last_error_uuid).cloned();
+    if let Some(ch) = &last_error {
+        match read_traced(&peri, ch, ui_tx).await {
+            Ok(bytes) => match protocol::FirmwareError::from_bytes(&bytes) {
+                Some(protocol::FirmwareError::None) | None => {}
+                Some(e) => {
+                    let line = format!("Firmware error before this connection: {e}");
+                    let _ = ui_tx.send(UiMsg::FirmwareError(line));
                 }
+            },
+            Err(e) => {
+                let _ = ui_tx.send(UiMsg::Log(format!("Last error read failed: {e:?}")));
             }
         }
     }
 
-    Ok(())
+    let memory_uuid = Uuid::parse_str(MEMORY_CHAR_UUID).unwrap();
+    let memory = chars.iter().find(|c| c.uuid == memory_uuid).cloned();
+
+    let conn_params_uuid = Uuid::parse_str(CONN_PARAMS_CHAR_UUID).unwrap();
+    let conn_params = chars.iter().find(|c| c.uuid == conn_params_uuid).cloned();
+    let _ = ui_tx.send(UiMsg::ConnParams(match &conn_params {
+        Some(ch) => read_conn_params(&peri, ch, ui_tx).await,
+        None => None,
+    }));
+
+    let mut link = Link {
+        peri,
+        addr: addr.to_string(),
+        led: ch,
+        battery,
+        battery_level: Arc::new(Mutex::new(None)),
+        battery_task: None,
+        test_mode,
+        locked,
+        staging,
+        autoplay,
+        blink,
+        breathe,
+        self_test,
+        control,
+        notify_task: None,
+        control_task: None,
+        heartbeat_ch,
+        heartbeat: None,
+        fw_log,
+        fw_log_task: None,
+        last_error,
+        last_error_task: None,
+        memory,
+        conn_params,
+        rssi: None,
+    };
+    link.set_streams(streams, ui_tx).await;
+    Ok(Some(link))
+}
+
+/// End a stream started by one of the `subscribe_*` functions.
+async fn stop_stream(peri: &Peripheral, ch: &Characteristic, task: tokio::task::JoinHandle<()>) {
+    task.abort();
+    peri.unsubscribe(ch).await.ok();
+}
+
+/// Tear down a link that went away on its own, and schedule the first
+/// reconnect attempt if the policy allows one.
+async fn drop_lost_link(
+    link: Link,
+    line: String,
+    policy: &ReconnectPolicy,
+    ui_tx: &mpsc::Sender<UiMsg>,
+) -> Option<Reconnect> {
+    let (peri, addr) = (link.peri.clone(), link.addr.clone());
+    link.close().await;
+    let _ = ui_tx.send(UiMsg::Log(line));
+    let _ = ui_tx.send(UiMsg::Subscribed(false));
+    let _ = ui_tx.send(UiMsg::Connected(false));
+
+    if policy.max_attempts == 0 {
+        return None;
+    }
+    let next_at = tokio::time::Instant::now() + policy.delay(1);
+    Some(Reconnect { peri, addr, attempt: 1, next_at })
+}
+
+/// Four u16 LE periods, LED1 first.
+fn blink_periods_from_bytes(bytes: &[u8]) -> Option<[u16; BLINK_LED_COUNT]> {
+    let bytes = bytes.get(..BLINK_LED_COUNT * 2)?;
+    Some(std::array::from_fn(|i| u16::from_le_bytes([bytes[2 * i], bytes[2 * i + 1]])))
+}
+
+/// `memory`: stack size, then its high-water mark (u32 LE each).
+fn stack_from_bytes(bytes: &[u8]) -> Option<(u32, u32)> {
+    let size = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?);
+    let peak = u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?);
+    Some((size, peak))
+}
+
+async fn read_conn_params(
+    peri: &Peripheral,
+    ch: &Characteristic,
+    ui_tx: &mpsc::Sender<UiMsg>,
+) -> Option<conn_params::ConnParams> {
+    match read_traced(peri, ch, ui_tx).await {
+        Ok(bytes) => conn_params::ConnParams::from_bytes(&bytes),
+        Err(e) => {
+            let _ = ui_tx.send(UiMsg::Log(format!("Connection parameter read failed: {e:?}")));
+            None
+        }
+    }
+}
+
+/// `self_test` value: LEDs tested, LEDs passed (u16 LE each); `None` while
+/// the board hasn't finished a test.
+fn self_test_from_bytes(bytes: &[u8]) -> Option<(u16, u16)> {
+    let tested = mask_from_bytes(bytes.get(..2)?)?;
+    let passed = mask_from_bytes(bytes.get(2..4)?)?;
+    (tested != 0).then_some((tested, passed))
+}
+
+/// Sample battery level and RSSI; failures just leave the field empty.
+async fn read_telemetry(link: &Link, ui_tx: &mpsc::Sender<UiMsg>) -> Telemetry {
+    let mut battery = *link.battery_level.lock().unwrap();
+    if let (None, Some(ch)) = (battery, &link.battery) {
+        if let Ok(bytes) = read_traced(&link.peri, ch, ui_tx).await {
+            battery = bytes.first().copied();
+        }
+    }
+    let rssi_raw = link.peri.properties().await.ok().flatten().and_then(|p| p.rssi);
+    let mut stack = None;
+    if let Some(ch) = &link.memory {
+        if let Ok(bytes) = read_traced(&link.peri, ch, ui_tx).await {
+            stack = stack_from_bytes(&bytes);
+        }
+    }
+
+    // The worker fills in the smoothed value.
+    Telemetry {
+        addr: link.addr.clone(),
+        has_battery: link.battery.is_some(),
+        battery,
+        rssi: rssi_raw,
+        rssi_raw,
+        stack,
+    }
+}
+
+/// Log `bytes` with a direction arrow when wire tracing is on.
+fn trace_wire(ui_tx: &mpsc::Sender<UiMsg>, arrow: &str, what: &str, uuid: Uuid, bytes: &[u8]) {
+    if WIRE_TRACE.load(Ordering::Relaxed) {
+        let _ = ui_tx.send(UiMsg::Log(format!("{arrow} {what} {uuid} [{}]", hex_bytes(bytes))));
+    }
+}
+
+async fn read_traced(peri: &Peripheral, ch: &Characteristic, ui_tx: &mpsc::Sender<UiMsg>) -> btleplug::Result<Vec<u8>> {
+    let bytes = peri.read(ch).await?;
+    trace_wire(ui_tx, "<-", "read", ch.uuid, &bytes);
+    Ok(bytes)
+}
+
+async fn write_traced(
+    peri: &Peripheral,
+    ch: &Characteristic,
+    data: &[u8],
+    ui_tx: &mpsc::Sender<UiMsg>,
+) -> btleplug::Result<()> {
+    write_traced_as(peri, ch, data, WriteType::WithResponse, ui_tx).await
+}
+
+async fn write_traced_as(
+    peri: &Peripheral,
+    ch: &Characteristic,
+    data: &[u8],
+    write_type: WriteType,
+    ui_tx: &mpsc::Sender<UiMsg>,
+) -> btleplug::Result<()> {
+    let what = match write_type {
+        WriteType::WithResponse => "write",
+        WriteType::WithoutResponse => "write-cmd",
+    };
+    trace_wire(ui_tx, "->", what, ch.uuid, data);
+    peri.write(ch, data, write_type).await
+}
+
+/// Subscribe to `control` replies and log the ones the board rejected.
+async fn subscribe_control_replies(
+    peri: &Peripheral,
+    ch: &Characteristic,
+    ui_tx: mpsc::Sender<UiMsg>,
+) -> Result<tokio::task::JoinHandle<()>> {
+    let mut stream = peri.notifications().await.context("notifications")?;
+    peri.subscribe(ch).await.context("subscribe")?;
+
+    let uuid = ch.uuid;
+    Ok(tokio::spawn(async move {
+        while let Some(n) = stream.next().await {
+            if n.uuid != uuid {
+                continue;
+            }
+            trace_wire(&ui_tx, "<-", "notify", n.uuid, &n.value);
+            let line = match protocol::decode_reply(&n.value) {
+                Some(r) if r.status == protocol::Status::Ok => continue,
+                Some(r) => format!("Board rejected control opcode 0x{:02x} (v{}): {}", r.opcode, r.version, r.status),
+                None => format!("Unexpected control notification [{}]", hex_bytes(&n.value)),
+            };
+            let _ = ui_tx.send(UiMsg::Log(line));
+        }
+    }))
+}
+
+/// Subscribe to Battery Level notifications and keep the latest in `level`.
+async fn subscribe_battery(
+    peri: &Peripheral,
+    ch: &Characteristic,
+    level: Arc<Mutex<Option<u8>>>,
+    ui_tx: mpsc::Sender<UiMsg>,
+) -> Result<tokio::task::JoinHandle<()>> {
+    let mut stream = peri.notifications().await.context("notifications")?;
+    peri.subscribe(ch).await.context("subscribe")?;
+
+    let uuid = ch.uuid;
+    Ok(tokio::spawn(async move {
+        while let Some(n) = stream.next().await {
+            if n.uuid != uuid {
+                continue;
+            }
+            trace_wire(&ui_tx, "<-", "notify", n.uuid, &n.value);
+            if let Some(&v) = n.value.first() {
+                *level.lock().unwrap() = Some(v);
+            }
+        }
+    }))
+}
+
+/// Subscribe to heartbeat notifications and count them into `beats`.
+async fn subscribe_heartbeat(
+    peri: &Peripheral,
+    ch: &Characteristic,
+    beats: Arc<AtomicU32>,
+    ui_tx: mpsc::Sender<UiMsg>,
+) -> Result<tokio::task::JoinHandle<()>> {
+    let mut stream = peri.notifications().await.context("notifications")?;
+    peri.subscribe(ch).await.context("subscribe")?;
+
+    let uuid = ch.uuid;
+    Ok(tokio::spawn(async move {
+        while let Some(n) = stream.next().await {
+            if n.uuid != uuid {
+                continue;
+            }
+            trace_wire(&ui_tx, "<-", "notify", n.uuid, &n.value);
+            beats.fetch_add(1, Ordering::Relaxed);
+        }
+    }))
+}
+
+/// Subscribe to the firmware's NUS log and forward complete lines to the UI
+/// log, prefixed with "[fw]".
+async fn subscribe_fw_log(
+    peri: &Peripheral,
+    ch: &Characteristic,
+    ui_tx: mpsc::Sender<UiMsg>,
+) -> Result<tokio::task::JoinHandle<()>> {
+    let mut stream = peri.notifications().await.context("notifications")?;
+    peri.subscribe(ch).await.context("subscribe")?;
+
+    let uuid = ch.uuid;
+    Ok(tokio::spawn(async move {
+        // Lines arrive split across notifications.
+        let mut pending = Vec::new();
+        while let Some(n) = stream.next().await {
+            if n.uuid != uuid {
+                continue;
+            }
+            trace_wire(&ui_tx, "<-", "notify", n.uuid, &n.value);
+            pending.extend_from_slice(&n.value);
+            while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                let text = String::from_utf8_lossy(&line[..end]);
+                let _ = ui_tx.send(UiMsg::Log(format!("[fw] {}", text.trim_end())));
+            }
+        }
+    }))
+}
+
+/// Subscribe to `last_error` and log each failure the firmware reports.
+async fn subscribe_last_error(
+    peri: &Peripheral,
+    ch: &Characteristic,
+    ui_tx: mpsc::Sender<UiMsg>,
+) -> Result<tokio::task::JoinHandle<()>> {
+    let mut stream = peri.notifications().await.context("notifications")?;
+    peri.subscribe(ch).await.context("subscribe")?;
+
+    let uuid = ch.uuid;
+    Ok(tokio::spawn(async move {
+        while let Some(n) = stream.next().await {
+            if n.uuid != uuid {
+                continue;
+            }
+            trace_wire(&ui_tx, "<-", "notify", n.uuid, &n.value);
+            let msg = match protocol::FirmwareError::from_bytes(&n.value) {
+                Some(protocol::FirmwareError::None) => continue,
+                Some(e) => UiMsg::FirmwareError(format!("Firmware error: {e}")),
+                None => UiMsg::Log(format!("Unexpected last_error notification [{}]", hex_bytes(&n.value))),
+            };
+            let _ = ui_tx.send(msg);
+        }
+    }))
+}
+
+/// Subscribe to LED mask notifications and forward them to the UI as `MaskState`.
+async fn subscribe_mask_notifications(
+    peri: &Peripheral,
+    ch: &Characteristic,
+    ui_tx: mpsc::Sender<UiMsg>,
+) -> Result<tokio::task::JoinHandle<()>> {
+    // Grab the stream first so no notification slips through after subscribing.
+    let mut stream = peri.notifications().await.context("notifications")?;
+    peri.subscribe(ch).await.context("subscribe")?;
+
+    let uuid = ch.uuid;
+    Ok(tokio::spawn(async move {
+        while let Some(n) = stream.next().await {
+            if n.uuid != uuid {
+                continue;
+            }
+            trace_wire(&ui_tx, "<-", "notify", n.uuid, &n.value);
+            if let Some(mask) = mask_from_bytes(&n.value) {
+                let _ = ui_tx.send(UiMsg::MaskState { mask, notified: true });
+            }
+        }
+    }))
+}
+
+/// Decode the firmware diagnostics payload: uptime secs (u32 LE) + RESETREAS (u32 LE).
+fn describe_diagnostics(bytes: &[u8]) -> Option<String> {
+    let uptime = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?);
+    let reason = u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?);
+
+    // nRF52840 POWER.RESETREAS bits
+    const FLAGS: &[(u32, &str)] = &[
+        (1 << 0, "reset pin"),
+        (1 << 1, "watchdog"),
+        (1 << 2, "soft reset"),
+        (1 << 3, "CPU lockup"),
+        (1 << 16, "wake from System OFF (GPIO)"),
+        (1 << 17, "wake from System OFF (LPCOMP)"),
+        (1 << 18, "debug interface"),
+        (1 << 19, "wake from System OFF (NFC)"),
+        (1 << 20, "wake from System OFF (VBUS)"),
+    ];
+    let flags: Vec<&str> = FLAGS.iter().filter(|(bit, _)| reason & bit != 0).map(|(_, n)| *n).collect();
+    let reason_text = if flags.is_empty() { "power-on".to_string() } else { flags.join(", ") };
+
+    let (h, m, s) = (uptime / 3600, (uptime / 60) % 60, uptime % 60);
+    Some(format!("Diagnostics: uptime {h}h{m:02}m{s:02}s, last reset: {reason_text} (0x{reason:08x})"))
+}
+
+/// `connections`: SoftDevice peripheral links, links the firmware serves at
+/// once, links active (ours included).
+fn describe_connections(bytes: &[u8]) -> Option<String> {
+    let [configured, served, active] = *bytes.get(..3)? else { return None };
+    Some(format!("Connections: {active} of {served} in use (SoftDevice configured for {configured} peripheral links)"))
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>().join(" ")
+}
+
+async fn properties_with_retry(p: &Peripheral) -> Option<PeripheralProperties> {
+    for _ in 0..PROPERTIES_RETRIES {
+        if let Some(props) = p.properties().await.ok().flatten() {
+            return Some(props);
+        }
+        tokio::time::sleep(PROPERTIES_RETRY_DELAY).await;
+    }
+    p.properties().await.ok().flatten()
 }
 
-async fn collect_devices(adapter: &Adapter) -> Result<(Vec<DeviceInfo>, Vec<Peripheral>)> {
+async fn collect_devices(
+    adapter: &Adapter,
+    filter: RssiFilter,
+    smoother: &mut RssiSmoother,
+) -> Result<(Vec<DeviceInfo>, Vec<Peripheral>)> {
     let peris = adapter.peripherals().await.context("adapter.peripherals")?;
+    let led_service = Uuid::parse_str(LED_SERVICE_UUID).unwrap();
     let mut infos = Vec::new();
     let mut keep = Vec::new();
 
     for p in peris {
-        let props = p.properties().await.ok().flatten();
+        let props = properties_with_retry(&p).await;
+        let resolving = props.is_none();
         let addr = p.id().to_string();
         let name = props.as_ref().and_then(|x| x.local_name.clone());
-        let rssi = props.as_ref().and_then(|x| x.rssi);
+        let rssi_raw = props.as_ref().and_then(|x| x.rssi);
+        let rssi = rssi_raw.map(|r| smoother.update(&addr, r));
+        if !filter.accepts(rssi) {
+            continue;
+        }
+        let services = props.as_ref().map(|x| x.services.clone()).unwrap_or_default();
+        let controllable = services.contains(&led_service);
 
-        infos.push(DeviceInfo { addr, name, rssi });
+        infos.push(DeviceInfo { addr, name, rssi, rssi_raw, controllable, services, resolving, cached: false });
         keep.push(p);
     }
 
-    // Sort: named first, stronger RSSI first
+    // Sort: controllable first, then named first, stronger RSSI first
     let mut zipped: Vec<(DeviceInfo, Peripheral)> = infos.into_iter().zip(keep.into_iter()).collect();
     zipped.sort_by(|a, b| {
         let an = a.0.name.is_some();
         let bn = b.0.name.is_some();
-        bn.cmp(&an)
+        b.0.controllable
+            .cmp(&a.0.controllable)
+            .then_with(|| bn.cmp(&an))
             .then_with(|| b.0.rssi.unwrap_or(-999).cmp(&a.0.rssi.unwrap_or(-999)))
     });
 
@@ -466,3 +4866,56 @@ async fn collect_devices(adapter: &Adapter) -> Result<(Vec<DeviceInfo>, Vec<Peri
     Ok((infos2, peris2))
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(addr: &str, name: &str) -> DeviceInfo {
+        DeviceInfo {
+            addr: addr.into(),
+            name: Some(name.into()),
+            rssi: Some(-60),
+            rssi_raw: Some(-60),
+            controllable: true,
+            services: Vec::new(),
+            resolving: false,
+            cached: false,
+        }
+    }
+
+    /// As the worker sorts them, strongest first.
+    fn scanned() -> Vec<DeviceInfo> {
+        vec![
+            device("AA:00:00:00:00:01", "LED-kitchen"),
+            device("AA:00:00:00:00:02", "Headphones"),
+            device("AA:00:00:00:00:03", "LED-desk"),
+            device("AA:00:00:00:00:04", "LED-hall"),
+        ]
+    }
+
+    #[test]
+    fn device_by_addr_follows_favorites_to_the_top() {
+        let mut list = scanned();
+        pin_favorites(&mut list, &["AA:00:00:00:00:04".into()]);
+        assert_eq!(list[0].addr, "AA:00:00:00:00:04");
+
+        for d in scanned() {
+            assert_eq!(device_by_addr(&d.addr, &list).map(|f| f.name.as_deref()), Some(d.name.as_deref()));
+        }
+    }
+
+    #[test]
+    fn device_by_addr_skips_filtered_devices() {
+        let filter = DeviceFilter { allow: vec!["LED-*".into()], deny: vec!["LED-desk".into()] };
+        let mut list = scanned();
+        list.retain(|d| filter.accepts(d));
+        pin_favorites(&mut list, &["AA:00:00:00:00:04".into()]);
+        let shown: Vec<&str> = list.iter().map(|d| d.addr.as_str()).collect();
+        assert_eq!(shown, ["AA:00:00:00:00:04", "AA:00:00:00:00:01"]);
+
+        // The second row is not the second device the worker reported.
+        assert_eq!(device_by_addr(shown[1], &list).and_then(|d| d.name.as_deref()), Some("LED-kitchen"));
+        assert!(device_by_addr("AA:00:00:00:00:02", &list).is_none());
+        assert!(device_by_addr("AA:00:00:00:00:03", &list).is_none());
+    }
+}
@@ -0,0 +1,45 @@
+//! Autoplay playlists: masks the board steps through by itself, each for its
+//! own time, from the `playlist` characteristic (`[mask u16 LE, ms u16 LE]`
+//! per step). The board keeps them in flash.
+//!
+//! The GUI edits a playlist as text, `mask:ms` per step with the mask in hex,
+//! e.g. `1:500, 2:500, 4:500, 8:500` for a running light.
+
+/// Steps the firmware keeps (`PLAYLIST_MAX_STEPS` in `ble_led.rs`).
+pub const MAX_STEPS: usize = 16;
+
+/// LED mask and how long it shows, in ms.
+pub type Step = (u16, u16);
+
+pub fn parse(text: &str) -> Result<Vec<Step>, String> {
+    let steps = text
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|step| {
+            let (mask, ms) = step.split_once(':').ok_or_else(|| format!("{step:?}: expected mask:ms"))?;
+            let mask = u16::from_str_radix(mask.trim(), 16).map_err(|_| format!("{step:?}: bad mask"))?;
+            let ms = ms.trim().parse().map_err(|_| format!("{step:?}: bad duration"))?;
+            Ok((mask, ms))
+        })
+        .collect::<Result<Vec<Step>, String>>()?;
+    if steps.len() > MAX_STEPS {
+        return Err(format!("{} steps; the board keeps at most {MAX_STEPS}", steps.len()));
+    }
+    Ok(steps)
+}
+
+/// Text `parse` takes back.
+pub fn format(steps: &[Step]) -> String {
+    let items: Vec<String> = steps.iter().map(|(mask, ms)| format!("{mask:x}:{ms}")).collect();
+    items.join(", ")
+}
+
+pub fn to_bytes(steps: &[Step]) -> Vec<u8> {
+    steps.iter().flat_map(|(mask, ms)| [mask.to_le_bytes(), ms.to_le_bytes()].concat()).collect()
+}
+
+/// Steps as read from the board; a trailing partial step is dropped.
+pub fn from_bytes(bytes: &[u8]) -> Vec<Step> {
+    bytes.chunks_exact(4).map(|s| (u16::from_le_bytes([s[0], s[1]]), u16::from_le_bytes([s[2], s[3]]))).collect()
+}
@@ -0,0 +1,266 @@
+//! Preferences window: every persisted setting in one place.
+//!
+//! Opens with the current values, validates on Save, writes the settings
+//! file and then calls `on_saved` so the caller can push the new values to
+//! the BLE worker. Cancel (or closing the window) discards the edits.
+
+use gtk::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+use uuid::Uuid;
+
+use crate::settings::{join_list, parse_by_position, parse_list, parse_weights, Settings, Streams};
+
+pub fn show(parent: &gtk::ApplicationWindow, settings: &Rc<RefCell<Settings>>, on_saved: Rc<dyn Fn(&Settings)>) {
+    let current = settings.borrow().clone();
+
+    let window = gtk::Window::builder()
+        .title("Preferences")
+        .transient_for(parent)
+        .modal(true)
+        .default_width(460)
+        .build();
+
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(12);
+    grid.set_margin_top(12);
+    grid.set_margin_bottom(12);
+    grid.set_margin_start(12);
+    grid.set_margin_end(12);
+
+    let mut row = 0;
+
+    heading(&grid, &mut row, "Scanning");
+    let min_rssi = gtk::SpinButton::with_range(-120.0, 0.0, 1.0);
+    min_rssi.set_value(current.min_rssi as f64);
+    field(&grid, &mut row, "Min RSSI (dBm)", &min_rssi);
+    let include_unknown = gtk::CheckButton::with_label("Include devices with unknown RSSI");
+    include_unknown.set_active(current.include_unknown_rssi);
+    grid.attach(&include_unknown, 0, row, 2, 1);
+    row += 1;
+    let auto_scan = gtk::CheckButton::with_label("Scan on startup");
+    auto_scan.set_active(current.auto_scan);
+    grid.attach(&auto_scan, 0, row, 2, 1);
+    row += 1;
+    let rssi_smoothing = gtk::SpinButton::with_range(0.05, 1.0, 0.05);
+    rssi_smoothing.set_digits(2);
+    rssi_smoothing.set_value(current.rssi_smoothing as f64);
+    rssi_smoothing.set_tooltip_text(Some("Weight of the newest reading; 1 shows raw values"));
+    field(&grid, &mut row, "RSSI smoothing", &rssi_smoothing);
+    let scan_duplicates = gtk::CheckButton::with_label("Report duplicate advertisements (live scan)");
+    scan_duplicates.set_active(current.scan_duplicates);
+    scan_duplicates.set_tooltip_text(Some("More frequent RSSI updates, at the cost of more radio and CPU time"));
+    grid.attach(&scan_duplicates, 0, row, 2, 1);
+    row += 1;
+
+    heading(&grid, &mut row, "Device list");
+    let device_allow = gtk::Entry::builder().text(join_list(&current.device_allow)).build();
+    device_allow.set_placeholder_text(Some("empty lists every device"));
+    device_allow.set_tooltip_text(Some(
+        "Comma-separated addresses or names (\\, for a comma); * matches anything, e.g. LED-*",
+    ));
+    field(&grid, &mut row, "Only show", &device_allow);
+    let device_deny = gtk::Entry::builder().text(join_list(&current.device_deny)).build();
+    device_deny.set_tooltip_text(Some("Comma-separated addresses or names; wins over \"Only show\""));
+    field(&grid, &mut row, "Hide", &device_deny);
+
+    heading(&grid, &mut row, "Connection");
+    let uuids: Vec<String> = current.led_char_uuids.iter().map(Uuid::to_string).collect();
+    let led_uuids = gtk::Entry::builder().text(uuids.join(", ")).build();
+    led_uuids.set_tooltip_text(Some("Comma-separated; the first one the board has is used"));
+    field(&grid, &mut row, "LED characteristic UUIDs", &led_uuids);
+    let reconnect_base = gtk::SpinButton::with_range(100.0, 60_000.0, 100.0);
+    reconnect_base.set_value(current.reconnect_base_ms as f64);
+    field(&grid, &mut row, "Reconnect base delay (ms)", &reconnect_base);
+    let reconnect_max = gtk::SpinButton::with_range(100.0, 600_000.0, 1000.0);
+    reconnect_max.set_value(current.reconnect_max_ms as f64);
+    field(&grid, &mut row, "Reconnect max delay (ms)", &reconnect_max);
+    let reconnect_attempts = gtk::SpinButton::with_range(0.0, 100.0, 1.0);
+    reconnect_attempts.set_value(current.reconnect_max_attempts as f64);
+    reconnect_attempts.set_tooltip_text(Some("0 disables auto-reconnect"));
+    field(&grid, &mut row, "Reconnect attempts", &reconnect_attempts);
+    let reconnect_jitter = gtk::SpinButton::with_range(0.0, 50.0, 5.0);
+    reconnect_jitter.set_value(current.reconnect_jitter_pct as f64);
+    reconnect_jitter.set_tooltip_text(Some("Spreads out retries from boards that dropped at the same time"));
+    field(&grid, &mut row, "Reconnect jitter (\u{b1}%)", &reconnect_jitter);
+    let heartbeat_missed = gtk::SpinButton::with_range(0.0, 60.0, 1.0);
+    heartbeat_missed.set_value(current.heartbeat_max_missed as f64);
+    heartbeat_missed.set_tooltip_text(Some("Reconnect after this many missed heartbeats in a row; 0 disables"));
+    field(&grid, &mut row, "Missed heartbeats", &heartbeat_missed);
+    let [w_rssi, w_writes, w_heartbeat] = current.quality_weights;
+    let quality_weights = gtk::Entry::builder().text(format!("{w_rssi}, {w_writes}, {w_heartbeat}")).build();
+    quality_weights.set_tooltip_text(Some("Link quality weights: RSSI, write success, heartbeats"));
+    field(&grid, &mut row, "Quality weights", &quality_weights);
+
+    heading(&grid, &mut row, "Notifications");
+    let stream_labels = [
+        "LED state",
+        "Battery level",
+        "Heartbeat (needed by the watchdog)",
+        "Control replies",
+        "Firmware log",
+        "Firmware errors",
+    ];
+    let stream_checks: Vec<gtk::CheckButton> = stream_labels
+        .into_iter()
+        .zip(current.notify_streams.flags())
+        .map(|(label, on)| {
+            let check = gtk::CheckButton::with_label(label);
+            check.set_active(on);
+            grid.attach(&check, 0, row, 2, 1);
+            row += 1;
+            check
+        })
+        .collect();
+
+    heading(&grid, &mut row, "LEDs");
+    let led_labels = gtk::Entry::builder().text(join_list(&current.led_labels)).build();
+    led_labels.set_placeholder_text(Some("Power, Link, Error, Status"));
+    led_labels.set_tooltip_text(Some(
+        "Comma-separated, LED1 first; leave one blank to keep its default name. Write \\, for a comma in a label",
+    ));
+    field(&grid, &mut row, "LED labels", &led_labels);
+
+    // Like the exporter, the gamepad thread is started once.
+    heading(&grid, &mut row, "Gamepad (applies on restart)");
+    let gamepad_buttons = gtk::Entry::builder().text(join_list(&current.gamepad_buttons)).build();
+    gamepad_buttons.set_placeholder_text(Some("empty disables gamepad input"));
+    gamepad_buttons.set_tooltip_text(Some("Button names in LED order, e.g. South, East, DPadUp (feature gamepad)"));
+    field(&grid, &mut row, "Buttons", &gamepad_buttons);
+
+    heading(&grid, &mut row, "TCP control (applies on restart)");
+    let tcp_listen = gtk::Entry::builder().text(current.tcp_listen.as_str()).build();
+    tcp_listen.set_placeholder_text(Some("empty disables the TCP server"));
+    tcp_listen.set_tooltip_text(Some("host:port for the line protocol (feature tcp); keep it on 127.0.0.1"));
+    field(&grid, &mut row, "Listen address", &tcp_listen);
+
+    // The exporter is started once with the worker.
+    heading(&grid, &mut row, "InfluxDB export (applies on restart)");
+    let influx_url = gtk::Entry::builder().text(current.influx_url.as_str()).build();
+    influx_url.set_placeholder_text(Some("empty disables export"));
+    field(&grid, &mut row, "URL", &influx_url);
+    let influx_org = gtk::Entry::builder().text(current.influx_org.as_str()).build();
+    field(&grid, &mut row, "Organization", &influx_org);
+    let influx_bucket = gtk::Entry::builder().text(current.influx_bucket.as_str()).build();
+    field(&grid, &mut row, "Bucket", &influx_bucket);
+    let influx_token = gtk::PasswordEntry::builder()
+        .text(current.influx_token.as_str())
+        .show_peek_icon(true)
+        .build();
+    field(&grid, &mut row, "Token", &influx_token);
+    let influx_interval = gtk::SpinButton::with_range(1.0, 3600.0, 1.0);
+    influx_interval.set_value(current.influx_interval_secs as f64);
+    field(&grid, &mut row, "Min interval per device (s)", &influx_interval);
+
+    let error_label = gtk::Label::new(None);
+    error_label.set_xalign(0.0);
+    error_label.add_css_class("error");
+    grid.attach(&error_label, 0, row, 2, 1);
+    row += 1;
+
+    let buttons = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    buttons.set_halign(gtk::Align::End);
+    buttons.set_margin_top(8);
+    let cancel = gtk::Button::with_label("Cancel");
+    let save = gtk::Button::with_label("Save");
+    save.add_css_class("suggested-action");
+    buttons.append(&cancel);
+    buttons.append(&save);
+    grid.attach(&buttons, 0, row, 2, 1);
+
+    window.set_child(Some(&grid));
+
+    {
+        let window = window.clone();
+        cancel.connect_clicked(move |_| window.close());
+    }
+
+    {
+        let window = window.clone();
+        let settings = settings.clone();
+        save.connect_clicked(move |_| {
+            let mut s = settings.borrow().clone();
+            s.min_rssi = min_rssi.value_as_int() as i16;
+            s.include_unknown_rssi = include_unknown.is_active();
+            s.auto_scan = auto_scan.is_active();
+            s.rssi_smoothing = rssi_smoothing.value() as f32;
+            s.scan_duplicates = scan_duplicates.is_active();
+            s.device_allow = parse_list(&device_allow.text());
+            s.device_deny = parse_list(&device_deny.text());
+
+            let parsed: Result<Vec<Uuid>, _> = led_uuids
+                .text()
+                .split(',')
+                .map(str::trim)
+                .filter(|u| !u.is_empty())
+                .map(Uuid::parse_str)
+                .collect();
+            match parsed {
+                Ok(uuids) if !uuids.is_empty() => s.led_char_uuids = uuids,
+                Ok(_) => return error_label.set_text("Enter at least one LED characteristic UUID."),
+                Err(e) => return error_label.set_text(&format!("Bad LED characteristic UUID: {e}")),
+            }
+
+            s.reconnect_base_ms = reconnect_base.value_as_int() as u64;
+            s.reconnect_max_ms = reconnect_max.value_as_int() as u64;
+            s.reconnect_max_attempts = reconnect_attempts.value_as_int() as u32;
+            s.reconnect_jitter_pct = reconnect_jitter.value_as_int() as u32;
+            s.heartbeat_max_missed = heartbeat_missed.value_as_int() as u32;
+            if s.reconnect_base_ms > s.reconnect_max_ms {
+                return error_label.set_text("Reconnect base delay can't exceed the max delay.");
+            }
+            match parse_weights(&quality_weights.text()) {
+                Some(w) => s.quality_weights = w,
+                None => return error_label.set_text("Quality weights: three numbers \u{2265} 0, not all zero."),
+            }
+
+            s.notify_streams = Streams::from_flags(std::array::from_fn(|i| stream_checks[i].is_active()));
+
+            s.led_labels = parse_by_position(&led_labels.text());
+            s.gamepad_buttons = parse_by_position(&gamepad_buttons.text());
+            s.tcp_listen = tcp_listen.text().trim().to_string();
+            if !s.tcp_listen.is_empty() && s.tcp_listen.parse::<std::net::SocketAddr>().is_err() {
+                return error_label.set_text("TCP listen address must look like 127.0.0.1:7878.");
+            }
+
+            s.influx_url = influx_url.text().trim().to_string();
+            s.influx_org = influx_org.text().trim().to_string();
+            s.influx_bucket = influx_bucket.text().trim().to_string();
+            s.influx_token = influx_token.text().to_string();
+            s.influx_interval_secs = influx_interval.value_as_int() as u64;
+            if !s.influx_url.is_empty() && !s.influx_url.starts_with("http") {
+                return error_label.set_text("InfluxDB URL must start with http:// or https://.");
+            }
+
+            if let Err(e) = s.save() {
+                return error_label.set_text(&format!("Saving settings failed: {e:#}"));
+            }
+            settings.replace(s);
+            on_saved(&settings.borrow());
+            window.close();
+        });
+    }
+
+    window.present();
+}
+
+fn heading(grid: &gtk::Grid, row: &mut i32, title: &str) {
+    let label = gtk::Label::new(Some(title));
+    label.set_xalign(0.0);
+    label.add_css_class("heading");
+    if *row > 0 {
+        label.set_margin_top(8);
+    }
+    grid.attach(&label, 0, *row, 2, 1);
+    *row += 1;
+}
+
+fn field(grid: &gtk::Grid, row: &mut i32, title: &str, widget: &impl IsA<gtk::Widget>) {
+    let label = gtk::Label::new(Some(title));
+    label.set_xalign(0.0);
+    grid.attach(&label, 0, *row, 1, 1);
+    widget.set_hexpand(true);
+    grid.attach(widget, 1, *row, 1, 1);
+    *row += 1;
+}
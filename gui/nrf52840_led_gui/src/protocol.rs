@@ -0,0 +1,235 @@
+//! Host side of the firmware's versioned `control` characteristic.
+//!
+//! Frames are `[version, opcode, payload...]`; the board answers each one
+//! with a `[version, opcode | 0x80, status]` notification. On connect we read
+//! the board's `protocol_version` characteristic and speak the lower of the
+//! two versions. Boards without `control` are driven through the older
+//! per-feature characteristics instead.
+
+/// Highest frame version this host can produce.
+///
+/// v2: `SetMask` carries a 16-bit mask (LE) instead of one byte.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+const OP_SET_MASK: u8 = 0x01;
+const OP_SET_TEST_MODE: u8 = 0x02;
+const OP_REBOOT: u8 = 0x04;
+/// Required payload for `OP_REBOOT`.
+const REBOOT_MAGIC: [u8; 4] = *b"BOOT";
+const OP_SELF_TEST: u8 = 0x05;
+const OP_REPLY: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    SetMask(u16),
+    SetTestMode(bool),
+    /// Turn the LEDs off and reset the board.
+    Reboot,
+    /// Check each LED output; the result is read from `self_test`.
+    SelfTest,
+}
+
+impl Request {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Request::SetMask(_) => OP_SET_MASK,
+            Request::SetTestMode(_) => OP_SET_TEST_MODE,
+            Request::Reboot => OP_REBOOT,
+            Request::SelfTest => OP_SELF_TEST,
+        }
+    }
+
+    /// Frame for a board speaking `version` (from `negotiate`).
+    pub fn encode(&self, version: u8) -> Vec<u8> {
+        let mut frame = vec![version, self.opcode()];
+        match *self {
+            Request::SetMask(mask) if version >= 2 => frame.extend(mask.to_le_bytes()),
+            // v1 boards only have eight LEDs.
+            Request::SetMask(mask) => frame.push(mask as u8),
+            Request::SetTestMode(on) => frame.push(on as u8),
+            Request::Reboot => frame.extend(REBOOT_MAGIC),
+            Request::SelfTest => {}
+        }
+        frame
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    UnsupportedVersion,
+    UnknownOpcode,
+    BadPayload,
+    /// The board's `locked` characteristic is set.
+    Locked,
+    /// The board is playing its playlist (`autoplay`).
+    Autoplay,
+    /// A status code newer than this host.
+    Other(u8),
+}
+
+impl From<u8> for Status {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => Status::Ok,
+            1 => Status::UnsupportedVersion,
+            2 => Status::UnknownOpcode,
+            3 => Status::BadPayload,
+            4 => Status::Locked,
+            5 => Status::Autoplay,
+            v => Status::Other(v),
+        }
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Ok => f.write_str("ok"),
+            Status::UnsupportedVersion => f.write_str("unsupported protocol version"),
+            Status::UnknownOpcode => f.write_str("unknown opcode"),
+            Status::BadPayload => f.write_str("bad payload"),
+            Status::Locked => f.write_str("LEDs are locked"),
+            Status::Autoplay => f.write_str("board is playing its playlist"),
+            Status::Other(v) => write!(f, "status {v}"),
+        }
+    }
+}
+
+/// The board's `last_error` characteristic (u16 LE), mirroring `FwError`
+/// in `ble_led.rs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareError {
+    None,
+    NotifyFailed,
+    SelfTestFailed,
+    DeviceNameSet,
+    ControlRejected,
+    FlashWriteFailed,
+    ConnParamsRejected,
+    /// An error code newer than this host.
+    Other(u16),
+}
+
+impl FirmwareError {
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let [lo, hi, ..] = *bytes else { return None };
+        Some(u16::from_le_bytes([lo, hi]).into())
+    }
+}
+
+impl From<u16> for FirmwareError {
+    fn from(v: u16) -> Self {
+        match v {
+            0 => FirmwareError::None,
+            1 => FirmwareError::NotifyFailed,
+            2 => FirmwareError::SelfTestFailed,
+            3 => FirmwareError::DeviceNameSet,
+            4 => FirmwareError::ControlRejected,
+            5 => FirmwareError::FlashWriteFailed,
+            6 => FirmwareError::ConnParamsRejected,
+            v => FirmwareError::Other(v),
+        }
+    }
+}
+
+impl std::fmt::Display for FirmwareError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FirmwareError::None => f.write_str("none"),
+            FirmwareError::NotifyFailed => f.write_str("notification dropped (board's send buffers full)"),
+            FirmwareError::SelfTestFailed => f.write_str("LED output self-test failed"),
+            FirmwareError::DeviceNameSet => f.write_str("couldn't change the advertised name"),
+            FirmwareError::ControlRejected => f.write_str("control request rejected"),
+            FirmwareError::FlashWriteFailed => f.write_str("couldn't save the playlist to flash"),
+            FirmwareError::ConnParamsRejected => f.write_str("connection parameter request refused"),
+            FirmwareError::Other(v) => write!(f, "error code {v}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reply {
+    pub version: u8,
+    /// Opcode of the request being answered (reply bit stripped).
+    pub opcode: u8,
+    pub status: Status,
+}
+
+/// Parse a `control` notification. Trailing bytes are ignored so newer
+/// firmware can add fields; anything that isn't a reply is `None`.
+pub fn decode_reply(bytes: &[u8]) -> Option<Reply> {
+    let [version, opcode, status, ..] = *bytes else { return None };
+    if opcode & OP_REPLY == 0 {
+        return None;
+    }
+    Some(Reply { version, opcode: opcode & !OP_REPLY, status: status.into() })
+}
+
+/// Frame version to use with a board reporting `board_version`; `None` if
+/// the board predates versioning (reports 0).
+pub fn negotiate(board_version: u8) -> Option<u8> {
+    (board_version >= 1).then(|| board_version.min(PROTOCOL_VERSION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_mask_v1_carries_the_low_byte() {
+        assert_eq!(Request::SetMask(0x0105).encode(1), vec![1, OP_SET_MASK, 0x05]);
+    }
+
+    #[test]
+    fn set_mask_v2_carries_the_full_mask_le() {
+        assert_eq!(Request::SetMask(0x0105).encode(2), vec![2, OP_SET_MASK, 0x05, 0x01]);
+    }
+
+    #[test]
+    fn other_requests_encode_the_same_in_every_version() {
+        for version in 1..=PROTOCOL_VERSION {
+            assert_eq!(Request::SetTestMode(true).encode(version), vec![version, OP_SET_TEST_MODE, 1]);
+            assert_eq!(Request::Reboot.encode(version), vec![version, OP_REBOOT, b'B', b'O', b'O', b'T']);
+            assert_eq!(Request::SelfTest.encode(version), vec![version, OP_SELF_TEST]);
+        }
+    }
+
+    #[test]
+    fn negotiate_picks_the_lower_version() {
+        assert_eq!(negotiate(0), None);
+        assert_eq!(negotiate(1), Some(1));
+        assert_eq!(negotiate(2), Some(2));
+        assert_eq!(negotiate(3), Some(PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn decode_reply_strips_the_reply_bit() {
+        let reply = decode_reply(&[2, OP_SET_MASK | OP_REPLY, 0]).unwrap();
+        assert_eq!(reply, Reply { version: 2, opcode: OP_SET_MASK, status: Status::Ok });
+    }
+
+    #[test]
+    fn decode_reply_rejects_short_frames() {
+        assert_eq!(decode_reply(&[]), None);
+        assert_eq!(decode_reply(&[2, OP_SET_MASK | OP_REPLY]), None);
+    }
+
+    #[test]
+    fn decode_reply_ignores_trailing_bytes() {
+        let reply = decode_reply(&[3, OP_REBOOT | OP_REPLY, 4, 0xAA, 0xBB]).unwrap();
+        assert_eq!(reply, Reply { version: 3, opcode: OP_REBOOT, status: Status::Locked });
+    }
+
+    #[test]
+    fn decode_reply_rejects_requests() {
+        assert_eq!(decode_reply(&Request::SetMask(1).encode(2)), None);
+    }
+
+    #[test]
+    fn unknown_status_is_other() {
+        assert_eq!(Status::from(5), Status::Autoplay);
+        assert_eq!(Status::from(6), Status::Other(6));
+        assert_eq!(decode_reply(&[2, OP_SET_MASK | OP_REPLY, 0xFF]).unwrap().status, Status::Other(0xFF));
+    }
+}
@@ -0,0 +1,145 @@
+//! Headless `pulse` subcommand: connect, write one mask, wait for the board
+//! to confirm it, disconnect. Meant for cron jobs and scripts.
+//!
+//! ```text
+//! nrf52840_led_gui pulse <address-or-name> <mask>
+//! ```
+//!
+//! `mask` accepts decimal or `0x`-prefixed hex. Each step is reported on
+//! stderr; the exit status is non-zero if any step before the write fails.
+
+use anyhow::{anyhow, bail, Context, Result};
+use btleplug::api::{Central, CharPropFlags, Manager as _, Peripheral as _, ScanFilter, WriteType};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use futures::StreamExt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::settings::Settings;
+use crate::{mask_bytes, mask_from_bytes};
+
+const FIND_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for the firmware's notification echoing the new mask.
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub fn run(args: &[String]) -> Result<()> {
+    let [target, mask] = args else {
+        bail!("usage: pulse <address-or-name> <mask>");
+    };
+    let mask = parse_mask(mask).with_context(|| format!("bad mask {mask:?}"))?;
+
+    let rt = tokio::runtime::Runtime::new().context("tokio runtime")?;
+    rt.block_on(pulse(target, mask))
+}
+
+fn parse_mask(s: &str) -> Result<u16> {
+    let v = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16)?,
+        None => s.parse()?,
+    };
+    Ok(v)
+}
+
+async fn pulse(target: &str, mask: u16) -> Result<()> {
+    let manager = Manager::new().await.context("btleplug Manager::new")?;
+    let adapters = manager.adapters().await.context("manager.adapters")?;
+    let adapter = adapters.into_iter().next().ok_or_else(|| anyhow!("No BLE adapters found"))?;
+
+    let peri = step("find", FIND_TIMEOUT, find(&adapter, target)).await?;
+    adapter.stop_scan().await.ok();
+
+    step("connect", CONNECT_TIMEOUT, async { peri.connect().await.context("connect") }).await?;
+
+    // From here on, always try to disconnect before reporting the outcome.
+    let result = write_and_confirm(&peri, mask).await;
+
+    step("disconnect", CONNECT_TIMEOUT, async { peri.disconnect().await.context("disconnect") })
+        .await
+        .ok();
+    result
+}
+
+async fn write_and_confirm(peri: &Peripheral, mask: u16) -> Result<()> {
+    let led_uuids = Settings::load().led_char_uuids;
+
+    step("discover", CONNECT_TIMEOUT, async { peri.discover_services().await.context("discover_services") })
+        .await?;
+    let chars = peri.characteristics();
+    let ch = led_uuids
+        .iter()
+        .find_map(|u| chars.iter().find(|c| c.uuid == *u))
+        .cloned()
+        .ok_or_else(|| anyhow!("LED characteristic not found"))?;
+
+    // Subscribe before writing so the confirmation can't be missed.
+    let mut notifications = None;
+    if ch.properties.contains(CharPropFlags::NOTIFY) {
+        let stream = step("subscribe", WRITE_TIMEOUT, async {
+            let stream = peri.notifications().await.context("notifications")?;
+            peri.subscribe(&ch).await.context("subscribe")?;
+            Ok(stream)
+        })
+        .await?;
+        notifications = Some(stream);
+    }
+
+    step("write", WRITE_TIMEOUT, async {
+        peri.write(&ch, &mask_bytes(mask), WriteType::WithResponse).await.context("write")
+    })
+    .await?;
+
+    let Some(mut stream) = notifications else {
+        eprintln!("[skip] confirm: LED characteristic doesn't notify");
+        return Ok(());
+    };
+    let uuid = ch.uuid;
+    let echoed = step("confirm", CONFIRM_TIMEOUT, async {
+        while let Some(n) = stream.next().await {
+            if n.uuid == uuid {
+                return mask_from_bytes(&n.value).ok_or_else(|| anyhow!("empty notification"));
+            }
+        }
+        bail!("notification stream ended")
+    })
+    .await?;
+
+    // The firmware reports what the pins show, which may drop unknown bits.
+    if echoed != mask {
+        eprintln!("[warn] board reports mask 0x{echoed:04x} (wrote 0x{mask:04x})");
+    }
+    Ok(())
+}
+
+/// Poll the adapter until a peripheral's address or local name matches `target`.
+pub(crate) async fn find(adapter: &Adapter, target: &str) -> Result<Peripheral> {
+    adapter.start_scan(ScanFilter::default()).await.context("start_scan")?;
+    loop {
+        for p in adapter.peripherals().await.context("adapter.peripherals")? {
+            if p.id().to_string().eq_ignore_ascii_case(target) {
+                return Ok(p);
+            }
+            let props = p.properties().await.ok().flatten();
+            if props.and_then(|x| x.local_name).as_deref() == Some(target) {
+                return Ok(p);
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
+/// Run one step with a timeout and report its outcome on stderr.
+async fn step<T>(name: &str, timeout: Duration, fut: impl Future<Output = Result<T>>) -> Result<T> {
+    let start = Instant::now();
+    let res = match tokio::time::timeout(timeout, fut).await {
+        Ok(r) => r,
+        Err(_) => Err(anyhow!("timed out after {}s", timeout.as_secs())),
+    };
+    let ms = start.elapsed().as_millis();
+    match &res {
+        Ok(_) => eprintln!("[ok] {name} ({ms} ms)"),
+        Err(e) => eprintln!("[fail] {name} ({ms} ms): {e:#}"),
+    }
+    res.with_context(|| format!("{name} failed"))
+}
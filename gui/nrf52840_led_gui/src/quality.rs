@@ -0,0 +1,58 @@
+//! Link quality: one 0..=100 score for the connected board, built from what
+//! the worker already tracks.
+//!
+//! Each input is scored 0..=100:
+//!
+//! - RSSI: the smoothed value, linear from `RSSI_FLOOR_DBM` (0) to
+//!   `RSSI_GOOD_DBM` (100), clamped.
+//! - Writes: the share of mask writes that succeeded since the previous
+//!   report; 100 when there were none.
+//! - Heartbeat: 100, minus `HEARTBEAT_PENALTY` per heartbeat missed in a row.
+//!
+//! The score is the weighted mean of the inputs that are available:
+//!
+//! ```text
+//! score = (w_rssi * rssi + w_writes * writes + w_heartbeat * heartbeat) / (w_rssi + w_writes + w_heartbeat)
+//! ```
+//!
+//! A board without heartbeats, or a reading without RSSI, drops that term
+//! (and its weight). The weights are `quality_weights` in the settings file.
+
+use crate::settings::Settings;
+
+const RSSI_FLOOR_DBM: f32 = -100.0;
+const RSSI_GOOD_DBM: f32 = -50.0;
+const HEARTBEAT_PENALTY: f32 = 40.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Weights {
+    pub rssi: f32,
+    pub writes: f32,
+    pub heartbeat: f32,
+}
+
+impl Weights {
+    pub fn from_settings(s: &Settings) -> Self {
+        let [rssi, writes, heartbeat] = s.quality_weights;
+        Self { rssi, writes, heartbeat }
+    }
+}
+
+/// `rssi` smoothed dBm, `writes` (ok, failed) since the previous report,
+/// `missed` heartbeats in a row. `None` only if every input is missing or
+/// weighted zero.
+pub fn score(w: &Weights, rssi: Option<i16>, writes: (u64, u64), missed: Option<u32>) -> Option<u8> {
+    let rssi = rssi.map(|dbm| (dbm as f32 - RSSI_FLOOR_DBM) / (RSSI_GOOD_DBM - RSSI_FLOOR_DBM) * 100.0);
+    let writes = match writes {
+        (0, 0) => 100.0,
+        (ok, err) => ok as f32 / (ok + err) as f32 * 100.0,
+    };
+    let heartbeat = missed.map(|n| 100.0 - n as f32 * HEARTBEAT_PENALTY);
+
+    let terms = [(w.rssi, rssi), (w.writes, Some(writes)), (w.heartbeat, heartbeat)];
+    let (sum, total) = terms
+        .iter()
+        .filter_map(|(w, v)| Some((*w, (*v)?.clamp(0.0, 100.0))))
+        .fold((0.0, 0.0), |(sum, total), (w, v)| (sum + w * v, total + w));
+    (total > 0.0).then(|| (sum / total).round() as u8)
+}
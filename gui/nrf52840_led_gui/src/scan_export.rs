@@ -0,0 +1,74 @@
+//! "Export scan": save the current device list for site surveys or bug
+//! reports about discovery.
+//!
+//! The format follows the file extension: `.json` gives an object with a
+//! `scanned_at_unix` timestamp and a `devices` array, anything else CSV with
+//! the timestamp in a leading `#` comment line.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::inventory::opt;
+use crate::DeviceInfo;
+
+#[derive(Serialize)]
+struct ScanExport<'a> {
+    scanned_at_unix: u64,
+    devices: Vec<ExportRow<'a>>,
+}
+
+/// One device as exported; the same columns in both formats.
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    addr: &'a str,
+    name: Option<&'a str>,
+    rssi: Option<i16>,
+    rssi_raw: Option<i16>,
+    controllable: bool,
+    services: Vec<String>,
+}
+
+impl<'a> From<&'a DeviceInfo> for ExportRow<'a> {
+    fn from(d: &'a DeviceInfo) -> Self {
+        ExportRow {
+            addr: &d.addr,
+            name: d.name.as_deref(),
+            rssi: d.rssi,
+            rssi_raw: d.rssi_raw,
+            controllable: d.controllable,
+            services: d.services.iter().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+pub fn write(path: &Path, devices: &[DeviceInfo]) -> Result<()> {
+    let scanned_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let export = ScanExport { scanned_at_unix: scanned_at, devices: devices.iter().map(ExportRow::from).collect() };
+    let json = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    let bytes = if json { to_json(&export)? } else { to_csv(&export)? };
+    std::fs::write(path, bytes).with_context(|| format!("write {}", path.display()))
+}
+
+fn to_csv(export: &ScanExport) -> Result<Vec<u8>> {
+    let mut bytes = format!("# scanned_at_unix = {}\n", export.scanned_at_unix).into_bytes();
+    let mut out = csv::Writer::from_writer(&mut bytes);
+    out.write_record(["addr", "name", "rssi", "rssi_raw", "controllable", "services"])?;
+    for d in &export.devices {
+        let (rssi, rssi_raw, controllable) = (opt(d.rssi), opt(d.rssi_raw), d.controllable.to_string());
+        // ';' keeps the list in one field without quoting.
+        let services = d.services.join(";");
+        let fields: [&str; 6] = [d.addr, d.name.unwrap_or(""), &rssi, &rssi_raw, &controllable, &services];
+        out.write_record(fields)?;
+    }
+    out.flush()?;
+    drop(out);
+    Ok(bytes)
+}
+
+fn to_json(export: &ScanExport) -> Result<Vec<u8>> {
+    let mut bytes = serde_json::to_vec_pretty(export)?;
+    bytes.push(b'\n');
+    Ok(bytes)
+}
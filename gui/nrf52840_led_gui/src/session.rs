@@ -0,0 +1,196 @@
+//! Record a session of user commands and replay it later, for repeatable
+//! demos and bug reproductions.
+//!
+//! A recording is a JSON array with one step per line:
+//!
+//! ```text
+//! [
+//!   {"at_ms":0,"cmd":"scan","min_dbm":-100,"include_unknown":true},
+//!   {"at_ms":5210,"cmd":"connect","addr":"F1:2C:..."},
+//!   {"at_ms":7480,"cmd":"set_mask","mask":5}
+//! ]
+//! ```
+//!
+//! `at_ms` counts from the start of the recording; replay keeps the gaps
+//! between steps. Only what a user does is recorded (scans, connects, LED
+//! writes, ...), not settings pushed from Preferences or live scanning.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::{Cmd, RssiFilter, BLINK_LED_COUNT};
+
+pub struct Recorder {
+    started: Instant,
+    steps: Vec<Step>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self { started: Instant::now(), steps: Vec::new() }
+    }
+
+    /// Note `cmd` if it's one worth replaying.
+    pub fn record(&mut self, cmd: &Cmd) {
+        let Some(action) = Action::from_cmd(cmd) else { return };
+        let at_ms = self.started.elapsed().as_millis() as u64;
+        self.steps.push(Step { at_ms: Some(at_ms), action });
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut lines = Vec::new();
+        for step in &self.steps {
+            lines.push(format!("  {}", serde_json::to_string(step)?));
+        }
+        let text = format!("[\n{}\n]\n", lines.join(",\n"));
+        std::fs::write(path, text).with_context(|| format!("write {}", path.display()))
+    }
+}
+
+/// Steps of a recording with the delay before each one (the first relative
+/// to the start of the replay).
+pub fn load(path: &Path) -> Result<Vec<(Duration, Cmd)>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    parse(&text)
+}
+
+fn parse(text: &str) -> Result<Vec<(Duration, Cmd)>> {
+    // Decoded one step at a time so an error can say which step it is.
+    let values: Vec<serde_json::Value> = serde_json::from_str(text).context("not a recording")?;
+    let mut steps = Vec::new();
+    let mut prev_ms = 0;
+    for (n, value) in values.into_iter().enumerate() {
+        let step: Step = serde_json::from_value(value).with_context(|| format!("step {}", n + 1))?;
+        let at_ms = step.at_ms.unwrap_or(prev_ms).max(prev_ms);
+        steps.push((Duration::from_millis(at_ms - prev_ms), step.action.into_cmd()));
+        prev_ms = at_ms;
+    }
+    Ok(steps)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Step {
+    /// Missing: right after the previous step.
+    #[serde(default)]
+    at_ms: Option<u64>,
+    #[serde(flatten)]
+    action: Action,
+}
+
+/// The commands that get recorded, as `"cmd": ...` plus arguments. A number
+/// out of range for its field fails to decode instead of wrapping.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Action {
+    Scan {
+        min_dbm: i16,
+        #[serde(default)]
+        include_unknown: bool,
+    },
+    Connect {
+        addr: String,
+    },
+    Disconnect,
+    SetMask {
+        mask: u16,
+    },
+    SetTestMode {
+        #[serde(default)]
+        on: bool,
+    },
+    SetBlinkPeriods {
+        periods: [u16; BLINK_LED_COUNT],
+    },
+    ReadAll,
+    Reboot,
+}
+
+impl Action {
+    /// `None` for commands that aren't recorded.
+    fn from_cmd(cmd: &Cmd) -> Option<Self> {
+        Some(match cmd {
+            Cmd::Scan(f) => Action::Scan { min_dbm: f.min_dbm, include_unknown: f.include_unknown },
+            Cmd::Connect { addr } => Action::Connect { addr: addr.clone() },
+            Cmd::Disconnect => Action::Disconnect,
+            Cmd::SetMask(mask) => Action::SetMask { mask: *mask },
+            Cmd::SetTestMode(on) => Action::SetTestMode { on: *on },
+            Cmd::SetBlinkPeriods(periods) => Action::SetBlinkPeriods { periods: *periods },
+            Cmd::ReadAll => Action::ReadAll,
+            Cmd::Reboot => Action::Reboot,
+            _ => return None,
+        })
+    }
+
+    fn into_cmd(self) -> Cmd {
+        match self {
+            Action::Scan { min_dbm, include_unknown } => Cmd::Scan(RssiFilter { min_dbm, include_unknown }),
+            Action::Connect { addr } => Cmd::Connect { addr },
+            Action::Disconnect => Cmd::Disconnect,
+            Action::SetMask { mask } => Cmd::SetMask(mask),
+            Action::SetTestMode { on } => Cmd::SetTestMode(on),
+            Action::SetBlinkPeriods { periods } => Cmd::SetBlinkPeriods(periods),
+            Action::ReadAll => Cmd::ReadAll,
+            Action::Reboot => Cmd::Reboot,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Delay in ms and action of each step.
+    fn replay(text: &str) -> Result<Vec<(u64, Action)>> {
+        let steps = parse(text)?;
+        Ok(steps.into_iter().map(|(d, cmd)| (d.as_millis() as u64, Action::from_cmd(&cmd).unwrap())).collect())
+    }
+
+    #[test]
+    fn round_trips_recorded_steps() {
+        let mut recorder = Recorder::new();
+        recorder.record(&Cmd::SetMask(0xBEEF));
+        recorder.record(&Cmd::Connect { addr: "Board\t\"1\"".into() });
+        recorder.record(&Cmd::SetBlinkPeriods([100, 0, 250, 65535]));
+        let lines: Vec<String> = recorder.steps.iter().map(|s| serde_json::to_string(s).unwrap()).collect();
+        let steps = replay(&format!("[{}]", lines.join(","))).unwrap();
+        let actions: Vec<Action> = steps.into_iter().map(|(_, a)| a).collect();
+        assert_eq!(
+            actions,
+            [
+                Action::SetMask { mask: 0xBEEF },
+                Action::Connect { addr: "Board\t\"1\"".into() },
+                Action::SetBlinkPeriods { periods: [100, 0, 250, 65535] },
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_the_gaps_between_steps() {
+        let text = r#"[{"at_ms": 100, "cmd": "disconnect"}, {"cmd": "read_all"}, {"at_ms": 350, "cmd": "reboot"}]"#;
+        let delays: Vec<u64> = replay(text).unwrap().into_iter().map(|(d, _)| d).collect();
+        assert_eq!(delays, [100, 0, 250]);
+    }
+
+    #[test]
+    fn rejects_out_of_range_values_naming_the_step() {
+        let text = r#"[{"at_ms": 0, "cmd": "set_mask", "mask": 1}, {"at_ms": 5, "cmd": "set_mask", "mask": 70000}]"#;
+        let err = format!("{:#}", parse(text).unwrap_err());
+        assert!(err.starts_with("step 2:"), "{err}");
+
+        let text = r#"[{"at_ms": 0, "cmd": "scan", "min_dbm": -40000}]"#;
+        let err = format!("{:#}", parse(text).unwrap_err());
+        assert!(err.starts_with("step 1:"), "{err}");
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        assert!(parse(r#"[{"at_ms": 0, "cmd": "explode"}]"#).is_err());
+        assert!(parse(r#"[{"at_ms": 0}]"#).is_err());
+    }
+}
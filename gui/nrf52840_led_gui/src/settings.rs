@@ -0,0 +1,332 @@
+//! Persisted GUI settings.
+//!
+//! Stored as simple `key = value` lines in
+//! `$XDG_CONFIG_HOME/nrf52840_led_gui/settings.conf` (or `~/.config/...`).
+//! Unknown keys and unparsable values are ignored, so older/newer files load fine.
+
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::LED_CHAR_UUID;
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Devices weaker than this are dropped from scan results.
+    pub min_rssi: i16,
+    /// Keep devices that didn't report an RSSI at all.
+    pub include_unknown_rssi: bool,
+    /// Start a scan as soon as the app is up (also `--auto-scan`).
+    pub auto_scan: bool,
+    /// Weight of the newest RSSI reading in the moving average (0..=1);
+    /// 1 shows raw values.
+    pub rssi_smoothing: f32,
+    /// Live scan: ask for repeated advertisements so RSSI updates keep
+    /// coming (off = the backend's default duplicate filtering).
+    pub scan_duplicates: bool,
+    /// LED characteristic UUIDs to look for, in order; the first one present
+    /// on the device is used. Lets one GUI drive old and new firmware builds.
+    pub led_char_uuids: Vec<Uuid>,
+    /// Auto-reconnect: first retry delay, doubling up to `reconnect_max_ms`.
+    pub reconnect_base_ms: u64,
+    pub reconnect_max_ms: u64,
+    /// 0 disables auto-reconnect.
+    pub reconnect_max_attempts: u32,
+    /// Each reconnect delay is randomly stretched or shrunk by up to this
+    /// many percent.
+    pub reconnect_jitter_pct: u32,
+    /// Drop and reconnect after this many missed firmware heartbeats in a
+    /// row; 0 disables the watchdog.
+    pub heartbeat_max_missed: u32,
+    /// Link quality weights for RSSI, write success and heartbeats (see
+    /// `quality`).
+    pub quality_weights: [f32; 3],
+    /// InfluxDB v2 telemetry export (feature `influx`); empty URL disables it.
+    pub influx_url: String,
+    pub influx_org: String,
+    pub influx_bucket: String,
+    pub influx_token: String,
+    /// Minimum seconds between points for the same device.
+    pub influx_interval_secs: u64,
+    /// Starred device addresses, pinned to the top of the device list.
+    pub favorites: Vec<String>,
+    /// Scan list filter: addresses or name patterns (`*` matches anything).
+    /// A device on `device_deny` is never listed; a non-empty `device_allow`
+    /// lists only the devices on it.
+    pub device_allow: Vec<String>,
+    pub device_deny: Vec<String>,
+    /// Toggle captions by LED index; missing or empty ones show "LED<n>".
+    pub led_labels: Vec<String>,
+    /// Gamepad button names in LED order (feature `gamepad`); empty disables.
+    pub gamepad_buttons: Vec<String>,
+    /// Address for the TCP line protocol (feature `tcp`); empty disables it.
+    pub tcp_listen: String,
+    /// Notification streams to subscribe to on connect.
+    pub notify_streams: Streams,
+}
+
+/// Notification streams the worker subscribes to, where the board has
+/// them. Each one costs a radio packet per event and a wakeup on both ends,
+/// so only what's needed is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Streams {
+    /// LED mask changes made elsewhere (buttons, other clients).
+    pub led: bool,
+    /// Battery level; polled instead while off.
+    pub battery: bool,
+    /// Firmware heartbeat; the watchdog only runs while this is on.
+    pub heartbeat: bool,
+    /// Replies to `control` requests; rejected ones are logged.
+    pub control: bool,
+    /// Firmware log lines (NUS).
+    pub fw_log: bool,
+    /// Failures the firmware reports in `last_error`.
+    pub errors: bool,
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        Self { led: true, battery: true, heartbeat: false, control: false, fw_log: false, errors: false }
+    }
+}
+
+impl Streams {
+    /// Names in the settings file, in field order.
+    pub const NAMES: [&str; 6] = ["led", "battery", "heartbeat", "control", "fw_log", "errors"];
+
+    pub fn flags(&self) -> [bool; 6] {
+        [self.led, self.battery, self.heartbeat, self.control, self.fw_log, self.errors]
+    }
+
+    pub fn from_flags(f: [bool; 6]) -> Self {
+        Self { led: f[0], battery: f[1], heartbeat: f[2], control: f[3], fw_log: f[4], errors: f[5] }
+    }
+
+    /// Comma-separated names of the enabled streams.
+    fn parse(value: &str) -> Result<Self, String> {
+        let mut flags = [false; 6];
+        for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let idx = Self::NAMES.iter().position(|n| *n == name).ok_or_else(|| format!("unknown stream {name:?}"))?;
+            flags[idx] = true;
+        }
+        Ok(Self::from_flags(flags))
+    }
+
+    fn names(&self) -> String {
+        let on: Vec<&str> = Self::NAMES.iter().zip(self.flags()).filter(|(_, on)| *on).map(|(n, _)| *n).collect();
+        on.join(", ")
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            min_rssi: -100,
+            include_unknown_rssi: true,
+            auto_scan: false,
+            rssi_smoothing: 0.3,
+            scan_duplicates: false,
+            led_char_uuids: vec![Uuid::parse_str(LED_CHAR_UUID).unwrap()],
+            reconnect_base_ms: 1000,
+            reconnect_max_ms: 30_000,
+            reconnect_max_attempts: 5,
+            reconnect_jitter_pct: 20,
+            heartbeat_max_missed: 3,
+            quality_weights: [0.5, 0.3, 0.2],
+            influx_url: String::new(),
+            influx_org: String::new(),
+            influx_bucket: String::new(),
+            influx_token: String::new(),
+            influx_interval_secs: 30,
+            favorites: Vec::new(),
+            device_allow: Vec::new(),
+            device_deny: Vec::new(),
+            led_labels: Vec::new(),
+            gamepad_buttons: ["South", "East", "West", "North"].map(String::from).to_vec(),
+            tcp_listen: "127.0.0.1:7878".into(),
+            notify_streams: Streams::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let mut s = Self::default();
+        let Some(path) = settings_path() else { return s };
+        let Ok(text) = std::fs::read_to_string(&path) else { return s };
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else { continue };
+            let _ = s.set(key.trim(), value.trim());
+        }
+        s
+    }
+
+    /// Apply one `key = value` pair as written by `save`. On `Err` (unknown
+    /// key, unparsable value) the setting keeps its current value; the
+    /// message says why.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "min_rssi" => self.min_rssi = parse(value)?,
+            "include_unknown_rssi" => self.include_unknown_rssi = parse(value)?,
+            "auto_scan" => self.auto_scan = parse(value)?,
+            "rssi_smoothing" => self.rssi_smoothing = parse::<f32>(value)?.clamp(0.05, 1.0),
+            "scan_duplicates" => self.scan_duplicates = parse(value)?,
+            "led_char_uuids" => {
+                let uuids: Vec<Uuid> = value.split(',').filter_map(|u| Uuid::parse_str(u.trim()).ok()).collect();
+                if uuids.is_empty() {
+                    return Err(format!("no valid UUID in {value:?}"));
+                }
+                self.led_char_uuids = uuids;
+            }
+            "reconnect_base_ms" => self.reconnect_base_ms = parse(value)?,
+            "reconnect_max_ms" => self.reconnect_max_ms = parse(value)?,
+            "reconnect_max_attempts" => self.reconnect_max_attempts = parse(value)?,
+            "reconnect_jitter_pct" => self.reconnect_jitter_pct = parse::<u32>(value)?.min(100),
+            "heartbeat_max_missed" => self.heartbeat_max_missed = parse(value)?,
+            "quality_weights" => {
+                self.quality_weights = parse_weights(value).ok_or_else(|| format!("bad weights {value:?}"))?;
+            }
+            "influx_url" => self.influx_url = value.to_string(),
+            "influx_org" => self.influx_org = value.to_string(),
+            "influx_bucket" => self.influx_bucket = value.to_string(),
+            "influx_token" => self.influx_token = value.to_string(),
+            "influx_interval_secs" => self.influx_interval_secs = parse(value)?,
+            "favorites" => self.favorites = parse_list(value),
+            "device_allow" => self.device_allow = parse_list(value),
+            "device_deny" => self.device_deny = parse_list(value),
+            "led_labels" => self.led_labels = parse_by_position(value),
+            "gamepad_buttons" => self.gamepad_buttons = parse_by_position(value),
+            "tcp_listen" => self.tcp_listen = value.to_string(),
+            "notify_streams" => self.notify_streams = Streams::parse(value)?,
+            _ => return Err("unknown setting".into()),
+        }
+        Ok(())
+    }
+
+    /// Every setting as `(key, value)`, in file order; `set` takes them back.
+    pub fn pairs(&self) -> Vec<(&'static str, String)> {
+        let uuids: Vec<String> = self.led_char_uuids.iter().map(Uuid::to_string).collect();
+        let [rssi, writes, heartbeat] = self.quality_weights;
+        vec![
+            ("min_rssi", self.min_rssi.to_string()),
+            ("include_unknown_rssi", self.include_unknown_rssi.to_string()),
+            ("auto_scan", self.auto_scan.to_string()),
+            ("rssi_smoothing", self.rssi_smoothing.to_string()),
+            ("scan_duplicates", self.scan_duplicates.to_string()),
+            ("led_char_uuids", uuids.join(", ")),
+            ("reconnect_base_ms", self.reconnect_base_ms.to_string()),
+            ("reconnect_max_ms", self.reconnect_max_ms.to_string()),
+            ("reconnect_max_attempts", self.reconnect_max_attempts.to_string()),
+            ("reconnect_jitter_pct", self.reconnect_jitter_pct.to_string()),
+            ("heartbeat_max_missed", self.heartbeat_max_missed.to_string()),
+            ("quality_weights", format!("{rssi}, {writes}, {heartbeat}")),
+            ("influx_url", self.influx_url.clone()),
+            ("influx_org", self.influx_org.clone()),
+            ("influx_bucket", self.influx_bucket.clone()),
+            ("influx_token", self.influx_token.clone()),
+            ("influx_interval_secs", self.influx_interval_secs.to_string()),
+            ("favorites", join_list(&self.favorites)),
+            ("device_allow", join_list(&self.device_allow)),
+            ("device_deny", join_list(&self.device_deny)),
+            ("led_labels", join_list(&self.led_labels)),
+            ("gamepad_buttons", join_list(&self.gamepad_buttons)),
+            ("tcp_listen", self.tcp_listen.clone()),
+            ("notify_streams", self.notify_streams.names()),
+        ]
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = settings_path().context("no config directory (HOME unset?)")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+        }
+
+        let text: String = self.pairs().into_iter().map(|(key, value)| format!("{key} = {value}\n")).collect();
+        std::fs::write(&path, text).with_context(|| format!("write {}", path.display()))
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("bad value {value:?}"))
+}
+
+/// Entries separated by ", ", for `parse_list` and `parse_by_position`. A
+/// comma or backslash inside an entry is escaped with a backslash, so a
+/// label like "Power, main" stays one entry.
+pub fn join_list(entries: &[String]) -> String {
+    let escaped: Vec<String> = entries.iter().map(|e| e.replace('\\', "\\\\").replace(',', "\\,")).collect();
+    escaped.join(", ")
+}
+
+/// Split on the commas `join_list` didn't escape, unescaping the rest.
+fn split_list(value: &str) -> Vec<String> {
+    let mut entries = vec![String::new()];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => entries.last_mut().unwrap().push(chars.next().unwrap_or('\\')),
+            ',' => entries.push(String::new()),
+            c => entries.last_mut().unwrap().push(c),
+        }
+    }
+    entries
+}
+
+/// Comma-separated entries (see `join_list`), blanks dropped.
+pub fn parse_list(value: &str) -> Vec<String> {
+    split_list(value).iter().map(|e| e.trim()).filter(|e| !e.is_empty()).map(String::from).collect()
+}
+
+/// Comma-separated entries (see `join_list`) in LED order; blanks keep their
+/// position (and the default).
+pub fn parse_by_position(value: &str) -> Vec<String> {
+    let mut entries: Vec<String> = split_list(value).iter().map(|l| l.trim().to_string()).collect();
+    while entries.last().is_some_and(String::is_empty) {
+        entries.pop();
+    }
+    entries
+}
+
+/// Three non-negative, comma-separated weights, not all zero.
+pub fn parse_weights(value: &str) -> Option<[f32; 3]> {
+    let parsed: Vec<f32> = value.split(',').map(|w| w.trim().parse().ok()).collect::<Option<_>>()?;
+    let weights: [f32; 3] = parsed.try_into().ok()?;
+    let valid = weights.iter().all(|w| w.is_finite() && *w >= 0.0) && weights.iter().any(|w| *w > 0.0);
+    valid.then_some(weights)
+}
+
+fn settings_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(base.join("nrf52840_led_gui").join("settings.conf"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn commas_in_entries_survive_a_round_trip() {
+        let labels = list(&["Power, main", "Link", "C:\\temp"]);
+        assert_eq!(join_list(&labels), "Power\\, main, Link, C:\\\\temp");
+        assert_eq!(parse_by_position(&join_list(&labels)), labels);
+        assert_eq!(parse_list(&join_list(&labels)), labels);
+    }
+
+    #[test]
+    fn unescaped_lists_parse_as_before() {
+        assert_eq!(parse_list(" a, ,b "), list(&["a", "b"]));
+        assert_eq!(parse_by_position("Power, , Error, , "), list(&["Power", "", "Error"]));
+    }
+}
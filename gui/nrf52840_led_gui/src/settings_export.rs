@@ -0,0 +1,146 @@
+//! "Export settings" / "Import settings": every persisted setting in one
+//! JSON file, for moving a setup between machines.
+//!
+//! The file is a flat object. Values are strings in the same form as in
+//! `settings.conf`, so the two stay interchangeable:
+//!
+//! ```text
+//! {
+//!   "format": "nrf52840_led_gui-settings",
+//!   "version": 1,
+//!   "auto_scan": "false",
+//!   "led_labels": "Power, Link, Error, Status",
+//!   ...
+//! }
+//! ```
+//!
+//! The InfluxDB token is left out (see `SECRET_KEYS`); the importing side
+//! keeps its own.
+//!
+//! Import applies what it can: a key this version doesn't know, or a value
+//! it can't parse, is skipped and reported, and the setting keeps its
+//! current value. Keys missing from the file are left alone too.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::settings::Settings;
+
+const FORMAT: &str = "nrf52840_led_gui-settings";
+const VERSION: u32 = 1;
+/// Never exported: an export is made to be copied around, and the token
+/// grants write access to the bucket.
+const SECRET_KEYS: [&str; 1] = ["influx_token"];
+
+#[derive(Serialize, Deserialize)]
+struct Export {
+    format: String,
+    version: u32,
+    /// Setting key to value. Kept as JSON values so one bad entry is
+    /// skipped rather than failing the whole import.
+    #[serde(flatten)]
+    settings: BTreeMap<String, Value>,
+}
+
+pub fn write(path: &Path, settings: &Settings) -> Result<()> {
+    let text = to_json(settings)?;
+    std::fs::write(path, text).with_context(|| format!("write {}", path.display()))
+}
+
+/// `current` with the file's settings applied, plus one "key: reason" line
+/// per setting that was skipped. Fails only if the file isn't an export.
+pub fn read(path: &Path, current: &Settings) -> Result<(Settings, Vec<String>)> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    apply(&text, current)
+}
+
+fn to_json(settings: &Settings) -> Result<String> {
+    let settings = settings
+        .pairs()
+        .into_iter()
+        .filter(|(key, _)| !SECRET_KEYS.contains(key))
+        .map(|(key, value)| (key.to_string(), Value::String(value)))
+        .collect();
+    let export = Export { format: FORMAT.into(), version: VERSION, settings };
+    let mut text = serde_json::to_string_pretty(&export)?;
+    text.push('\n');
+    Ok(text)
+}
+
+fn apply(text: &str, current: &Settings) -> Result<(Settings, Vec<String>)> {
+    let export: Export = serde_json::from_str(text).context("not a settings export")?;
+    if export.format != FORMAT {
+        bail!("not a settings export (\"format\" is {:?}, not {FORMAT:?})", export.format);
+    }
+    if export.version != VERSION {
+        bail!("export version {}; this build reads version {VERSION}", export.version);
+    }
+
+    let mut settings = current.clone();
+    let mut skipped = Vec::new();
+    for (key, value) in &export.settings {
+        let result = match value {
+            Value::String(value) => settings.set(key, value.trim()),
+            _ => Err("not a string".into()),
+        };
+        if let Err(reason) = result {
+            skipped.push(format!("{key}: {reason}"));
+        }
+    }
+    Ok((settings, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_settings_with_escapes() {
+        let mut settings = Settings::default();
+        settings.led_labels = vec!["Power, main".into(), "Tab\there".into(), "\u{1F4A1}".into()];
+        settings.tcp_listen = "0.0.0.0:9000".into();
+        let (imported, skipped) = apply(&to_json(&settings).unwrap(), &Settings::default()).unwrap();
+        assert!(skipped.is_empty(), "{skipped:?}");
+        assert_eq!(imported.led_labels, settings.led_labels);
+        assert_eq!(imported.tcp_listen, settings.tcp_listen);
+    }
+
+    #[test]
+    fn leaves_the_influx_token_out() {
+        let mut settings = Settings::default();
+        settings.influx_token = "secret".into();
+        assert!(!to_json(&settings).unwrap().contains("secret"));
+
+        let mut current = Settings::default();
+        current.influx_token = "mine".into();
+        let (imported, _) = apply(&to_json(&settings).unwrap(), &current).unwrap();
+        assert_eq!(imported.influx_token, "mine");
+    }
+
+    #[test]
+    fn decodes_standard_json_escapes() {
+        let text = r#"{"format": "nrf52840_led_gui-settings", "version": 1,
+                       "led_labels": "A\tB, \ud83d\udca1, C\r\nD"}"#;
+        let (imported, _) = apply(text, &Settings::default()).unwrap();
+        assert_eq!(imported.led_labels, ["A\tB", "\u{1F4A1}", "C\r\nD"]);
+    }
+
+    #[test]
+    fn skips_values_that_are_not_strings() {
+        let text = r#"{"format": "nrf52840_led_gui-settings", "version": 1,
+                       "rssi_smoothing": 0.5, "tcp_listen": null, "auto_scan": "true"}"#;
+        let (imported, skipped) = apply(text, &Settings::default()).unwrap();
+        assert!(imported.auto_scan);
+        assert_eq!(skipped, ["rssi_smoothing: not a string", "tcp_listen: not a string"]);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(apply("[]", &Settings::default()).is_err());
+        assert!(apply(r#"{"format": "something-else", "version": 1}"#, &Settings::default()).is_err());
+        assert!(apply(r#"{"format": "nrf52840_led_gui-settings", "version": 2}"#, &Settings::default()).is_err());
+    }
+}
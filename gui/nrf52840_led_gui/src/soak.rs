@@ -0,0 +1,186 @@
+//! Connection soak test: connect, wait, write a mask, read it back,
+//! disconnect, pause, and again, counting failures per phase. Shakes out
+//! the intermittent connect and discovery failures one session won't show.
+//!
+//! ```text
+//! nrf52840_led_gui soak <address-or-name> [--cycles N]
+//! ```
+//!
+//! Each cycle is reported on stderr, the summary on stdout; Ctrl-C abandons
+//! the current cycle and still prints it. The exit status is non-zero if any
+//! cycle failed. The GUI runs the same cycles from Device > Soak Test.
+
+use anyhow::{anyhow, bail, Context, Result};
+use btleplug::api::{Central, Manager as _, Peripheral as _, WriteType};
+use btleplug::platform::{Manager, Peripheral};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::settings::Settings;
+use crate::{mask_bytes, mask_from_bytes};
+
+pub const DEFAULT_CYCLES: u32 = 20;
+const FIND_TIMEOUT: Duration = Duration::from_secs(10);
+/// Upper bound for each phase.
+const PHASE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Connected time before the write, like a user looking at the window.
+const DWELL: Duration = Duration::from_secs(1);
+/// Disconnected time between cycles, so the board is back to advertising.
+pub const PAUSE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    Connect,
+    Discover,
+    Write,
+    Read,
+    Disconnect,
+}
+
+impl Phase {
+    const NAMES: [&str; 5] = ["connect", "discover", "write", "read", "disconnect"];
+
+    fn name(self) -> &'static str {
+        Self::NAMES[self as usize]
+    }
+}
+
+/// A failed cycle: where, and why.
+pub type Failure = (Phase, anyhow::Error);
+
+#[derive(Debug, Default)]
+pub struct Tally {
+    ok: u32,
+    failed: [u32; 5],
+}
+
+impl Tally {
+    pub fn record(&mut self, outcome: &Result<(), Failure>) {
+        match outcome {
+            Ok(()) => self.ok += 1,
+            Err((phase, _)) => self.failed[*phase as usize] += 1,
+        }
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failed.iter().sum()
+    }
+
+    /// "18/20 cycles ok; failed: connect 1, read 1"
+    pub fn summary(&self) -> String {
+        let total = self.ok + self.failures();
+        let failed: Vec<String> = Phase::NAMES
+            .iter()
+            .zip(self.failed)
+            .filter(|(_, n)| *n > 0)
+            .map(|(name, n)| format!("{name} {n}"))
+            .collect();
+        if failed.is_empty() {
+            format!("{}/{total} cycles ok", self.ok)
+        } else {
+            format!("{}/{total} cycles ok; failed: {}", self.ok, failed.join(", "))
+        }
+    }
+}
+
+/// Mask for cycle `n`: alternating, so a stale read-back can't pass, and
+/// only LED1, which every board has.
+pub fn cycle_mask(n: u32) -> u16 {
+    (n % 2) as u16
+}
+
+/// One log line for cycle `n` of `cycles`.
+pub fn describe(n: u32, cycles: u32, outcome: &Result<(), Failure>, took: Duration) -> String {
+    let ms = took.as_millis();
+    match outcome {
+        Ok(()) => format!("Soak cycle {n}/{cycles}: ok ({ms} ms)"),
+        Err((phase, e)) => format!("Soak cycle {n}/{cycles}: {} failed ({ms} ms): {e:#}", phase.name()),
+    }
+}
+
+/// One cycle against `peri`. The board is disconnected afterwards whatever
+/// happened; a disconnect failure only counts if everything else worked.
+pub async fn cycle(peri: &Peripheral, led_uuids: &[Uuid], mask: u16) -> Result<(), Failure> {
+    phase(Phase::Connect, async { peri.connect().await.context("connect") }).await?;
+    let result = exercise(peri, led_uuids, mask).await;
+    let disconnected = phase(Phase::Disconnect, async { peri.disconnect().await.context("disconnect") }).await;
+    result.and(disconnected)
+}
+
+async fn exercise(peri: &Peripheral, led_uuids: &[Uuid], mask: u16) -> Result<(), Failure> {
+    tokio::time::sleep(DWELL).await;
+    phase(Phase::Discover, async { peri.discover_services().await.context("discover_services") }).await?;
+    let chars = peri.characteristics();
+    let ch = led_uuids
+        .iter()
+        .find_map(|u| chars.iter().find(|c| c.uuid == *u))
+        .cloned()
+        .ok_or_else(|| (Phase::Discover, anyhow!("LED characteristic not found")))?;
+
+    phase(Phase::Write, async {
+        peri.write(&ch, &mask_bytes(mask), WriteType::WithResponse).await.context("write")
+    })
+    .await?;
+    let bytes = phase(Phase::Read, async { peri.read(&ch).await.context("read") }).await?;
+    match mask_from_bytes(&bytes) {
+        Some(read) if read == mask => Ok(()),
+        Some(read) => Err((Phase::Read, anyhow!("read back 0x{read:04x}, wrote 0x{mask:04x}"))),
+        None => Err((Phase::Read, anyhow!("empty read"))),
+    }
+}
+
+async fn phase<T>(phase: Phase, fut: impl Future<Output = Result<T>>) -> Result<T, Failure> {
+    match tokio::time::timeout(PHASE_TIMEOUT, fut).await {
+        Ok(r) => r.map_err(|e| (phase, e)),
+        Err(_) => Err((phase, anyhow!("timed out after {}s", PHASE_TIMEOUT.as_secs()))),
+    }
+}
+
+pub fn run(args: &[String]) -> Result<()> {
+    let (target, cycles) = match args {
+        [target] => (target, DEFAULT_CYCLES),
+        [target, flag, n] if flag == "--cycles" => (target, n.parse().with_context(|| format!("bad count {n:?}"))?),
+        _ => bail!("usage: soak <address-or-name> [--cycles N]"),
+    };
+
+    let rt = tokio::runtime::Runtime::new().context("tokio runtime")?;
+    rt.block_on(soak(target, cycles))
+}
+
+async fn soak(target: &str, cycles: u32) -> Result<()> {
+    let manager = Manager::new().await.context("btleplug Manager::new")?;
+    let adapters = manager.adapters().await.context("manager.adapters")?;
+    let adapter = adapters.into_iter().next().ok_or_else(|| anyhow!("No BLE adapters found"))?;
+
+    eprintln!("Looking for {target}...");
+    let peri = tokio::time::timeout(FIND_TIMEOUT, crate::pulse::find(&adapter, target))
+        .await
+        .map_err(|_| anyhow!("{target} not found within {}s", FIND_TIMEOUT.as_secs()))??;
+    adapter.stop_scan().await.ok();
+
+    let led_uuids = Settings::load().led_char_uuids;
+    let mut tally = Tally::default();
+    let interrupted = tokio::signal::ctrl_c();
+    tokio::pin!(interrupted);
+    for n in 1..=cycles {
+        let started = Instant::now();
+        let outcome = tokio::select! {
+            outcome = cycle(&peri, &led_uuids, cycle_mask(n)) => outcome,
+            _ = &mut interrupted => {
+                eprintln!("Interrupted.");
+                peri.disconnect().await.ok();
+                break;
+            }
+        };
+        eprintln!("{}", describe(n, cycles, &outcome, started.elapsed()));
+        tally.record(&outcome);
+        tokio::time::sleep(PAUSE).await;
+    }
+
+    println!("{}", tally.summary());
+    if tally.failures() > 0 {
+        bail!("{} cycle(s) failed", tally.failures());
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+//! Optional TCP line protocol (cargo feature `tcp`) for scripts and older
+//! tooling, e.g. `nc 127.0.0.1 7878`, then one command per line:
+//!
+//! ```text
+//! MASK 0F    set the LED mask (hex)
+//! ON 2       turn LED2 on, leaving the others as the board reports them
+//! OFF 2      turn LED2 off
+//! SCAN       5 s scan with the saved RSSI filter
+//! ```
+//!
+//! Each command gets one line back once the board (or adapter) has actually
+//! answered: `OK`, `OK <detail>` or `ERR <reason>`. Commands go through the
+//! same worker as the GUI's, so the GUI follows along.
+//!
+//! Listens on `tcp_listen` from the settings file, loopback only by default;
+//! empty disables it. There is no authentication, so only bind to other
+//! addresses on a trusted network.
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc as tokio_mpsc, oneshot};
+
+use crate::settings::Settings;
+use crate::{Cmd, RssiFilter, MAX_LED_COUNT};
+
+/// Start the listener on its own thread; does nothing if `tcp_listen` is empty.
+pub fn spawn(settings: &Settings, cmd_tx: tokio_mpsc::UnboundedSender<Cmd>) {
+    if settings.tcp_listen.is_empty() {
+        return;
+    }
+    let addr = settings.tcp_listen.clone();
+    let filter = RssiFilter::from_settings(settings);
+
+    std::thread::spawn(move || {
+        let rt = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(rt) => rt,
+            Err(e) => return eprintln!("tcp: {e}"),
+        };
+        rt.block_on(async move {
+            let listener = match TcpListener::bind(&addr).await {
+                Ok(l) => l,
+                Err(e) => return eprintln!("tcp: bind {addr}: {e}"),
+            };
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(serve(stream, cmd_tx.clone(), filter));
+                    }
+                    Err(e) => eprintln!("tcp: accept: {e}"),
+                }
+            }
+        });
+    });
+}
+
+/// One client: answer each line until it hangs up.
+async fn serve(stream: TcpStream, cmd_tx: tokio_mpsc::UnboundedSender<Cmd>, filter: RssiFilter) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let answer = match parse(line, filter) {
+            Ok(cmd) => run(&cmd_tx, cmd).await,
+            Err(e) => Err(e),
+        };
+        let out = match answer {
+            Ok(detail) if detail.is_empty() => "OK\n".to_string(),
+            Ok(detail) => format!("OK {detail}\n"),
+            Err(e) => format!("ERR {e}\n"),
+        };
+        if write.write_all(out.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Hand `cmd` to the worker and wait for its outcome.
+async fn run(cmd_tx: &tokio_mpsc::UnboundedSender<Cmd>, cmd: Cmd) -> Result<String, String> {
+    let (tx, rx) = oneshot::channel();
+    cmd_tx.send(Cmd::WithReply(Box::new(cmd), tx)).map_err(|_| "GUI is shutting down".to_string())?;
+    rx.await.map_err(|_| "no answer from the BLE worker".to_string())?
+}
+
+fn parse(line: &str, filter: RssiFilter) -> Result<Cmd, String> {
+    let mut words = line.split_whitespace();
+    let verb = words.next().unwrap_or_default().to_ascii_uppercase();
+    let arg = words.next();
+    if words.next().is_some() {
+        return Err("too many arguments".into());
+    }
+
+    match (verb.as_str(), arg) {
+        ("MASK", Some(hex)) => u16::from_str_radix(hex, 16).map(Cmd::SetMask).map_err(|_| format!("bad mask {hex:?}")),
+        ("ON" | "OFF", Some(n)) => match n.parse::<u8>() {
+            Ok(n @ 1..=MAX_LED_COUNT) => Ok(Cmd::SetLed { idx: n - 1, on: verb == "ON" }),
+            _ => Err(format!("LED must be 1..{MAX_LED_COUNT}")),
+        },
+        ("SCAN", None) => Ok(Cmd::Scan(filter)),
+        ("MASK" | "ON" | "OFF", None) => Err(format!("{verb} needs an argument")),
+        _ => Err(format!("unknown command {line:?}")),
+    }
+}